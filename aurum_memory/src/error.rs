@@ -0,0 +1,246 @@
+use crate::heap::AllocError;
+use crate::heap::Interrupted;
+use crate::object::DeserializeError;
+use crate::object::FormatError;
+use crate::object::LimitError;
+use crate::object::Metavariable;
+use crate::object::NumArgumentsError;
+use crate::object::NumSlotsError;
+use crate::object::PathError;
+use crate::object::SerializeError;
+use crate::object::SymbolLenError;
+use crate::object::TryNewApplicationError;
+use crate::object::TryNewEnvFrameError;
+use crate::object::TryNewSymbolError;
+use crate::object::UnifyError;
+
+use core::fmt;
+
+/// Any error raised by this crate.
+///
+/// Every error type in this crate converts into this type,
+/// so that functions which do many different things
+/// can use the `?` operator uniformly.
+#[allow(missing_docs)]
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error
+{
+    SymbolLen(SymbolLenError),
+    NumArguments(NumArgumentsError),
+    NumSlots(NumSlotsError),
+    Alloc(AllocError),
+    Path(PathError),
+    Unify(UnifyError),
+    Serialize(SerializeError),
+    Format(FormatError),
+    Limit(LimitError),
+    Interrupted(Interrupted),
+}
+
+impl fmt::Display for Error
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self {
+            Self::SymbolLen(SymbolLenError) =>
+                write!(f, "Symbol name is too long"),
+            Self::NumArguments(NumArgumentsError) =>
+                write!(f, "Application has too many arguments"),
+            Self::NumSlots(NumSlotsError) =>
+                write!(f, "Environment frame has too many slots"),
+            Self::Alloc(AllocError::OutOfMemory(layout)) =>
+                write!(f, "Out of memory allocating {} bytes", layout.size()),
+            Self::Alloc(AllocError::HeapFull(layout)) =>
+                write!(f, "Heap full allocating {} bytes", layout.size()),
+            Self::Path(PathError(_)) =>
+                write!(f, "Path does not lead to a subterm"),
+            Self::Unify(UnifyError::Mismatch) =>
+                write!(f, "Terms do not unify"),
+            Self::Unify(UnifyError::Occurs(Metavariable(m))) =>
+                write!(f, "Metavariable ?{} occurs in its own binding", m),
+            Self::Serialize(SerializeError::Custom) =>
+                write!(f, "Term contains an object that cannot be serialized"),
+            Self::Serialize(SerializeError::Interrupted(Interrupted)) =>
+                write!(f, "Interrupted"),
+            Self::Format(FormatError::Corrupt{offset}) =>
+                write!(f, "Serialized term is corrupt at byte {}", offset),
+            Self::Format(FormatError::Truncated) =>
+                write!(f, "Serialized term is truncated"),
+            Self::Format(FormatError::UnsupportedVersion{version}) =>
+                write!(f, "Serialized term has unsupported version {}",
+                    version),
+            Self::Limit(LimitError::NumObjects{offset}) =>
+                write!(f, "Serialized term has too many objects at byte {}",
+                    offset),
+            Self::Limit(LimitError::PayloadSize{offset}) =>
+                write!(f, "Serialized term has too large an object at byte {}",
+                    offset),
+            Self::Limit(LimitError::Depth{offset}) =>
+                write!(f, "Serialized term is too deep at byte {}", offset),
+            Self::Interrupted(Interrupted) =>
+                write!(f, "Interrupted"),
+        }
+    }
+}
+
+impl From<SymbolLenError> for Error
+{
+    fn from(other: SymbolLenError) -> Self
+    {
+        Self::SymbolLen(other)
+    }
+}
+
+impl From<NumArgumentsError> for Error
+{
+    fn from(other: NumArgumentsError) -> Self
+    {
+        Self::NumArguments(other)
+    }
+}
+
+impl From<NumSlotsError> for Error
+{
+    fn from(other: NumSlotsError) -> Self
+    {
+        Self::NumSlots(other)
+    }
+}
+
+impl From<AllocError> for Error
+{
+    fn from(other: AllocError) -> Self
+    {
+        Self::Alloc(other)
+    }
+}
+
+impl From<PathError> for Error
+{
+    fn from(other: PathError) -> Self
+    {
+        Self::Path(other)
+    }
+}
+
+impl From<UnifyError> for Error
+{
+    fn from(other: UnifyError) -> Self
+    {
+        Self::Unify(other)
+    }
+}
+
+impl From<SerializeError> for Error
+{
+    fn from(other: SerializeError) -> Self
+    {
+        match other {
+            SerializeError::Interrupted(err) => err.into(),
+            other => Self::Serialize(other),
+        }
+    }
+}
+
+impl From<FormatError> for Error
+{
+    fn from(other: FormatError) -> Self
+    {
+        Self::Format(other)
+    }
+}
+
+impl From<LimitError> for Error
+{
+    fn from(other: LimitError) -> Self
+    {
+        Self::Limit(other)
+    }
+}
+
+impl From<Interrupted> for Error
+{
+    fn from(other: Interrupted) -> Self
+    {
+        Self::Interrupted(other)
+    }
+}
+
+impl From<TryNewSymbolError> for Error
+{
+    fn from(other: TryNewSymbolError) -> Self
+    {
+        match other {
+            TryNewSymbolError::Len(err) => err.into(),
+            TryNewSymbolError::Alloc(err) => err.into(),
+        }
+    }
+}
+
+impl From<TryNewApplicationError> for Error
+{
+    fn from(other: TryNewApplicationError) -> Self
+    {
+        match other {
+            TryNewApplicationError::NumArguments(err) => err.into(),
+            TryNewApplicationError::Alloc(err) => err.into(),
+        }
+    }
+}
+
+impl From<TryNewEnvFrameError> for Error
+{
+    fn from(other: TryNewEnvFrameError) -> Self
+    {
+        match other {
+            TryNewEnvFrameError::NumSlots(err) => err.into(),
+            TryNewEnvFrameError::Alloc(err) => err.into(),
+        }
+    }
+}
+
+impl From<DeserializeError> for Error
+{
+    fn from(other: DeserializeError) -> Self
+    {
+        match other {
+            DeserializeError::Format(err) => err.into(),
+            DeserializeError::Limit(err) => err.into(),
+            DeserializeError::Alloc(err) => err.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::heap::Heap;
+    use crate::object::DeBruijn;
+
+    use alloc::string::ToString;
+
+    #[test]
+    fn question_mark()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[f, x, app]| {
+                let result: Result<(), Error> = (|| {
+                    heap.try_new_symbol(f, b"F")?;
+                    heap.try_new_variable(x, DeBruijn(0))?;
+                    heap.try_new_application(app, f, [x])?;
+                    Ok(())
+                })();
+                result.unwrap();
+            });
+        });
+    }
+
+    #[test]
+    fn display()
+    {
+        let err = Error::from(SymbolLenError);
+        assert_eq!(err.to_string(), "Symbol name is too long");
+    }
+}
@@ -1,25 +1,79 @@
+use crate::object::HEADER_SIZE;
 use crate::object::Header;
+#[cfg(feature = "wide-header")]
+use crate::object::HeaderExtension;
 use crate::object::Object;
 use crate::object::Payload;
+use crate::object::visit_children;
 use super::Heap;
+use super::Scope;
 use super::ScopedHandle;
 use super::UnsafeHandle;
 
-use alloc::alloc::alloc;
 use alloc::alloc::handle_alloc_error;
 use core::alloc::Layout;
 use core::ptr::NonNull;
 
+/// Number of bytes before each object that hold the [runtime id]
+/// of the heap that created it.
+/// Only debug builds stamp objects in this way.
+/// The stamps do not count towards [`Heap::allocated_bytes`],
+/// so that the memory limit admits as many objects in debug builds
+/// as it does in release builds.
+///
+/// [runtime id]: `Heap::runtime_id`
+#[cfg(debug_assertions)]
+const STAMP_SIZE: usize = 8;
+#[cfg(not(debug_assertions))]
+const STAMP_SIZE: usize = 0;
+
+/// Raised when memory for an object could not be allocated.
+///
+/// Each variant carries the layout of the memory
+/// that could not be allocated.
+#[derive(Debug)]
+pub enum AllocError
+{
+    /// The allocator given by [`HeapConfig::allocator`] failed.
+    ///
+    /// [`HeapConfig::allocator`]: `crate::heap::HeapConfig::allocator`
+    OutOfMemory(Layout),
+
+    /// The allocation would exceed [`HeapConfig::memory_limit`].
+    ///
+    /// [`HeapConfig::memory_limit`]: `crate::heap::HeapConfig::memory_limit`
+    HeapFull(Layout),
+}
+
+impl AllocError
+{
+    /// The layout of the memory that could not be allocated.
+    #[inline]
+    pub fn layout(&self) -> Layout
+    {
+        match self {
+            Self::OutOfMemory(layout) => *layout,
+            Self::HeapFull(layout) => *layout,
+        }
+    }
+}
+
 impl<'h> Heap<'h>
 {
     /// Allocate memory for an object and initialize it.
     ///
     /// Memory is allocated on the garbage collected heap
-    /// for an object of the given payload size.
+    /// for an object of the given payload size,
+    /// using the allocator given by [`HeapConfig::allocator`].
     /// The `init` function is called to initialize the payload.
     /// The object header is set to the header returned by `init`,
     /// and then the relevant flags of the object are set.
     ///
+    /// If memory cannot be allocated, or if allocating the object
+    /// would exceed the [memory limit],
+    /// [`handle_alloc_error`] is called.
+    /// Use [`try_alloc`][`Self::try_alloc`] to handle this yourself.
+    ///
     /// You would not normally use this method.
     /// Instead use one of the `new_*` methods.
     /// They will initialize the object for you
@@ -37,30 +91,211 @@ impl<'h> Heap<'h>
     ///  - Once it returns, the object must be properly initialized
     ///    (when the garbage collector kicks in later,
     ///     it must not find an improperly initialized object).
+    ///
+    /// [`HeapConfig::allocator`]: `crate::heap::HeapConfig::allocator`
+    /// [memory limit]: `crate::heap::HeapConfig::memory_limit`
     pub unsafe fn alloc(
         &self,
         payload_size: usize,
         init: impl FnOnce(*mut Payload) -> Header,
     ) -> UnsafeHandle<'h>
     {
-        // TODO: Replace this with a pointer bump allocation.
+        match self.try_alloc(payload_size, init) {
+            Ok(handle) => handle,
+            Err(err) => handle_alloc_error(err.layout()),
+        }
+    }
+
+    /// Similar to [`alloc`][`Self::alloc`],
+    /// but return an error if memory cannot be allocated.
+    ///
+    /// If an error is returned, `init` is not called.
+    ///
+    /// # Safety
+    ///
+    /// See the safety section of [`alloc`][`Self::alloc`].
+    pub unsafe fn try_alloc(
+        &self,
+        payload_size: usize,
+        init: impl FnOnce(*mut Payload) -> Header,
+    ) -> Result<UnsafeHandle<'h>, AllocError>
+    {
+        let size = STAMP_SIZE + HEADER_SIZE + payload_size;
+        let layout = Layout::from_size_align_unchecked(size, 8);
 
-        let layout = Layout::from_size_align_unchecked(8 + payload_size, 8);
+        let pointer = self.allocate(layout, STAMP_SIZE)?;
+        let pointer = self.stamp(pointer.as_ptr()) as *mut Object<'h>;
 
-        let pointer = alloc(layout);
-        let pointer = pointer as *mut Object<'h>;
+        #[cfg(feature = "wide-header")]
+        core::ptr::addr_of_mut!((*pointer).extension)
+            .write(HeaderExtension::empty());
+        (*pointer).header = init(&mut (*pointer).payload);
 
-        if pointer.is_null() {
-            handle_alloc_error(layout);
+        Ok(UnsafeHandle::new(NonNull::new_unchecked(pointer)))
+    }
+
+    /// Allocate memory for several objects at once and initialize them.
+    ///
+    /// This is similar to calling [`alloc`][`Self::alloc`] once per object,
+    /// but memory for all objects is obtained in a single allocation,
+    /// and no garbage collection takes place until all of them are
+    /// initialized (see [`without_gc`][`Self::without_gc`]).
+    /// This is useful for building structures such as application spines.
+    ///
+    /// The objects are initialized in order, with `init` receiving
+    /// the index of the object and a pointer to its payload.
+    /// After object _i_ is initialized, the _i_th handle of `into` is
+    /// pointed to it, so that initializing later objects may refer to it.
+    ///
+    /// # Panics
+    ///
+    /// If `into` has fewer handles than there are payload sizes,
+    /// this method panics before allocating anything.
+    ///
+    /// # Safety
+    ///
+    /// The payload sizes must not be so large
+    /// that the sum of the object sizes exceeds [`isize::MAX`],
+    /// as a [`Layout`] of that size cannot be created.
+    /// The conditions on `init` are the same as for [`alloc`][`Self::alloc`].
+    pub unsafe fn alloc_group(
+        &self,
+        into: &Scope<'h>,
+        payload_sizes: &[usize],
+        init: impl FnMut(usize, *mut Payload) -> Header,
+    )
+    {
+        if let Err(err) = self.try_alloc_group(into, payload_sizes, init) {
+            handle_alloc_error(err.layout());
         }
+    }
 
-        (*pointer).header = init(&mut (*pointer).payload);
+    /// Similar to [`alloc_group`][`Self::alloc_group`],
+    /// but return an error if memory cannot be allocated.
+    ///
+    /// If an error is returned, `init` is not called
+    /// and the handles in `into` are left unchanged.
+    ///
+    /// # Safety
+    ///
+    /// See the safety section of [`alloc_group`][`Self::alloc_group`].
+    pub unsafe fn try_alloc_group(
+        &self,
+        into: &Scope<'h>,
+        payload_sizes: &[usize],
+        mut init: impl FnMut(usize, *mut Payload) -> Header,
+    ) -> Result<(), AllocError>
+    {
+        assert!(
+            payload_sizes.len() <= into.len(),
+            "Scope is too small for the group of objects",
+        );
+
+        // Each object is padded so that the next one is aligned.
+        let object_size = |payload_size: usize| {
+            STAMP_SIZE + ((HEADER_SIZE + payload_size + 7) & !7)
+        };
+
+        if payload_sizes.is_empty() {
+            return Ok(());
+        }
 
-        UnsafeHandle::new(NonNull::new_unchecked(pointer))
+        let size = payload_sizes.iter().copied().map(object_size).sum();
+        let layout = Layout::from_size_align_unchecked(size, 8);
+
+        let stamps = STAMP_SIZE * payload_sizes.len();
+        let pointer = self.allocate(layout, stamps)?;
+
+        self.without_gc(|| {
+            let mut pointer = pointer.as_ptr();
+            for (i, &payload_size) in payload_sizes.iter().enumerate() {
+                let object = self.stamp(pointer) as *mut Object<'h>;
+                #[cfg(feature = "wide-header")]
+                core::ptr::addr_of_mut!((*object).extension)
+                    .write(HeaderExtension::empty());
+                (*object).header = init(i, &mut (*object).payload);
+                let handle = UnsafeHandle::new(NonNull::new_unchecked(object));
+                into.get_unchecked(i).copy_from_unsafe_handle(handle);
+                pointer = pointer.add(object_size(payload_size));
+            }
+            for i in 0 .. payload_sizes.len() {
+                let handle = into.get_unchecked(i).as_unsafe_handle();
+                self.debug_assert_children_owned(handle);
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Obtain memory from the allocator, respecting the memory limit.
+    ///
+    /// The given number of bytes of the memory hold stamps,
+    /// which do not count towards the memory limit.
+    unsafe fn allocate(&self, layout: Layout, stamps: usize)
+        -> Result<NonNull<u8>, AllocError>
+    {
+        // TODO: Replace this with a pointer bump allocation.
+
+        let object_bytes = layout.size() - stamps;
+        let allocated_bytes = self.allocated_bytes.get() + object_bytes;
+        if allocated_bytes > self.memory_limit {
+            // TODO: Collect garbage before giving up.
+            return Err(AllocError::HeapFull(layout));
+        }
+
+        let pointer = self.allocator.allocate(layout)
+            .map_err(|_| AllocError::OutOfMemory(layout))?;
+        self.allocated_bytes.set(allocated_bytes);
+
+        Ok(pointer.cast())
+    }
+
+    /// In debug builds, write the runtime id of the heap
+    /// to the start of the memory for an object.
+    /// Return a pointer to the object, which follows the stamp.
+    unsafe fn stamp(&self, pointer: *mut u8) -> *mut u8
+    {
+        #[cfg(debug_assertions)]
+        (pointer as *mut usize).write(self.runtime_id());
+        pointer.add(STAMP_SIZE)
+    }
+
+    /// In debug builds, check that the object was created by this heap.
+    ///
+    /// # Safety
+    ///
+    /// The object must not have been destroyed.
+    #[allow(unused_variables)]
+    pub (super) unsafe fn debug_assert_owned(&self, handle: UnsafeHandle<'h>)
+    {
+        #[cfg(debug_assertions)]
+        assert_eq!(
+            *(handle.as_ptr() as *const usize).sub(1),
+            self.runtime_id(),
+            "Object was created by another heap",
+        );
+    }
+
+    /// In debug builds, check that the children of the object
+    /// were created by this heap.
+    ///
+    /// # Safety
+    ///
+    /// The object must be initialized.
+    unsafe fn debug_assert_children_owned(&self, handle: UnsafeHandle<'h>)
+    {
+        if cfg!(debug_assertions) {
+            visit_children(&*handle.header(), handle.payload(), |child| {
+                self.debug_assert_owned(child.get());
+            });
+        }
     }
 
     /// Similar to [`alloc`][`Self::alloc`],
     /// but point the given scoped handle to the new object.
+    ///
+    /// In debug builds, this checks that the children of the new object
+    /// were created by this heap, and panics otherwise.
     pub unsafe fn new<'s>(
         &self,
         into: ScopedHandle<'h, 's>,
@@ -69,6 +304,92 @@ impl<'h> Heap<'h>
     )
     {
         let object = self.alloc(payload_size, init);
+        self.debug_assert_children_owned(object);
+        into.copy_from_unsafe_handle(object);
+    }
+
+    /// Similar to [`try_alloc`][`Self::try_alloc`],
+    /// but point the given scoped handle to the new object.
+    ///
+    /// If an error is returned, the scoped handle is left unchanged.
+    ///
+    /// # Safety
+    ///
+    /// See the safety section of [`alloc`][`Self::alloc`].
+    pub unsafe fn try_new<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        payload_size: usize,
+        init: impl FnOnce(*mut Payload) -> Header,
+    ) -> Result<(), AllocError>
+    {
+        let object = self.try_alloc(payload_size, init)?;
+        self.debug_assert_children_owned(object);
         into.copy_from_unsafe_handle(object);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::heap::Heap;
+    use crate::heap::UnsafeHandle;
+    use crate::object::Flags;
+    use crate::object::FreeCache;
+    use crate::object::Header;
+    use crate::object::Kind;
+
+    use core::mem::MaybeUninit;
+    use core::mem::transmute;
+
+    #[test]
+    fn alloc_group()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_boxed_scope(3, |scope| {
+                let payload_sizes = [0, 3, 1];
+                unsafe {
+                    heap.alloc_group(scope, &payload_sizes, |i, payload| {
+                        let name = &b"abc"[.. payload_sizes[i]];
+                        let name_len = name.len() as u32;
+                        let payload = payload as *mut u8;
+                        payload.copy_from(name.as_ptr(), name.len());
+                        Header{
+                            kind: Kind::Symbol,
+                            flags: Flags::empty(),
+                            free_cache: FreeCache::EMPTY,
+                            extra: name_len.to_ne_bytes().map(MaybeUninit::new),
+                        }
+                    });
+                }
+                let names = [&b""[..], b"abc", b"a"];
+                for (handle, name) in scope.iter().zip(names) {
+                    handle.with_pin(|handle| {
+                        assert_eq!(handle.as_symbol(), Some(name));
+                    });
+                }
+            });
+        });
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Object was created by another heap")]
+    fn other_heap()
+    {
+        Heap::with_new(|a| Heap::with_new(|b| {
+            a.with_new_array_scope(|[symbol]| {
+                b.with_new_array_scope(|[smuggled, term]| {
+                    a.new_symbol(symbol, b"F").unwrap();
+                    unsafe {
+                        let handle = symbol.as_unsafe_handle();
+                        let handle: UnsafeHandle = transmute(handle);
+                        smuggled.copy_from_unsafe_handle(handle);
+                    }
+                    b.new_application(term, smuggled, [smuggled]).unwrap();
+                });
+            });
+        }));
     }
 }
@@ -0,0 +1,153 @@
+use core::alloc::AllocError;
+use core::alloc::Allocator;
+use core::alloc::Layout;
+use core::cell::Cell;
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use core::ptr::NonNull;
+use core::ptr;
+
+/// Allocator that hands out memory from a caller-provided buffer.
+///
+/// Memory is allocated by bumping an offset into the buffer.
+/// Deallocation only reclaims memory if it was the most recent allocation,
+/// which is the common case for scopes, as they are created and destroyed
+/// in a stack-like fashion.
+/// Once the buffer is exhausted, allocation fails.
+///
+/// This is what [`Heap::with_new_in`] uses.
+///
+/// [`Heap::with_new_in`]: `super::Heap::with_new_in`
+pub struct BufferAllocator<'b>
+{
+    _buffer: PhantomData<&'b mut [MaybeUninit<u8>]>,
+    start: NonNull<u8>,
+    len: usize,
+
+    /// Offset into the buffer at which the next allocation starts.
+    next: Cell<usize>,
+}
+
+impl<'b> BufferAllocator<'b>
+{
+    /// Create an allocator that allocates from the given buffer.
+    pub fn new(buffer: &'b mut [MaybeUninit<u8>]) -> Self
+    {
+        let start = buffer.as_mut_ptr() as *mut u8;
+        Self{
+            _buffer: PhantomData,
+            // SAFETY: Slices never start at a null pointer.
+            start: unsafe { NonNull::new_unchecked(start) },
+            len: buffer.len(),
+            next: Cell::new(0),
+        }
+    }
+
+    /// The number of bytes that are in use by allocations,
+    /// including any padding between them.
+    #[inline]
+    pub fn used(&self) -> usize
+    {
+        self.next.get()
+    }
+}
+
+unsafe impl<'b> Allocator for BufferAllocator<'b>
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError>
+    {
+        let start = self.start.as_ptr();
+        let next = self.next.get();
+
+        // Find the first suitably aligned offset.
+        let padding = start.wrapping_add(next).align_offset(layout.align());
+        let offset = next.checked_add(padding).ok_or(AllocError)?;
+
+        let end = offset.checked_add(layout.size()).ok_or(AllocError)?;
+        if end > self.len {
+            return Err(AllocError);
+        }
+
+        self.next.set(end);
+
+        // SAFETY: The offset is within the buffer.
+        let pointer = unsafe { start.add(offset) };
+        let slice = ptr::slice_from_raw_parts_mut(pointer, layout.size());
+        // SAFETY: The buffer does not start at a null pointer.
+        Ok(unsafe { NonNull::new_unchecked(slice) })
+    }
+
+    unsafe fn deallocate(&self, pointer: NonNull<u8>, layout: Layout)
+    {
+        // Only the most recent allocation can be reclaimed.
+        let offset = pointer.as_ptr().offset_from(self.start.as_ptr()) as usize;
+        if offset + layout.size() == self.next.get() {
+            self.next.set(offset);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::heap::AllocError;
+    use crate::heap::Heap;
+    use crate::object::TryNewSymbolError;
+
+    #[test]
+    fn buffer_too_small()
+    {
+        let mut buffer = [MaybeUninit::uninit(); 16];
+        let result = Heap::with_new_in(&mut buffer, |_| ());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn buffer_exhausted()
+    {
+        let mut buffer = [MaybeUninit::uninit(); 4096];
+        Heap::with_new_in(&mut buffer, |heap| {
+            heap.with_new_array_scope(|[symbol]| {
+                let mut count = 0;
+                loop {
+                    match heap.try_new_symbol(symbol, b"Pi") {
+                        Ok(()) => count += 1,
+                        Err(TryNewSymbolError::Alloc(_)) => break,
+                        Err(err) => panic!("{:?}", err),
+                    }
+                }
+                assert!(count > 0);
+                symbol.with_pin(|symbol| {
+                    assert_eq!(symbol.as_symbol(), Some(&b"Pi"[..]));
+                });
+            });
+        }).unwrap();
+    }
+
+    #[test]
+    fn scopes_exhausted()
+    {
+        /// Nest array scopes until the stack of scopes cannot grow,
+        /// and return how deep they were nested.
+        fn nest(heap: &Heap, depth: usize) -> usize
+        {
+            match heap.try_with_new_array_scope(|[_]| nest(heap, depth + 1)) {
+                Ok(depth) => depth,
+                Err(AllocError::OutOfMemory(_)) => depth,
+                Err(err) => panic!("{:?}", err),
+            }
+        }
+
+        let mut buffer = [MaybeUninit::uninit(); 4096];
+        Heap::with_new_in(&mut buffer, |heap| {
+            assert!(nest(heap, 0) > 16);
+            assert_eq!(heap.scope_depth(), 0);
+
+            let result = heap.try_with_new_boxed_scope(1 << 20, |_| ());
+            assert!(matches!(result, Err(AllocError::OutOfMemory(_))));
+            let result = heap.try_with_new_boxed_scope(4, |scope| scope.len());
+            assert_eq!(result.unwrap(), 4);
+        }).unwrap();
+    }
+}
@@ -0,0 +1,370 @@
+use super::Heap;
+use super::HeapMap;
+use super::HeapVec;
+use super::ScopedHandle;
+use super::UnsafeHandle;
+use crate::object::Kind;
+
+use alloc::vec::Vec;
+use core::cell::Cell;
+
+/// Entry in the report returned by [`Heap::dominators`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DominatorNode
+{
+    /// The address of the object, for telling objects apart.
+    pub address: usize,
+
+    #[allow(missing_docs)]
+    pub kind: Kind,
+
+    /// The number of bytes occupied by the object itself.
+    pub size: usize,
+
+    /// The number of bytes that would no longer be reachable
+    /// if this object were no longer reachable.
+    ///
+    /// This includes the object itself, and is at least [`size`].
+    ///
+    /// [`size`]: `Self::size`
+    pub retained_size: usize,
+
+    /// The index into the report of the immediate dominator:
+    /// the nearest object that every path from the roots
+    /// to this object passes through.
+    ///
+    /// If there is no such object, this is [`None`].
+    pub dominator: Option<usize>,
+}
+
+/// Methods for finding out what keeps objects reachable.
+///
+/// An object _dominates_ another if every path from the roots
+/// to the other passes through it,
+/// so that it keeps the other reachable on its own.
+/// Roots are as in [`verify`][`Self::verify`].
+/// The _retained size_ of an object is the total size
+/// of the objects it dominates, including itself.
+/// This is how much memory a collector could reclaim
+/// if the object were no longer reachable.
+impl<'h> Heap<'h>
+{
+    /// The retained size of the given object.
+    ///
+    /// This visits every reachable object.
+    /// To find the retained sizes of many objects,
+    /// use [`dominators`][`Self::dominators`] instead.
+    pub fn retained_size<'s>(&self, object: ScopedHandle<'h, 's>) -> usize
+    {
+        let object = object.as_unsafe_handle();
+
+        // Objects that are reachable without passing through the object.
+        let mut others = HeapMap::new(self);
+        let mut stack = self.roots();
+        stack.retain(|&root| root != object);
+        // SAFETY: These objects are reachable from the roots.
+        unsafe {
+            walk_graph(&mut stack, |handle, _| {
+                handle != object
+                    && others.insert(address(handle), ()).is_none()
+            });
+        }
+
+        let mut retained = HeapMap::new(self);
+        let mut retained_size = 0;
+        let mut stack = self.new_vec();
+        stack.push(object);
+        // SAFETY: The object is reachable from a scope.
+        unsafe {
+            walk_graph(&mut stack, |handle, size| {
+                let new = !others.contains_key(&address(handle))
+                    && retained.insert(address(handle), ()).is_none();
+                if new {
+                    retained_size += size;
+                }
+                new
+            });
+        }
+        retained_size
+    }
+
+    /// Compute the dominator tree of all reachable objects.
+    ///
+    /// The report has an entry for every reachable object,
+    /// ordered by decreasing retained size,
+    /// so the objects that keep the most memory reachable come first.
+    pub fn dominators(&self) -> Vec<DominatorNode>
+    {
+        // Number the objects, with a virtual root at index 0
+        // whose successors are the roots.
+        let mut graph = Graph{
+            heap: self,
+            indices: HeapMap::new(self),
+            nodes: self.new_vec(),
+            sizes: self.new_vec(),
+            successors: self.new_vec(),
+            stack: self.new_vec(),
+        };
+        graph.nodes.push(None);
+        graph.sizes.push(0);
+        graph.successors.push(self.new_vec());
+        for root in self.roots() {
+            let index = graph.index(root);
+            graph.successors[0].push(index);
+        }
+        while let Some(handle) = graph.stack.pop() {
+            let parent = graph.index(handle);
+            let cell = Cell::new(handle);
+            // SAFETY: Objects reachable from roots are not destroyed.
+            let object = unsafe { ScopedHandle::new(&cell) };
+            object.with_pin(|object| {
+                graph.sizes[parent] = object.object_size();
+                object.visit_children(|child| {
+                    let child = graph.index(child.as_unsafe_handle());
+                    graph.successors[parent].push(child);
+                });
+            });
+        }
+        let Graph{nodes, sizes, successors, ..} = graph;
+
+        let dominators = immediate_dominators(self, &successors);
+
+        // Dominators come before the objects they dominate
+        // in reverse postorder, so sum the sizes in postorder.
+        let mut retained = sizes.clone();
+        for &node in dominators.postorder.iter() {
+            if node != 0 {
+                retained[dominators.idom[node]] += retained[node];
+            }
+        }
+
+        // Order the report and translate the indices.
+        let mut order = self.new_vec();
+        order.extend(1 .. nodes.len());
+        order.sort_by(|&a, &b| retained[b].cmp(&retained[a]));
+        let mut position = self.new_vec();
+        position.resize(nodes.len(), 0);
+        for (i, &node) in order.iter().enumerate() {
+            position[node] = i;
+        }
+        order.iter().map(|&node| {
+            let handle = nodes[node].unwrap();
+            // SAFETY: The object is reachable, as nothing was allocated.
+            let kind = unsafe { (*handle.header()).kind };
+            let dominator = dominators.idom[node];
+            DominatorNode{
+                address: address(handle),
+                kind,
+                size: sizes[node],
+                retained_size: retained[node],
+                dominator: (dominator != 0).then(|| position[dominator]),
+            }
+        }).collect()
+    }
+}
+
+/// The object graph, as built by [`Heap::dominators`].
+struct Graph<'a, 'h>
+{
+    heap: &'a Heap<'h>,
+    indices: HeapMap<'h, usize, usize>,
+    nodes: HeapVec<'h, Option<UnsafeHandle<'h>>>,
+    sizes: HeapVec<'h, usize>,
+    successors: HeapVec<'h, HeapVec<'h, usize>>,
+
+    /// Objects whose successors are yet to be found.
+    stack: HeapVec<'h, UnsafeHandle<'h>>,
+}
+
+impl<'a, 'h> Graph<'a, 'h>
+{
+    /// The index of the object, adding it if it is new.
+    fn index(&mut self, handle: UnsafeHandle<'h>) -> usize
+    {
+        let Self{heap, indices, nodes, sizes, successors, stack} = self;
+        *indices.get_or_insert_with(address(handle), || {
+            stack.push(handle);
+            nodes.push(Some(handle));
+            sizes.push(0);
+            successors.push(heap.new_vec());
+            nodes.len() - 1
+        })
+    }
+}
+
+fn address(handle: UnsafeHandle) -> usize
+{
+    handle.as_ptr() as usize
+}
+
+/// Visit the objects reachable from the stack,
+/// descending into an object if `f` returns true for it.
+///
+/// `f` is given the handle and the size of the object.
+///
+/// # Safety
+///
+/// The objects on the stack must be reachable from a scope.
+unsafe fn walk_graph<'h>(
+    stack: &mut HeapVec<'h, UnsafeHandle<'h>>,
+    mut f: impl FnMut(UnsafeHandle<'h>, usize) -> bool,
+)
+{
+    while let Some(handle) = stack.pop() {
+        let cell = Cell::new(handle);
+        let object = ScopedHandle::new(&cell);
+        object.with_pin(|object| {
+            if f(handle, object.object_size()) {
+                object.visit_children(|child| {
+                    stack.push(child.as_unsafe_handle());
+                });
+            }
+        });
+    }
+}
+
+/// Result of [`immediate_dominators`].
+struct Dominators<'h>
+{
+    /// The nodes reachable from node 0, in postorder.
+    postorder: HeapVec<'h, usize>,
+
+    /// The immediate dominator of every node; node 0 dominates itself.
+    idom: HeapVec<'h, usize>,
+}
+
+/// Compute the immediate dominators of a graph rooted at node 0,
+/// using the algorithm of Cooper, Harvey, and Kennedy.
+///
+/// Every node must be reachable from node 0.
+fn immediate_dominators<'h>(
+    heap: &Heap<'h>,
+    successors: &[HeapVec<'h, usize>],
+) -> Dominators<'h>
+{
+    const UNDEFINED: usize = usize::MAX;
+    let len = successors.len();
+
+    // Number the nodes in postorder, without recursion.
+    let mut postorder = heap.new_vec();
+    postorder.reserve(len);
+    let mut number = heap.new_vec();
+    number.resize(len, UNDEFINED);
+    let mut visited = heap.new_vec();
+    visited.resize(len, false);
+    let mut stack = heap.new_vec();
+    stack.push((0, 0));
+    visited[0] = true;
+    while let Some(&mut (node, ref mut next)) = stack.last_mut() {
+        match successors[node].get(*next) {
+            Some(&child) => {
+                *next += 1;
+                if !visited[child] {
+                    visited[child] = true;
+                    stack.push((child, 0));
+                }
+            },
+            None => {
+                number[node] = postorder.len();
+                postorder.push(node);
+                stack.pop();
+            },
+        }
+    }
+
+    let mut predecessors = heap.new_vec();
+    predecessors.resize_with(len, || heap.new_vec());
+    for (node, children) in successors.iter().enumerate() {
+        for &child in children {
+            predecessors[child].push(node);
+        }
+    }
+
+    let intersect = |idom: &[usize], mut a: usize, mut b: usize| {
+        while a != b {
+            while number[a] < number[b] {
+                a = idom[a];
+            }
+            while number[b] < number[a] {
+                b = idom[b];
+            }
+        }
+        a
+    };
+
+    let mut idom = heap.new_vec();
+    idom.resize(len, UNDEFINED);
+    idom[0] = 0;
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &node in postorder.iter().rev().skip(1) {
+            let mut new_idom = UNDEFINED;
+            for &predecessor in &predecessors[node] {
+                if idom[predecessor] == UNDEFINED {
+                    continue;
+                }
+                new_idom = match new_idom {
+                    UNDEFINED => predecessor,
+                    _ => intersect(&idom, predecessor, new_idom),
+                };
+            }
+            if idom[node] != new_idom {
+                idom[node] = new_idom;
+                changed = true;
+            }
+        }
+    }
+
+    Dominators{postorder, idom}
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::object::HEADER_SIZE;
+    use crate::term;
+
+    #[test]
+    fn retained_size()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[shared, term]| {
+                term!(heap, shared, (G A));
+                term!(heap, term, (F {shared} (H B)));
+
+                // The application of F retains itself, F, and H B,
+                // but not the shared term, which is in a scope.
+                let header = HEADER_SIZE;
+                let expected =
+                    (header + 3 * 8) + header + (header + 2 * 8) + 2 * header;
+                assert_eq!(heap.retained_size(term), expected);
+                let shared_size = header + 2 * 8 + 2 * header;
+                assert_eq!(heap.retained_size(shared), shared_size);
+
+                let report = heap.dominators();
+                let find = |handle: ScopedHandle| {
+                    let address = address(handle.as_unsafe_handle());
+                    report.iter().position(|n| n.address == address).unwrap()
+                };
+                let node = report[find(term)];
+                assert_eq!(node.retained_size, expected);
+                assert_eq!(node.kind, Kind::Application);
+                assert_eq!(node.dominator, None);
+
+                // Dominators retain at least what they dominate.
+                for node in &report {
+                    assert!(node.retained_size >= node.size);
+                    if let Some(dominator) = node.dominator {
+                        let dominator = report[dominator];
+                        assert!(dominator.retained_size >= node.retained_size);
+                    }
+                }
+                assert!(report.windows(2).all(|w| {
+                    w[0].retained_size >= w[1].retained_size
+                }));
+            });
+        });
+    }
+}
@@ -0,0 +1,352 @@
+use crate::object::DeBruijn;
+use crate::object::Flags;
+use crate::object::Header;
+use crate::object::WellKnown;
+use crate::object::visit_children;
+use super::Heap;
+use super::HeapMap;
+use super::HeapVec;
+use super::Interrupted;
+use super::Scope;
+use super::ScopedHandle;
+use super::UnsafeHandle;
+
+use core::cell::Cell;
+
+/// Methods for copying objects into a new heap.
+impl<'h> Heap<'h>
+{
+    /// Copy the objects reachable from the given roots into a new heap,
+    /// and pass the new heap and the copies of the roots to `then`.
+    ///
+    /// The new heap has the same configuration as this heap,
+    /// and is independent of it: changes to either do not affect the other,
+    /// and the new heap is destroyed when `then` returns.
+    /// This makes it cheap to try out a transformation and throw it away.
+    /// Sharing between objects is preserved,
+    /// and interned objects are mapped to those of the new heap.
+    /// Gensyms created in the new heap do not clash with those copied.
+    ///
+    /// # Panics
+    ///
+    /// If a reachable custom object has a
+    /// [finalizer][`crate::object::KindDescriptor::finalize`],
+    /// this method panics, as its resources cannot be copied.
+    /// If the new heap runs out of memory, [`handle_alloc_error`] is called.
+    ///
+    /// [`handle_alloc_error`]: `alloc::alloc::handle_alloc_error`
+    pub fn fork<F, R>(&self, roots: &Scope<'h>, then: F) -> R
+        where F: for<'f> FnOnce(&Heap<'f>, &Scope<'f>) -> R
+    {
+        match self.fork_with_safepoint(roots, || Ok(()), then) {
+            Ok(result) => result,
+            Err(Interrupted) => unreachable!(),
+        }
+    }
+
+    /// Similar to [`fork`][`Self::fork`],
+    /// but call [`safepoint`][`Self::safepoint`] before copying each object.
+    ///
+    /// If it returns an error, the new heap is destroyed
+    /// without calling `then`.
+    pub fn try_fork<F, R>(&self, roots: &Scope<'h>, then: F)
+        -> Result<R, Interrupted>
+        where F: for<'f> FnOnce(&Heap<'f>, &Scope<'f>) -> R
+    {
+        self.fork_with_safepoint(roots, || self.safepoint(), then)
+    }
+
+    fn fork_with_safepoint<F, R>(
+        &self,
+        roots: &Scope<'h>,
+        mut safepoint: impl FnMut() -> Result<(), Interrupted>,
+        then: F,
+    ) -> Result<R, Interrupted>
+        where F: for<'f> FnOnce(&Heap<'f>, &Scope<'f>) -> R
+    {
+        Heap::with_new_config(self.config(), |fork| {
+            fork.next_gensym_number.set(self.next_gensym_number.get());
+            fork.with_new_boxed_scope(roots.len(), |copies| {
+                fork.without_gc(|| {
+                    let mut copier = Copier{
+                        fork,
+                        copies: self.interned_copies(fork),
+                        fixups: fork.new_vec(),
+                    };
+                    for (root, copy) in roots.iter().zip(copies.iter()) {
+                        let root = root.as_unsafe_handle();
+                        // SAFETY: Roots are reachable from a scope.
+                        let root = unsafe {
+                            copier.copy_graph(root, &mut safepoint)?
+                        };
+                        // SAFETY: The copy is reachable from the fork.
+                        unsafe { copy.copy_from_unsafe_handle(root) };
+                    }
+                    // SAFETY: Every reachable object was copied.
+                    unsafe { copier.fix_children() };
+                    Ok(())
+                })?;
+                Ok(then(fork, copies))
+            })
+        })
+    }
+
+    /// The objects of the fork that correspond to
+    /// the interned objects of this heap, by address.
+    fn interned_copies<'f>(&self, fork: &Heap<'f>)
+        -> HeapMap<'f, usize, UnsafeHandle<'f>>
+    {
+        let mut copies = HeapMap::new(fork);
+        let address = |handle: UnsafeHandle| handle.as_ptr() as usize;
+
+        for well_known in WellKnown::ALL {
+            copies.insert(
+                address(self.interned_symbol(well_known)),
+                fork.interned_symbol(well_known),
+            );
+        }
+
+        for i in 0 .. {
+            match self.interned_extra_symbol(i) {
+                Some(interned) => copies.insert(
+                    address(interned),
+                    fork.interned_extra_symbol(i).unwrap(),
+                ),
+                None => break,
+            };
+        }
+
+        for i in 0 .. {
+            let de_bruijn = DeBruijn(i);
+            match self.interned_variable(de_bruijn) {
+                Some(interned) => copies.insert(
+                    address(interned),
+                    fork.interned_variable(de_bruijn).unwrap(),
+                ),
+                None => break,
+            };
+        }
+
+        fork.with_new_array_scope(|[scoped]| {
+            for char in (0u8 ..= 0x7F).map(char::from) {
+                if let Some(interned) = self.interned_char(char) {
+                    fork.new_char(scoped, char);
+                    copies.insert(address(interned), scoped.as_unsafe_handle());
+                }
+            }
+
+            // SAFETY: We only borrow the table for short periods of time.
+            let num_qualified = unsafe {
+                self.interned_qualified_symbols.borrow().len()
+            };
+            for i in 0 .. num_qualified {
+                let interned = unsafe {
+                    self.interned_qualified_symbols.borrow()[i].get()
+                };
+                let cell = Cell::new(interned);
+                // SAFETY: Interned objects are not destroyed.
+                let interned = unsafe { ScopedHandle::new(&cell) };
+                interned.with_pin(|interned| {
+                    let (namespace, name) =
+                        interned.as_qualified_symbol().unwrap();
+                    fork.intern_qualified_symbol(scoped, namespace, name)
+                        .expect("Interned symbol has a valid length");
+                });
+                copies.insert(
+                    address(interned.as_unsafe_handle()),
+                    scoped.as_unsafe_handle(),
+                );
+            }
+        });
+
+        copies
+    }
+}
+
+/// State of [`Heap::fork`].
+struct Copier<'a, 'f>
+{
+    fork: &'a Heap<'f>,
+
+    /// The copy of every object copied so far,
+    /// by the address of the original.
+    copies: HeapMap<'f, usize, UnsafeHandle<'f>>,
+
+    /// Copies whose children still refer to the originals.
+    fixups: HeapVec<'f, UnsafeHandle<'f>>,
+}
+
+impl<'a, 'f> Copier<'a, 'f>
+{
+    /// Copy the object and the objects reachable from it,
+    /// except for those copied before, and return the copy.
+    ///
+    /// The children of the copies still refer to the originals
+    /// until [`fix_children`][`Self::fix_children`] is called.
+    /// The safepoint is called before each object is copied,
+    /// and copying stops if it returns an error.
+    ///
+    /// # Safety
+    ///
+    /// The object must be reachable from a scope of the original heap.
+    unsafe fn copy_graph(
+        &mut self,
+        root: UnsafeHandle,
+        safepoint: &mut impl FnMut() -> Result<(), Interrupted>,
+    ) -> Result<UnsafeHandle<'f>, Interrupted>
+    {
+        let mut stack = self.fork.new_vec();
+        stack.push(root);
+        while let Some(handle) = stack.pop() {
+            let address = handle.as_ptr() as usize;
+            if self.copies.contains_key(&address) {
+                continue;
+            }
+            safepoint()?;
+
+            let cell = Cell::new(handle);
+            let object = ScopedHandle::new(&cell);
+            let copy = object.with_pin(|object| {
+                object.visit_children(|child| {
+                    stack.push(child.as_unsafe_handle());
+                });
+                if let Some((kind, _)) = object.as_custom() {
+                    let descriptor = self.fork.custom_kind(kind).unwrap();
+                    assert!(
+                        descriptor.finalize.is_none(),
+                        "Custom object with a finalizer cannot be copied",
+                    );
+                }
+
+                let payload_size = object.payload_size();
+                let mut header = object.header();
+                header.flags.remove(
+                    Flags::MARKED | Flags::PINNED | Flags::AGE,
+                );
+                self.fork.alloc(payload_size, |payload| {
+                    (object.payload() as *const u8).copy_to_nonoverlapping(
+                        payload as *mut u8,
+                        payload_size,
+                    );
+                    header
+                })
+            });
+
+            self.copies.insert(address, copy);
+            self.fixups.push(copy);
+        }
+        Ok(self.copies[&(root.as_ptr() as usize)])
+    }
+
+    /// Make the children of the copies refer to the copies of the children.
+    ///
+    /// # Safety
+    ///
+    /// Every object reachable from the copies must have been copied.
+    unsafe fn fix_children(&mut self)
+    {
+        for &copy in &self.fixups {
+            let header: &Header = &*copy.header();
+            visit_children(header, copy.payload(), |child| {
+                let original = child.get().as_ptr() as usize;
+                child.set(self.copies[&original]);
+            });
+        }
+        self.fixups.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::heap::HeapConfig;
+    use crate::term;
+    use crate::testing::TermTree;
+
+    use core::sync::atomic::AtomicBool;
+    use core::sync::atomic::Ordering;
+
+    #[test]
+    fn fork()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_boxed_scope(2, |roots| {
+                let [term, gensym] = [0, 1].map(|i| roots.get(i).unwrap());
+                heap.with_new_array_scope(|[inner, char, nil]| {
+                    heap.new_char(char, 'x');
+                    let interned = heap.interned_symbol(WellKnown::Nil);
+                    // SAFETY: Interned objects are not destroyed.
+                    unsafe { nil.copy_from_unsafe_handle(interned) };
+                    term!(heap, inner, (G #0 {char} {nil}));
+                    term!(heap, term, (F {inner} {inner} ?1));
+                });
+                heap.new_gensym(gensym, b"x").unwrap();
+
+                let tree = TermTree::read(heap, term);
+                heap.fork(roots, |fork, copies| {
+                    let [term, gensym] =
+                        [0, 1].map(|i| copies.get(i).unwrap());
+                    assert_eq!(TermTree::read(fork, term), tree);
+                    assert!(gensym.is_gensym());
+
+                    term.with_pin(|term| {
+                        let (_, arguments) = term.as_application().unwrap();
+                        let [a, b] = [0, 1].map(|i| arguments.get(i).unwrap());
+                        assert!(a.ptr_eq(b));
+                        a.with_pin(|a| {
+                            let (_, arguments) = a.as_application().unwrap();
+                            let nil = fork.interned_symbol(WellKnown::Nil);
+                            let [var, char, symbol] =
+                                [0, 1, 2].map(|i| arguments.get(i).unwrap());
+                            assert_eq!(
+                                Some(var.as_unsafe_handle()),
+                                fork.interned_variable(DeBruijn(0)),
+                            );
+                            assert_eq!(
+                                Some(char.as_unsafe_handle()),
+                                fork.interned_char('x'),
+                            );
+                            assert!(symbol.as_unsafe_handle() == nil);
+                        });
+                    });
+
+                    // Gensyms keep counting where the original left off.
+                    fork.with_new_array_scope(|[fresh]| {
+                        fork.new_gensym(fresh, b"x").unwrap();
+                        fresh.with_pin(|fresh| gensym.with_pin(|gensym| {
+                            assert_ne!(fresh.as_symbol(), gensym.as_symbol());
+                        }));
+                    });
+
+                    fork.verify();
+                });
+                heap.verify();
+            });
+        });
+    }
+
+    #[test]
+    fn interrupt()
+    {
+        let flag = AtomicBool::new(true);
+        let config = HeapConfig{
+            interrupt: Some(&flag),
+            ..HeapConfig::default()
+        };
+        Heap::with_new_config(config, |heap| {
+            heap.with_new_boxed_scope(1, |roots| {
+                term!(heap, roots.get(0).unwrap(), (F A));
+                let forked = heap.try_fork(roots, |_, _| unreachable!());
+                assert!(matches!(forked, Err(Interrupted)));
+
+                flag.store(false, Ordering::Relaxed);
+                let tree = TermTree::read(heap, roots.get(0).unwrap());
+                let forked = heap.try_fork(roots, |fork, copies| {
+                    TermTree::read(fork, copies.get(0).unwrap())
+                });
+                assert_eq!(forked.unwrap(), tree);
+            });
+        });
+    }
+}
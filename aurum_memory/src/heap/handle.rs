@@ -1,4 +1,5 @@
 use crate::object::Flags;
+use crate::object::HEADER_SIZE;
 use crate::object::Header;
 use crate::object::Object;
 use crate::object::Payload;
@@ -107,6 +108,13 @@ impl<'h, 'p> PinnedHandle<'h, 'p>
         self.handle
     }
 
+    /// Whether this handle and the other handle refer to the same object.
+    #[inline]
+    pub fn ptr_eq(self, other: PinnedHandle<'h, '_>) -> bool
+    {
+        self.as_unsafe_handle() == other.as_unsafe_handle()
+    }
+
     /// Get the header of the object referenced by this handle.
     #[inline]
     pub fn header(self) -> Header
@@ -122,6 +130,23 @@ impl<'h, 'p> PinnedHandle<'h, 'p>
         // SAFETY: The handle refers to an object, as it is pinned.
         unsafe { self.as_unsafe_handle().payload() }
     }
+
+    /// The number of garbage collection cycles the object has survived.
+    ///
+    /// See [`Flags::age`] for more information.
+    #[inline]
+    pub fn age(self) -> u8
+    {
+        self.header().flags.age()
+    }
+
+    /// The number of bytes occupied by the object,
+    /// including both the header and the payload.
+    #[inline]
+    pub fn object_size(self) -> usize
+    {
+        HEADER_SIZE + self.payload_size()
+    }
 }
 
 /// Pointer to a handle that is part of a scope.
@@ -161,6 +186,13 @@ impl<'h, 's> ScopedHandle<'h, 's>
         self.handle.get()
     }
 
+    /// Whether this handle and the other handle refer to the same object.
+    #[inline]
+    pub fn ptr_eq(self, other: ScopedHandle<'h, '_>) -> bool
+    {
+        self.as_unsafe_handle() == other.as_unsafe_handle()
+    }
+
     /// Modify this handle to refer to the same object as the other handle.
     #[inline]
     pub fn copy_from(self, other: ScopedHandle<'h, 's>)
@@ -168,6 +200,13 @@ impl<'h, 's> ScopedHandle<'h, 's>
         self.handle.set(other.as_unsafe_handle());
     }
 
+    /// Exchange the objects referred to by this handle and the other handle.
+    #[inline]
+    pub fn swap(self, other: ScopedHandle<'h, 's>)
+    {
+        self.handle.swap(other.handle);
+    }
+
     /// Modify this handle to refer to the same object as the other handle.
     #[inline]
     pub unsafe fn copy_from_unsafe_handle(self, other: UnsafeHandle<'h>)
@@ -214,3 +253,32 @@ impl<'h, 's> ScopedHandle<'h, 's>
         unsafe { *self.as_unsafe_handle().header() }
     }
 }
+
+#[cfg(test)]
+mod tests
+{
+    use crate::heap::Heap;
+
+    #[test]
+    fn ptr_eq()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[a, b, c]| {
+                heap.new_symbol(a, b"Same").unwrap();
+                heap.new_symbol(b, b"Same").unwrap();
+                c.copy_from(a);
+
+                // Equal contents do not make the objects the same.
+                assert!(a.ptr_eq(a));
+                assert!(a.ptr_eq(c));
+                assert!(!a.ptr_eq(b));
+
+                a.with_pin(|pa| b.with_pin(|pb| c.with_pin(|pc| {
+                    assert!(pa.ptr_eq(pa));
+                    assert!(pa.ptr_eq(pc));
+                    assert!(!pa.ptr_eq(pb));
+                })));
+            });
+        });
+    }
+}
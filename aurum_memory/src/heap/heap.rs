@@ -1,12 +1,42 @@
+use super::AllocError;
+use super::BufferAllocator;
+use super::MemoEntry;
+use super::Trace;
 use super::UnsafeHandle;
+use crate::object::CustomKind;
 use crate::object::DeBruijn;
+use crate::object::KindDescriptor;
+use crate::object::TryNewSymbolError;
+use crate::object::WellKnown;
 
+use alloc::alloc::Global;
+use alloc::alloc::handle_alloc_error;
+use alloc::boxed::Box;
 use alloc::vec::Vec;
 use unsafe_ref_cell::UnsafeRefCell;
+use core::alloc::Allocator;
+use core::alloc::Layout;
+use core::any::Any;
 use core::cell::Cell;
 use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+use scopeguard::defer;
 
-const INTERNED_VARIABLE_COUNT: usize = 16;
+const INITIAL_SCOPES_CAPACITY: usize = 16;
+
+/// Characters below this code point are interned on first use,
+/// which covers the ASCII range.
+const INTERNED_CHAR_COUNT: usize = 0x80;
+
+/// Source of [`Heap::runtime_id`]s.
+static NEXT_RUNTIME_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// Returned by [`Heap::safepoint`] when an interrupt was requested.
+#[derive(Debug)]
+pub struct Interrupted;
 
 /// Uniquely identifies a heap at compile-time.
 ///
@@ -16,21 +46,187 @@ const INTERNED_VARIABLE_COUNT: usize = 16;
 /// which is important for the garbage collector to work safely.
 pub type HeapId<'h> = PhantomData<fn(&'h ()) -> &'h ()>;
 
+/// Memory for the handles of a boxed scope.
+pub (super) type ScopeBuffer<'h> =
+    Vec<Cell<UnsafeHandle<'h>>, &'h dyn Allocator>;
+
+/// Vector whose memory comes from [`HeapConfig::allocator`],
+/// for the working memory of operations on terms.
+pub (crate) type HeapVec<'h, T> = Vec<T, &'h dyn Allocator>;
+
+/// Options for creating a heap.
+///
+/// Pass this to [`Heap::with_new_config`].
+/// The [`Default`] configuration is what [`Heap::with_new`] uses.
+pub struct HeapConfig<'a>
+{
+    /// Allocator from which the heap obtains all of its memory.
+    pub allocator: &'a dyn Allocator,
+
+    /// Maximum number of bytes that objects may occupy in total.
+    ///
+    /// Once this limit would be exceeded, the `try_new_*` methods
+    /// return [`AllocError::HeapFull`] rather than allocating.
+    /// This is useful for bounding the memory used by untrusted programs.
+    /// By default there is no limit.
+    pub memory_limit: usize,
+
+    /// Number of variable objects to intern.
+    ///
+    /// Variables with De Bruijn indices below this number
+    /// are created when the heap is created,
+    /// and [`Heap::new_variable`] reuses them rather than allocating.
+    /// Workloads with deeply nested binders benefit from a larger number,
+    /// whereas tiny heaps may want to intern fewer variables.
+    /// By default 16 variables are interned.
+    pub interned_variable_count: usize,
+
+    /// Names of symbols to intern in addition to the [`WellKnown`] ones.
+    ///
+    /// The symbol objects are created when the heap is created,
+    /// and [`Heap::interned_extra_symbol`] returns them by their index
+    /// into this list, so a language can intern the names it uses often.
+    /// Creating the heap panics if a name is too long for a symbol.
+    /// By default no extra symbols are interned.
+    pub extra_interned_symbols: &'a [&'a [u8]],
+
+    /// Maximum number of entries in the memo table.
+    ///
+    /// Once the table is full, [`Heap::memo_insert`] evicts
+    /// the entry that was least recently inserted or found,
+    /// so the table does not grow without bound
+    /// when a long-running program memoizes many distinct terms.
+    /// If this is zero, nothing is memoized.
+    /// By default the table holds up to 4096 entries.
+    pub memo_capacity: usize,
+
+    /// Kinds of objects that are defined outside this crate.
+    ///
+    /// Objects of these kinds can be created with [`Heap::new_custom`],
+    /// passing the index into this list as the [`CustomKind`].
+    /// By default there are no custom kinds.
+    pub custom_kinds: &'a [&'static KindDescriptor],
+
+    /// Flag with which the host requests that computations stop.
+    ///
+    /// Heaps cannot be shared between threads, but this flag can:
+    /// another thread may set it, and long-running operations
+    /// then return [`Interrupted`] at their next [safepoint].
+    /// The flag is not cleared by the heap; the host does that
+    /// before starting the next computation.
+    /// By default there is no flag, and nothing is interrupted.
+    ///
+    /// [safepoint]: `Heap::safepoint`
+    pub interrupt: Option<&'a AtomicBool>,
+
+    /// Data that the host associates with the heap.
+    ///
+    /// Native code that is only given the heap can retrieve this
+    /// with [`Heap::user_data`], for instance to reach the host's
+    /// I/O handles or configuration without resorting to thread-locals.
+    /// By default there is no data.
+    pub user_data: Option<&'a dyn Any>,
+}
+
+impl Default for HeapConfig<'static>
+{
+    fn default() -> Self
+    {
+        Self{
+            allocator: &Global,
+            memory_limit: usize::MAX,
+            interned_variable_count: 16,
+            extra_interned_symbols: &[],
+            memo_capacity: 4096,
+            custom_kinds: &[],
+            interrupt: None,
+            user_data: None,
+        }
+    }
+}
+
 /// Collection of objects that may point to each other.
 pub struct Heap<'h>
 {
     /// Uniquely identifies this heap.
     heap_id: HeapId<'h>,
 
+    /// See [`Heap::runtime_id`].
+    runtime_id: usize,
+
+    /// See [`HeapConfig::allocator`].
+    pub (super) allocator: &'h dyn Allocator,
+
+    /// See [`HeapConfig::memory_limit`] and [`Heap::allocated_bytes`].
+    pub (super) memory_limit: usize,
+    pub (super) allocated_bytes: Cell<usize>,
+
+    /// Number of active regions created by [`Heap::without_gc`].
+    without_gc_depth: Cell<usize>,
+
+    /// Number of the next gensym created by [`Heap::new_gensym`].
+    pub (super) next_gensym_number: Cell<u64>,
+
     /// Stack of scopes managed by `with_scope`.
-    /// It is important that the stack is managed *only* by `with_scope`,
+    /// It is important that the stack is managed *only* by `with_scope`
+    /// (and `with_new_growable_scope`, which works the same way),
     /// as the push and pop must happen in the same order
     /// as scope creation and destruction.
-    pub (super) scopes: UnsafeRefCell<Vec<*const [Cell<UnsafeHandle<'h>>]>>,
+    /// In debug mode, unregistering a scope out of order panics.
+    ///
+    /// Borrows of the stack must not outlive the statement that makes them,
+    /// and must not span calls that can register or unregister scopes,
+    /// such as allocation or embedder code.
+    /// In debug mode, overlapping borrows panic, as with any [`UnsafeRefCell`].
+    pub (super) scopes: UnsafeRefCell<
+        Vec<*const [Cell<UnsafeHandle<'h>>], &'h dyn Allocator>
+    >,
+
+    /// Stack of data structures registered by `with_root`.
+    pub (super) traced_roots: UnsafeRefCell<
+        Vec<*const dyn Trace<'h>, &'h dyn Allocator>
+    >,
+
+    /// Buffers for boxed scopes that are not currently in use.
+    /// Managed by `with_new_boxed_scope`, which reuses them.
+    pub (super) scope_pool: UnsafeRefCell<
+        Vec<ScopeBuffer<'h>, &'h dyn Allocator>
+    >,
+
+    /// See [`HeapConfig::extra_interned_symbols`].
+    extra_interned_symbol_names: &'h [&'h [u8]],
+
+    /// See [`HeapConfig::custom_kinds`].
+    custom_kinds: &'h [&'static KindDescriptor],
+
+    /// See [`HeapConfig::interrupt`].
+    interrupt: Option<&'h AtomicBool>,
+
+    /// See [`HeapConfig::user_data`].
+    user_data: Option<&'h dyn Any>,
+
+    /// Entries of the memo table, ordered by hash.
+    /// See [`Heap::memo_get`] for more information.
+    pub (super) memo: UnsafeRefCell<Vec<MemoEntry<'h>, &'h dyn Allocator>>,
+
+    /// See [`HeapConfig::memo_capacity`].
+    pub (super) memo_capacity: usize,
+
+    /// Incremented on every use of the memo table,
+    /// to find the least recently used entry.
+    pub (super) memo_clock: Cell<u64>,
+
+    /// Qualified symbols, ordered by namespace and name.
+    /// See [`Heap::intern_qualified_symbol`] for more information.
+    pub (crate) interned_qualified_symbols: UnsafeRefCell<
+        Vec<Cell<UnsafeHandle<'h>>, &'h dyn Allocator>
+    >,
 
     /// See the corresponding methods for more information.
-    interned_null: Cell<UnsafeHandle<'h>>,
-    interned_variables: Cell<[UnsafeHandle<'h>; INTERNED_VARIABLE_COUNT]>,
+    interned_symbols: Cell<[UnsafeHandle<'h>; WellKnown::ALL.len()]>,
+    interned_variables: Box<[Cell<UnsafeHandle<'h>>], &'h dyn Allocator>,
+    interned_extra_symbols: Box<[Cell<UnsafeHandle<'h>>], &'h dyn Allocator>,
+    interned_chars: Cell<[Option<UnsafeHandle<'h>>; INTERNED_CHAR_COUNT]>,
 }
 
 impl<'h> Heap<'h>
@@ -46,39 +242,308 @@ impl<'h> Heap<'h>
     pub fn with_new<F, R>(then: F) -> R
         where F: for<'fresh_h> FnOnce(&Heap<'fresh_h>) -> R
     {
+        Self::with_new_config(HeapConfig::default(), then)
+    }
+
+    /// Create a new heap with the given configuration
+    /// and pass it to the given function.
+    ///
+    /// See [`with_new`][`Self::with_new`] for more information.
+    pub fn with_new_config<F, R>(config: HeapConfig, then: F) -> R
+        where F: for<'fresh_h> FnOnce(&Heap<'fresh_h>) -> R
+    {
+        match Self::try_with_new_config(config, then) {
+            Ok(result) => result,
+            Err(err) => handle_alloc_error(err.layout()),
+        }
+    }
+
+    /// Create a new heap with the given [user data]
+    /// and pass it to the given function.
+    ///
+    /// See [`with_new`][`Self::with_new`] for more information.
+    ///
+    /// [user data]: `HeapConfig::user_data`
+    pub fn with_new_and_data<D, F, R>(data: D, then: F) -> R
+        where D: Any,
+              F: for<'fresh_h> FnOnce(&Heap<'fresh_h>) -> R
+    {
+        let config = HeapConfig{
+            user_data: Some(&data),
+            ..HeapConfig::default()
+        };
+        Self::with_new_config(config, then)
+    }
+
+    /// Create a new heap that allocates from the given buffer
+    /// and pass it to the given function.
+    ///
+    /// The heap does not use the global allocator at all;
+    /// all of its memory comes from the buffer (see [`BufferAllocator`]).
+    /// Once the buffer is exhausted, the `try_new_*` methods return errors.
+    /// If the buffer is too small to even create the heap,
+    /// this method returns an error and `then` is not called.
+    ///
+    /// Scopes, and the working memory of operations on terms,
+    /// also come from the buffer.
+    /// Once it is exhausted, the `try_with_new_*_scope` methods
+    /// return errors, whereas other methods abort the process
+    /// through [`handle_alloc_error`], as with any allocator.
+    /// Results that do not borrow the heap, such as a [`HeapSnapshot`]
+    /// or the report of [`dominators`][`Self::dominators`],
+    /// belong to the caller and are ordinary collections.
+    ///
+    /// [`HeapSnapshot`]: `super::HeapSnapshot`
+    pub fn with_new_in<F, R>(buffer: &mut [MaybeUninit<u8>], then: F)
+        -> Result<R, AllocError>
+        where F: for<'fresh_h> FnOnce(&Heap<'fresh_h>) -> R
+    {
+        let allocator = BufferAllocator::new(buffer);
+        let config = HeapConfig{
+            allocator: &allocator,
+            ..HeapConfig::default()
+        };
+        Self::try_with_new_config(config, then)
+    }
+
+    /// Similar to [`with_new_config`][`Self::with_new_config`],
+    /// but return an error if the heap cannot be created.
+    ///
+    /// If an error is returned, `then` is not called.
+    pub fn try_with_new_config<F, R>(config: HeapConfig, then: F)
+        -> Result<R, AllocError>
+        where F: for<'fresh_h> FnOnce(&Heap<'fresh_h>) -> R
+    {
+        let HeapConfig{
+            allocator,
+            memory_limit,
+            interned_variable_count,
+            extra_interned_symbols,
+            memo_capacity,
+            custom_kinds,
+            interrupt,
+            user_data,
+        } = config;
+
+        // Reserve the stack of scopes up front, so that
+        // a lack of memory is reported rather than aborting.
+        let scopes = try_vec_with_capacity(allocator, INITIAL_SCOPES_CAPACITY)?;
+
+        let mut interned_variables =
+            try_vec_with_capacity(allocator, interned_variable_count)?;
+        interned_variables.resize(
+            interned_variable_count,
+            Cell::new(UnsafeHandle::dangling()),
+        );
+
+        let mut interned_extra_symbols =
+            try_vec_with_capacity(allocator, extra_interned_symbols.len())?;
+        interned_extra_symbols.resize(
+            extra_interned_symbols.len(),
+            Cell::new(UnsafeHandle::dangling()),
+        );
+
         // Create the heap.
         let this = Heap{
 
             heap_id: PhantomData,
-            scopes: UnsafeRefCell::new(Vec::new()),
+            runtime_id: NEXT_RUNTIME_ID.fetch_add(1, Ordering::Relaxed),
+            allocator,
+            memory_limit,
+            allocated_bytes: Cell::new(0),
+            without_gc_depth: Cell::new(0),
+            next_gensym_number: Cell::new(0),
+            scopes: UnsafeRefCell::new(scopes),
+            scope_pool: UnsafeRefCell::new(Vec::new_in(allocator)),
+            traced_roots: UnsafeRefCell::new(Vec::new_in(allocator)),
+            custom_kinds,
+            interrupt,
+            user_data,
+            extra_interned_symbol_names: extra_interned_symbols,
+            memo: UnsafeRefCell::new(Vec::new_in(allocator)),
+            memo_capacity,
+            memo_clock: Cell::new(0),
+            interned_qualified_symbols:
+                UnsafeRefCell::new(Vec::new_in(allocator)),
 
             // These will be initialized below.
-            interned_null: Cell::new(UnsafeHandle::dangling()),
-            interned_variables: Cell::new([UnsafeHandle::dangling(); 16]),
+            interned_symbols: Cell::new(
+                [UnsafeHandle::dangling(); WellKnown::ALL.len()]
+            ),
+            interned_variables: interned_variables.into_boxed_slice(),
+            interned_extra_symbols: interned_extra_symbols.into_boxed_slice(),
+            interned_chars: Cell::new([None; INTERNED_CHAR_COUNT]),
 
         };
 
-        this.with_new_array_scope(|[scoped]| {
-
-            // TODO: Make sure no GC takes place until
-            //       interned fields have been initialized
+        // Interned fields are dangling until they are initialized,
+        // so the garbage collector must not look at them until then.
+        this.without_gc(|| this.with_new_array_scope(|[scoped]| {
 
-            // Initialize the interned null object.
-            this.new_symbol(scoped, b"Null").unwrap();
-            this.interned_null.set(scoped.as_unsafe_handle());
+            // Initialize the interned symbol objects.
+            let interned_symbols = this.interned_symbols.as_array_of_cells();
+            for (&well_known, interned) in
+                WellKnown::ALL.iter().zip(interned_symbols)
+            {
+                match this.try_new_symbol(scoped, well_known.name()) {
+                    Ok(()) => (),
+                    Err(TryNewSymbolError::Alloc(err)) => return Err(err),
+                    Err(TryNewSymbolError::Len(_)) => unreachable!(),
+                }
+                interned.set(scoped.as_unsafe_handle());
+            }
 
             // Initialize the interned variable objects.
-            for i in 0 .. INTERNED_VARIABLE_COUNT {
+            for (i, interned) in this.interned_variables.iter().enumerate() {
                 let de_bruijn = DeBruijn(i as u32);
-                this.new_variable_not_interned(scoped, de_bruijn);
-                this.interned_variables.as_array_of_cells()[i]
-                    .set(scoped.as_unsafe_handle());
+                this.try_new_variable_not_interned(scoped, de_bruijn)?;
+                interned.set(scoped.as_unsafe_handle());
             }
 
-        });
+            // Initialize the extra interned symbol objects.
+            let interned_extra_symbols = this.interned_extra_symbols.iter();
+            for (&name, interned) in
+                extra_interned_symbols.iter().zip(interned_extra_symbols)
+            {
+                match this.try_new_symbol(scoped, name) {
+                    Ok(()) => (),
+                    Err(TryNewSymbolError::Alloc(err)) => return Err(err),
+                    Err(TryNewSymbolError::Len(_)) =>
+                        panic!("Extra interned symbol name is too long"),
+                }
+                interned.set(scoped.as_unsafe_handle());
+            }
+
+            Ok(())
+
+        }))?;
 
         // Call the continuation.
-        then(&this)
+        Ok(then(&this))
+    }
+
+    /// The configuration that this heap was created with.
+    pub (super) fn config(&self) -> HeapConfig<'h>
+    {
+        HeapConfig{
+            allocator: self.allocator,
+            memory_limit: self.memory_limit,
+            interned_variable_count: self.interned_variables.len(),
+            extra_interned_symbols: self.extra_interned_symbol_names,
+            memo_capacity: self.memo_capacity,
+            custom_kinds: self.custom_kinds,
+            interrupt: self.interrupt,
+            user_data: self.user_data,
+        }
+    }
+
+    /// Create an empty vector that allocates from the allocator
+    /// that this heap was created with.
+    ///
+    /// Operations on terms keep their working memory in these,
+    /// so that they too respect [`HeapConfig::allocator`].
+    pub (crate) fn new_vec<T>(&self) -> HeapVec<'h, T>
+    {
+        Vec::new_in(self.allocator)
+    }
+
+    /// Number that identifies the heap at runtime.
+    ///
+    /// The `'h` lifetime already keeps objects of different heaps apart,
+    /// but unsafe code can get around it.
+    /// Every heap created by this process has a different runtime id,
+    /// which debug builds stamp into each object,
+    /// so that objects that end up in the wrong heap
+    /// are caught when new objects refer to them,
+    /// and by [`verify`][`Self::verify`].
+    #[inline]
+    pub fn runtime_id(&self) -> usize
+    {
+        self.runtime_id
+    }
+
+    /// The number of bytes that objects occupy in total.
+    ///
+    /// This is the amount that is checked against
+    /// the [memory limit][`HeapConfig::memory_limit`].
+    /// It is the same in debug and release builds,
+    /// even though debug builds stamp each object with a few more bytes.
+    #[inline]
+    pub fn allocated_bytes(&self) -> usize
+    {
+        self.allocated_bytes.get()
+    }
+
+    /// Call the given function while preventing garbage collection.
+    ///
+    /// The garbage collector will not run until the function returns,
+    /// so unsafe handles obtained within it remain valid throughout.
+    /// This is useful when initializing several objects
+    /// that must not be observed by the garbage collector halfway through.
+    /// Allocations within the function still succeed as usual.
+    /// These regions may be nested.
+    pub fn without_gc<F, R>(&self, then: F) -> R
+        where F: FnOnce() -> R
+    {
+        self.without_gc_depth.set(self.without_gc_depth.get() + 1);
+        defer! { self.without_gc_depth.set(self.without_gc_depth.get() - 1); }
+        then()
+    }
+
+    /// Whether garbage collection is currently prevented
+    /// by [`without_gc`][`Self::without_gc`].
+    #[inline]
+    pub fn is_gc_prevented(&self) -> bool
+    {
+        self.without_gc_depth.get() != 0
+    }
+
+    /// Check whether the host requested an interrupt.
+    ///
+    /// Long-running operations call this regularly,
+    /// and stop with [`Interrupted`] if it says so.
+    /// Code that builds on this crate should do the same,
+    /// for instance once per reduction step.
+    /// See [`HeapConfig::interrupt`] for more information.
+    #[inline]
+    pub fn safepoint(&self) -> Result<(), Interrupted>
+    {
+        match self.interrupt {
+            Some(flag) if flag.load(Ordering::Relaxed) => Err(Interrupted),
+            _ => Ok(()),
+        }
+    }
+
+    /// Request that long-running operations on this heap stop,
+    /// by setting the [interrupt flag][`HeapConfig::interrupt`].
+    ///
+    /// Other threads set the flag directly, as they cannot use the heap.
+    /// If the heap has no interrupt flag, this method does nothing.
+    pub fn request_interrupt(&self)
+    {
+        if let Some(flag) = self.interrupt {
+            flag.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// The [user data][`HeapConfig::user_data`] of the heap.
+    ///
+    /// If the heap has no user data, or the data is not of type `D`,
+    /// this method returns [`None`].
+    #[inline]
+    pub fn user_data<D>(&self) -> Option<&D>
+        where D: Any
+    {
+        self.user_data?.downcast_ref()
+    }
+
+    /// Obtain a number that was not obtained before,
+    /// for the name of a gensym.
+    pub (crate) fn next_gensym_number(&self) -> u64
+    {
+        let number = self.next_gensym_number.get();
+        self.next_gensym_number.set(number + 1);
+        number
     }
 
     /// Interned Null object.
@@ -88,19 +553,303 @@ impl<'h> Heap<'h>
     #[inline]
     pub fn interned_null(&self) -> UnsafeHandle<'h>
     {
-        self.interned_null.get()
+        self.interned_symbol(WellKnown::Null)
+    }
+
+    /// Interned symbol objects with commonly used names.
+    ///
+    /// Using these rather than creating symbols with the same names
+    /// saves memory and allows for comparison using
+    /// [`ptr_eq`][`super::ScopedHandle::ptr_eq`] as a fast path.
+    #[inline]
+    pub fn interned_symbol(&self, well_known: WellKnown) -> UnsafeHandle<'h>
+    {
+        self.interned_symbols.as_array_of_cells()[well_known as usize].get()
+    }
+
+    /// Interned symbol objects with the names given by
+    /// [`HeapConfig::extra_interned_symbols`], by index into that list.
+    #[inline]
+    pub fn interned_extra_symbol(&self, index: usize)
+        -> Option<UnsafeHandle<'h>>
+    {
+        self.interned_extra_symbols.get(index).map(Cell::get)
     }
 
     /// Interned variable objects with small De Bruijn indices.
     ///
     /// The [`new_variable`][`Heap::new_variable`]
     /// method automatically consults this array.
+    /// Its size is given by [`HeapConfig::interned_variable_count`].
     #[inline]
     pub fn interned_variable(&self, de_bruijn: DeBruijn)
         -> Option<UnsafeHandle<'h>>
     {
-        self.interned_variables.as_array_of_cells()
+        self.interned_variables
             .get(de_bruijn.0 as usize)
             .map(Cell::get)
     }
+
+    /// Interned character objects for the ASCII range.
+    ///
+    /// The [`new_char`][`Heap::new_char`]
+    /// method automatically consults this array,
+    /// and interns each character the first time it is created.
+    /// Characters that were not created yet are not in the array.
+    #[inline]
+    pub fn interned_char(&self, char: char) -> Option<UnsafeHandle<'h>>
+    {
+        self.interned_chars.as_array_of_cells()
+            .get(char as usize)
+            .and_then(Cell::get)
+    }
+
+    /// Remember the given character object for
+    /// [`interned_char`][`Self::interned_char`],
+    /// if its character is in the interned range.
+    pub (crate) fn set_interned_char(
+        &self,
+        char: char,
+        object: UnsafeHandle<'h>,
+    )
+    {
+        if let Some(interned) =
+            self.interned_chars.as_array_of_cells().get(char as usize)
+        {
+            interned.set(Some(object));
+        }
+    }
+
+    /// The descriptor of the given custom kind.
+    ///
+    /// If the kind was not registered with [`HeapConfig::custom_kinds`],
+    /// this method returns [`None`].
+    #[inline]
+    pub fn custom_kind(&self, kind: CustomKind)
+        -> Option<&'static KindDescriptor>
+    {
+        self.custom_kinds.get(kind.0 as usize).copied()
+    }
+}
+
+/// Create an empty vector with the given capacity.
+///
+/// Unlike [`Vec::with_capacity_in`], this reports a lack of memory
+/// rather than aborting the process.
+fn try_vec_with_capacity<A, T>(allocator: A, capacity: usize)
+    -> Result<Vec<T, A>, AllocError>
+    where A: Allocator
+{
+    let mut vec = Vec::new_in(allocator);
+    vec.try_reserve_exact(capacity)
+        .map_err(|_| array_out_of_memory::<T>(capacity))?;
+    Ok(vec)
+}
+
+/// The error for failing to allocate an array of the given length.
+pub (super) fn array_out_of_memory<T>(len: usize) -> AllocError
+{
+    let layout = Layout::array::<T>(len);
+    AllocError::OutOfMemory(layout.unwrap_or_else(|_| Layout::new::<T>()))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    use core::alloc::Layout;
+    use core::ptr::NonNull;
+
+    /// Allocator that counts the number of allocations.
+    struct CountingAllocator
+    {
+        count: Cell<usize>,
+    }
+
+    unsafe impl Allocator for CountingAllocator
+    {
+        fn allocate(&self, layout: Layout)
+            -> Result<NonNull<[u8]>, core::alloc::AllocError>
+        {
+            self.count.set(self.count.get() + 1);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout)
+        {
+            Global.deallocate(ptr, layout);
+        }
+    }
+
+    #[test]
+    fn without_gc()
+    {
+        Heap::with_new(|heap| {
+            assert!(!heap.is_gc_prevented());
+            heap.without_gc(|| {
+                heap.without_gc(|| assert!(heap.is_gc_prevented()));
+                assert!(heap.is_gc_prevented());
+            });
+            assert!(!heap.is_gc_prevented());
+        });
+    }
+
+    #[test]
+    fn interned_symbol()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[handle]| {
+                for well_known in WellKnown::ALL {
+                    let interned = heap.interned_symbol(well_known);
+                    unsafe { handle.copy_from_unsafe_handle(interned) };
+                    handle.with_pin(|handle| {
+                        assert_eq!(handle.as_symbol(), Some(well_known.name()));
+                    });
+                }
+            });
+        });
+    }
+
+    #[test]
+    fn interned_variable_count()
+    {
+        let config = HeapConfig{
+            interned_variable_count: 2,
+            ..HeapConfig::default()
+        };
+        Heap::with_new_config(config, |heap| {
+            heap.with_new_array_scope(|[a, b]| {
+                for (de_bruijn, interned) in [(1, true), (2, false)] {
+                    heap.new_variable(a, DeBruijn(de_bruijn));
+                    heap.new_variable(b, DeBruijn(de_bruijn));
+                    assert_eq!(a.ptr_eq(b), interned);
+                }
+            });
+        });
+    }
+
+    #[test]
+    fn extra_interned_symbols()
+    {
+        let config = HeapConfig{
+            extra_interned_symbols: &[b"lambda", b"let"],
+            ..HeapConfig::default()
+        };
+        Heap::with_new_config(config, |heap| {
+            heap.with_new_array_scope(|[handle]| {
+                let names: [&[u8]; 2] = [b"lambda", b"let"];
+                for (index, name) in names.into_iter().enumerate() {
+                    let interned = heap.interned_extra_symbol(index).unwrap();
+                    unsafe { handle.copy_from_unsafe_handle(interned) };
+                    handle.with_pin(|handle| {
+                        assert_eq!(handle.as_symbol(), Some(name));
+                    });
+                }
+            });
+            assert!(heap.interned_extra_symbol(2).is_none());
+            heap.verify();
+        });
+    }
+
+    #[test]
+    fn memory_limit()
+    {
+        let config = HeapConfig{memory_limit: 1024, ..HeapConfig::default()};
+        Heap::with_new_config(config, |heap| {
+            heap.with_new_array_scope(|[symbol]| {
+                use TryNewSymbolError::Alloc;
+                loop {
+                    match heap.try_new_symbol(symbol, b"Pi") {
+                        Ok(()) => assert!(heap.allocated_bytes() <= 1024),
+                        Err(Alloc(AllocError::HeapFull(_))) => break,
+                        Err(err) => panic!("{:?}", err),
+                    }
+                }
+            });
+        });
+    }
+
+    #[test]
+    fn allocated_bytes()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[symbol]| {
+                let before = heap.allocated_bytes();
+                heap.new_symbol(symbol, b"Allocated").unwrap();
+                let size = symbol.with_pin(|symbol| symbol.object_size());
+                assert_eq!(heap.allocated_bytes(), before + size);
+            });
+        });
+    }
+
+    #[test]
+    fn user_data()
+    {
+        Heap::with_new_and_data(42u32, |heap| {
+            assert_eq!(heap.user_data::<u32>(), Some(&42));
+            assert_eq!(heap.user_data::<u64>(), None);
+        });
+        Heap::with_new(|heap| {
+            assert_eq!(heap.user_data::<u32>(), None);
+        });
+    }
+
+    #[test]
+    fn boxed_scope_pool()
+    {
+        let allocator = CountingAllocator{count: Cell::new(0)};
+        let config = HeapConfig{
+            allocator: &allocator,
+            ..HeapConfig::default()
+        };
+        Heap::with_new_config(config, |heap| {
+            heap.with_new_boxed_scope(8, |_| ());
+            let before = allocator.count.get();
+            heap.with_new_boxed_scope(4, |scope| {
+                heap.new_symbol(scope.get(3).unwrap(), b"Pi").unwrap();
+            });
+            heap.with_new_boxed_scope(8, |scope| {
+                let handle = scope.get(3).unwrap();
+                assert_eq!(handle.as_unsafe_handle(), heap.interned_null());
+            });
+            assert_eq!(allocator.count.get(), before + 1);
+        });
+    }
+
+    #[test]
+    fn custom_allocator()
+    {
+        let allocator = CountingAllocator{count: Cell::new(0)};
+        let config = HeapConfig{
+            allocator: &allocator,
+            ..HeapConfig::default()
+        };
+        Heap::with_new_config(config, |heap| {
+            let before = allocator.count.get();
+            heap.with_new_array_scope(|[symbol]| {
+                heap.new_symbol(symbol, b"Pi").unwrap();
+            });
+            assert_eq!(allocator.count.get(), before + 1);
+        });
+    }
+
+    #[test]
+    fn working_memory()
+    {
+        let allocator = CountingAllocator{count: Cell::new(0)};
+        let config = HeapConfig{
+            allocator: &allocator,
+            ..HeapConfig::default()
+        };
+        Heap::with_new_config(config, |heap| {
+            heap.with_new_array_scope(|[f, term]| {
+                heap.new_symbol(f, b"F").unwrap();
+                heap.new_application(term, f, [f, f]).unwrap();
+                let before = allocator.count.get();
+                heap.term_size(term);
+                assert!(allocator.count.get() > before);
+            });
+        });
+    }
 }
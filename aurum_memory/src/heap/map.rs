@@ -0,0 +1,229 @@
+use super::Heap;
+use super::HeapVec;
+
+use core::hash::Hash;
+use core::hash::Hasher;
+use core::mem;
+use core::ops::Index;
+
+/// Hash map whose memory comes from [`HeapConfig::allocator`].
+///
+/// The maps in [`alloc::collections`] cannot be given an allocator,
+/// so operations on terms that need working memory in the form of a map
+/// use this instead, keeping the promise of [`Heap::with_new_in`].
+/// Keys are often addresses of objects.
+///
+/// Entries are kept in a table with linear probing,
+/// which is at most three quarters full.
+/// Individual entries cannot be removed.
+///
+/// [`HeapConfig::allocator`]: `super::HeapConfig::allocator`
+pub (crate) struct HeapMap<'h, K, V>
+{
+    /// Power-of-two number of slots, or none at all.
+    slots: HeapVec<'h, Option<(K, V)>>,
+
+    /// The number of occupied slots.
+    len: usize,
+}
+
+impl<'h, K, V> HeapMap<'h, K, V>
+    where K: Eq + Hash
+{
+    /// Create an empty map that allocates from the given heap.
+    ///
+    /// No memory is allocated until the first entry is inserted.
+    pub fn new(heap: &Heap<'h>) -> Self
+    {
+        Self{slots: heap.new_vec(), len: 0}
+    }
+
+    /// The number of entries in the map.
+    #[inline]
+    pub fn len(&self) -> usize
+    {
+        self.len
+    }
+
+    /// Whether there is an entry for the given key.
+    pub fn contains_key(&self, key: &K) -> bool
+    {
+        self.get(key).is_some()
+    }
+
+    /// The value for the given key, if any.
+    pub fn get(&self, key: &K) -> Option<&V>
+    {
+        if self.slots.is_empty() {
+            return None;
+        }
+        let index = self.find(key);
+        self.slots[index].as_ref().map(|(_, value)| value)
+    }
+
+    /// Insert an entry, returning the value it replaced, if any.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V>
+    {
+        if (self.len + 1) * 4 > self.slots.len() * 3 {
+            self.grow();
+        }
+        let index = self.find(&key);
+        match &mut self.slots[index] {
+            Some((_, old)) => Some(mem::replace(old, value)),
+            slot @ None => {
+                *slot = Some((key, value));
+                self.len += 1;
+                None
+            },
+        }
+    }
+
+    /// The value for the given key,
+    /// inserting the result of `default` if there is none.
+    pub fn get_or_insert_with(&mut self, key: K, default: impl FnOnce() -> V)
+        -> &mut V
+    {
+        if (self.len + 1) * 4 > self.slots.len() * 3 {
+            self.grow();
+        }
+        let index = self.find(&key);
+        let slot = &mut self.slots[index];
+        if slot.is_none() {
+            *slot = Some((key, default()));
+            self.len += 1;
+        }
+        &mut slot.as_mut().unwrap().1
+    }
+
+    /// Remove all entries, keeping the table for reuse.
+    pub fn clear(&mut self)
+    {
+        for slot in self.slots.iter_mut() {
+            *slot = None;
+        }
+        self.len = 0;
+    }
+
+    /// The index of the slot with the given key,
+    /// or of the empty slot where it would go.
+    ///
+    /// There must be at least one empty slot.
+    fn find(&self, key: &K) -> usize
+    {
+        let mut hasher = FxHasher(0);
+        key.hash(&mut hasher);
+
+        // The high bits of the hash are the best mixed.
+        let bits = self.slots.len().trailing_zeros();
+        let mask = self.slots.len() - 1;
+        let mut index = (hasher.finish() >> (64 - bits)) as usize;
+        loop {
+            match &self.slots[index] {
+                Some((other, _)) if other != key =>
+                    index = (index + 1) & mask,
+                _ => return index,
+            }
+        }
+    }
+
+    /// Double the number of slots and move the entries over.
+    fn grow(&mut self)
+    {
+        let capacity = (self.slots.len() * 2).max(8);
+        let mut slots = HeapVec::new_in(*self.slots.allocator());
+        slots.resize_with(capacity, || None);
+        let old = mem::replace(&mut self.slots, slots);
+        for (key, value) in old.into_iter().flatten() {
+            let index = self.find(&key);
+            self.slots[index] = Some((key, value));
+        }
+    }
+}
+
+impl<'h, K, V> Index<&K> for HeapMap<'h, K, V>
+    where K: Eq + Hash
+{
+    type Output = V;
+
+    fn index(&self, key: &K) -> &V
+    {
+        self.get(key).expect("Key is in the map")
+    }
+}
+
+/// The hash function used by rustc, which is fast for small keys.
+///
+/// It is not resistant to collision attacks,
+/// but keys of heap maps are not chosen by untrusted programs.
+struct FxHasher(u64);
+
+impl FxHasher
+{
+    #[inline]
+    fn add(&mut self, word: u64)
+    {
+        const SEED: u64 = 0x51_7C_C1_B7_27_22_0A_95;
+        self.0 = (self.0.rotate_left(5) ^ word).wrapping_mul(SEED);
+    }
+}
+
+impl Hasher for FxHasher
+{
+    fn write(&mut self, bytes: &[u8])
+    {
+        for &byte in bytes {
+            self.add(byte as u64);
+        }
+    }
+
+    fn write_u32(&mut self, word: u32)
+    {
+        self.add(word as u64);
+    }
+
+    fn write_u64(&mut self, word: u64)
+    {
+        self.add(word);
+    }
+
+    fn write_usize(&mut self, word: usize)
+    {
+        self.add(word as u64);
+    }
+
+    fn finish(&self) -> u64
+    {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn insert_get()
+    {
+        Heap::with_new(|heap| {
+            let mut map = HeapMap::new(heap);
+            assert_eq!(map.get(&8), None);
+            for i in 0 .. 1000usize {
+                assert_eq!(map.insert(i * 8, i), None);
+            }
+            assert_eq!(map.len(), 1000);
+            for i in 0 .. 1000usize {
+                assert_eq!(map[&(i * 8)], i);
+            }
+            assert_eq!(map.insert(8, 7), Some(1));
+            assert!(!map.contains_key(&9));
+
+            map.clear();
+            assert_eq!(map.len(), 0);
+            assert!(!map.contains_key(&8));
+            *map.get_or_insert_with(8, || 1) += 1;
+            *map.get_or_insert_with(8, || 1) += 1;
+            assert_eq!(map[&8], 3);
+        });
+    }
+}
@@ -0,0 +1,224 @@
+use super::Heap;
+use super::ScopedHandle;
+use super::UnsafeHandle;
+use crate::object::terms_equal;
+
+use core::cell::Cell;
+
+/// Entry in the memo table of a heap.
+pub (super) struct MemoEntry<'h>
+{
+    /// The [structural hash][`Heap::structural_hash`] of the key.
+    pub hash: u64,
+    pub key: Cell<UnsafeHandle<'h>>,
+    pub value: Cell<UnsafeHandle<'h>>,
+
+    /// The clock of the memo table when the entry was last used.
+    pub last_used: Cell<u64>,
+}
+
+/// Methods for memoizing results of computations on terms.
+///
+/// The heap keeps a table that maps terms to terms.
+/// Keys are compared structurally,
+/// so a result computed for one term is found for any equal term,
+/// even if it consists of different objects.
+/// This is useful for caching the results of expensive transformations
+/// of terms that occur many times.
+///
+/// The entries are strong: objects in the table stay alive
+/// until their entry is evicted or the table is cleared.
+/// They cannot be weak, because a weak entry must be cleared
+/// when its key or value is destroyed, and only a garbage collector
+/// destroys objects; this heap does not have one yet.
+/// To bound the size of the table regardless,
+/// it holds at most [`HeapConfig::memo_capacity`] entries,
+/// evicting the least recently used entry when it is full.
+///
+/// [`HeapConfig::memo_capacity`]: `super::HeapConfig::memo_capacity`
+impl<'h> Heap<'h>
+{
+    /// Look up the value for the given key in the memo table.
+    ///
+    /// If there is an entry with an equal key,
+    /// `into` is made to refer to its value and this method returns true.
+    /// Otherwise, `into` is not modified and this method returns false.
+    pub fn memo_get<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        key: ScopedHandle<'h, 's>,
+    ) -> bool
+    {
+        let hash = self.structural_hash(key);
+
+        // SAFETY: Comparing terms does not access the memo table.
+        let memo = unsafe { self.memo.borrow() };
+        let value = find(self, &memo, hash, key).map(|index| {
+            memo[index].last_used.set(tick(self));
+            memo[index].value.get()
+        });
+
+        match value {
+            Some(value) => {
+                // SAFETY: Objects in the memo table are not destroyed.
+                unsafe { into.copy_from_unsafe_handle(value) };
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Insert an entry into the memo table.
+    ///
+    /// If there is already an entry with an equal key,
+    /// its value is replaced.
+    /// Otherwise, if the table is full,
+    /// the least recently used entry is evicted first.
+    pub fn memo_insert<'s>(
+        &self,
+        key: ScopedHandle<'h, 's>,
+        value: ScopedHandle<'h, 's>,
+    )
+    {
+        let hash = self.structural_hash(key);
+
+        // SAFETY: Comparing terms does not access the memo table.
+        let mut memo = unsafe { self.memo.borrow_mut() };
+        match find(self, &memo, hash, key) {
+            Some(index) => {
+                memo[index].value.set(value.as_unsafe_handle());
+                memo[index].last_used.set(tick(self));
+            },
+            None => {
+                if self.memo_capacity == 0 {
+                    return;
+                }
+                if memo.len() >= self.memo_capacity {
+                    let oldest = (0 .. memo.len())
+                        .min_by_key(|&i| memo[i].last_used.get())
+                        .unwrap();
+                    memo.remove(oldest);
+                }
+                let index = memo.partition_point(|entry| entry.hash <= hash);
+                let entry = MemoEntry{
+                    hash,
+                    key: Cell::new(key.as_unsafe_handle()),
+                    value: Cell::new(value.as_unsafe_handle()),
+                    last_used: Cell::new(tick(self)),
+                };
+                memo.insert(index, entry);
+            },
+        }
+    }
+
+    /// The number of entries in the memo table.
+    pub fn memo_len(&self) -> usize
+    {
+        // SAFETY: We only borrow the table for a short period of time.
+        unsafe { self.memo.borrow() }.len()
+    }
+
+    /// Remove all entries from the memo table.
+    pub fn memo_clear(&self)
+    {
+        // SAFETY: We only borrow the table for a short period of time.
+        unsafe { self.memo.borrow_mut() }.clear();
+    }
+}
+
+/// Advance the clock of the memo table, returning its old value.
+fn tick(heap: &Heap) -> u64
+{
+    let now = heap.memo_clock.get();
+    heap.memo_clock.set(now + 1);
+    now
+}
+
+/// Find the index of the entry with a key equal to the given key.
+fn find<'h>(
+    heap: &Heap<'h>,
+    memo: &[MemoEntry<'h>],
+    hash: u64,
+    key: ScopedHandle<'h, '_>,
+) -> Option<usize>
+{
+    let start = memo.partition_point(|entry| entry.hash < hash);
+    memo[start ..].iter()
+        .take_while(|entry| entry.hash == hash)
+        .position(|entry| {
+            // SAFETY: Objects in the memo table are not destroyed.
+            let other = unsafe { ScopedHandle::new(&entry.key) };
+            terms_equal(heap, key, other)
+        })
+        .map(|offset| start + offset)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::heap::HeapConfig;
+    use crate::term;
+
+    #[test]
+    fn memo()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[key, value, other, result]| {
+                term!(heap, key, (F (G #0) A));
+                term!(heap, value, B);
+                heap.memo_insert(key, value);
+
+                term!(heap, other, (F (G #0) A));
+                assert!(heap.memo_get(result, other));
+                assert!(result.ptr_eq(value));
+
+                term!(heap, other, (F (G #1) A));
+                assert!(!heap.memo_get(result, other));
+
+                heap.memo_insert(other, other);
+                heap.memo_insert(key, key);
+                assert_eq!(heap.memo_len(), 2);
+                assert!(heap.memo_get(result, key));
+                assert!(result.ptr_eq(key));
+                heap.verify();
+
+                heap.memo_clear();
+                assert!(!heap.memo_get(result, key));
+            });
+        });
+    }
+
+    #[test]
+    fn memo_capacity()
+    {
+        let config = HeapConfig{memo_capacity: 2, ..HeapConfig::default()};
+        Heap::with_new_config(config, |heap| {
+            heap.with_new_array_scope(|[a, b, c, result]| {
+                term!(heap, a, A);
+                term!(heap, b, B);
+                term!(heap, c, C);
+                heap.memo_insert(a, a);
+                heap.memo_insert(b, b);
+                assert!(heap.memo_get(result, a));
+
+                // B is the least recently used entry.
+                heap.memo_insert(c, c);
+                assert_eq!(heap.memo_len(), 2);
+                assert!(heap.memo_get(result, a));
+                assert!(!heap.memo_get(result, b));
+                assert!(heap.memo_get(result, c));
+            });
+        });
+
+        let config = HeapConfig{memo_capacity: 0, ..HeapConfig::default()};
+        Heap::with_new_config(config, |heap| {
+            heap.with_new_array_scope(|[a, result]| {
+                term!(heap, a, A);
+                heap.memo_insert(a, a);
+                assert_eq!(heap.memo_len(), 0);
+                assert!(!heap.memo_get(result, a));
+            });
+        });
+    }
+}
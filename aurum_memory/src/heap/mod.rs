@@ -14,9 +14,18 @@
 //! | [`ScopedHandle`] | Object won’t be destroyed | Safe copying of parts of the object   |
 //! | [`PinnedHandle`] | Object won’t be relocated | Safe borrowing of parts of the object |
 
+pub use self::alloc::*;
+pub use self::buffer::*;
+pub use self::dominators::*;
 pub use self::handle::*;
 pub use self::heap::*;
+pub (crate) use self::map::*;
+use self::memo::*;
 pub use self::scope::*;
+pub use self::snapshot::*;
+pub use self::trace::*;
+#[cfg(feature = "derive")]
+pub use aurum_memory_derive::Trace;
 
 // The order of these declarations influences
 // the order of the Heap impls in in rustdoc.
@@ -24,4 +33,12 @@ mod heap;
 mod scope;
 mod alloc;
 
+mod buffer;
+mod dominators;
+mod fork;
 mod handle;
+mod map;
+mod memo;
+mod snapshot;
+mod trace;
+mod verify;
@@ -1,13 +1,18 @@
+use super::AllocError;
 use super::Heap;
+use super::ScopeBuffer;
+use super::heap::array_out_of_memory;
 use super::handle::ScopedHandle;
 use super::handle::UnsafeHandle;
 
-use alloc::vec;
+use alloc::vec::Vec;
 use core::cell::Cell;
 use core::iter::TrustedLen;
 use core::mem::MaybeUninit;
 use core::mem::transmute;
 use scopeguard::defer;
+use scopeguard::ScopeGuard;
+use scopeguard::guard;
 
 impl<'h> Heap<'h>
 {
@@ -18,9 +23,8 @@ impl<'h> Heap<'h>
     fn with_scope<F, R>(&self, scope: &[Cell<UnsafeHandle<'h>>], then: F) -> R
         where F: FnOnce(&Scope<'h>) -> R
     {
-        // SAFETY: We only borrow these for short periods of time.
-        unsafe { self.scopes.borrow_mut() }.push(scope);
-        defer! { unsafe { self.scopes.borrow_mut() }.pop(); }
+        let index = self.register_scope(scope);
+        defer! { self.unregister_scope(index); }
 
         // SAFETY: The scope is registerd with the heap.
         let scope = unsafe { Scope::new(scope) };
@@ -28,6 +32,24 @@ impl<'h> Heap<'h>
         then(scope)
     }
 
+    /// Similar to [`with_scope`][`Self::with_scope`],
+    /// but return an error if the scope cannot be registered.
+    fn try_with_scope<F, R>(
+        &self,
+        scope: &[Cell<UnsafeHandle<'h>>],
+        then: F,
+    ) -> Result<R, AllocError>
+        where F: FnOnce(&Scope<'h>) -> R
+    {
+        let index = self.try_register_scope(scope)?;
+        defer! { self.unregister_scope(index); }
+
+        // SAFETY: The scope is registerd with the heap.
+        let scope = unsafe { Scope::new(scope) };
+
+        Ok(then(scope))
+    }
+
     /// Create a new scope on the stack and pass it to the given function.
     ///
     /// The scope is destroyed as soon as the given function returns or panics.
@@ -59,22 +81,28 @@ impl<'h> Heap<'h>
         let scope = Cell::new([self.interned_null(); N]);
         let scope = scope.as_array_of_cells();
 
-        self.with_scope(scope, |scope| {
-
-            let mut scoped_handles = MaybeUninit::uninit_array::<N>();
-
-            for (i, s) in scoped_handles.iter_mut().enumerate() {
-                // SAFETY: We created a scope with N handle slots.
-                s.write(unsafe { scope.get_unchecked(i) });
-            }
-
-            // SAFETY: We initialized all N elements of the array.
-            let scoped_handles = unsafe {
-                MaybeUninit::array_assume_init(scoped_handles)
-            };
+        // SAFETY: We created a scope with N handle slots.
+        self.with_scope(scope, |scope| then(unsafe { array_handles(scope) }))
+    }
 
-            then(scoped_handles)
+    /// Similar to [`with_new_array_scope`][`Self::with_new_array_scope`],
+    /// but return an error if the scope cannot be registered.
+    ///
+    /// The handles themselves live on the stack,
+    /// but the heap keeps track of scopes in memory
+    /// obtained from [`HeapConfig::allocator`].
+    ///
+    /// [`HeapConfig::allocator`]: `super::HeapConfig::allocator`
+    pub fn try_with_new_array_scope<F, R, const N: usize>(&self, then: F)
+        -> Result<R, AllocError>
+        where F: for<'s> FnOnce([ScopedHandle<'h, 's>; N]) -> R
+    {
+        let scope = Cell::new([self.interned_null(); N]);
+        let scope = scope.as_array_of_cells();
 
+        // SAFETY: We created a scope with N handle slots.
+        self.try_with_scope(scope, |scope| {
+            then(unsafe { array_handles(scope) })
         })
     }
 
@@ -82,12 +110,236 @@ impl<'h> Heap<'h>
     ///
     /// The scope is destroyed as soon as the given function returns or panics.
     /// For more information about scopes, see [`Scope`].
+    ///
+    /// The memory for the scope is not freed once it is destroyed.
+    /// Instead it is kept around for use by subsequent calls,
+    /// so that creating boxed scopes in a loop or in a recursive function
+    /// does not allocate memory each time.
     pub fn with_new_boxed_scope<F, R>(&self, size: usize, then: F) -> R
         where F: FnOnce(&Scope<'h>) -> R
     {
-        let scope = vec![Cell::new(self.interned_null()); size];
+        self.with_pooled_scope(
+            |scope| scope.resize(size, Cell::new(self.interned_null())),
+            then,
+        )
+    }
+
+    /// Similar to [`with_new_boxed_scope`][`Self::with_new_boxed_scope`],
+    /// but return an error if memory for the scope cannot be allocated.
+    pub fn try_with_new_boxed_scope<F, R>(&self, size: usize, then: F)
+        -> Result<R, AllocError>
+        where F: FnOnce(&Scope<'h>) -> R
+    {
+        let mut scope = self.take_scope_buffer();
+        scope.try_reserve(size)
+            .map_err(|_| array_out_of_memory::<Cell<UnsafeHandle>>(size))?;
+        scope.resize(size, Cell::new(self.interned_null()));
+        self.try_with_scope(&scope, then)
+    }
+
+    /// Create a new scope on the heap and pass it to the given function.
+    ///
+    /// The scope has one handle for each of the given handles,
+    /// and each of its handles initially refers to
+    /// the same object as the corresponding given handle.
+    /// Otherwise this method behaves like
+    /// [`with_new_boxed_scope`][`Self::with_new_boxed_scope`].
+    pub fn with_new_scope_from_iter<'s, I, F, R>(&self, handles: I, then: F)
+        -> R
+        where 'h: 's,
+              I: IntoIterator<Item=ScopedHandle<'h, 's>>,
+              F: FnOnce(&Scope<'h>) -> R
+    {
+        self.with_pooled_scope(
+            |scope| scope.extend(
+                handles.into_iter()
+                    .map(|h| Cell::new(h.as_unsafe_handle()))
+            ),
+            then,
+        )
+    }
+
+    /// Shared implementation of
+    /// [`with_new_boxed_scope`][`Self::with_new_boxed_scope`] and
+    /// [`with_new_scope_from_iter`][`Self::with_new_scope_from_iter`].
+    ///
+    /// The `init` function is given an empty buffer to fill with handles.
+    /// It must not create objects, as the scope is not yet registered.
+    fn with_pooled_scope<F, R>(
+        &self,
+        init: impl FnOnce(&mut ScopeBuffer<'h>),
+        then: F,
+    ) -> R
+        where F: FnOnce(&Scope<'h>) -> R
+    {
+        let mut scope = self.take_scope_buffer();
+        init(&mut scope);
         self.with_scope(&scope, then)
     }
+
+    /// Create a new scope to which handles can be added and removed
+    /// while it is in use, and pass it to the given function.
+    ///
+    /// The scope is destroyed as soon as the given function returns or panics.
+    /// See [`GrowableScope`] for more information.
+    pub (crate) fn with_new_growable_scope<F, R>(&self, then: F) -> R
+        where F: for<'g> FnOnce(GrowableScope<'g, 'h>) -> R
+    {
+        let mut buffer = self.take_scope_buffer();
+
+        // Like `with_scope`, but we remember where the scope is registered,
+        // so that we can register it again when the buffer moves.
+        let index = self.register_scope(&buffer[..]);
+        defer! { self.unregister_scope(index); }
+
+        then(GrowableScope{heap: self, index, buffer: &mut buffer})
+    }
+
+    /// Push the scope onto the stack of scopes and return its index.
+    fn register_scope(&self, scope: &[Cell<UnsafeHandle<'h>>]) -> usize
+    {
+        // SAFETY: We only borrow these for short periods of time.
+        let mut scopes = unsafe { self.scopes.borrow_mut() };
+        scopes.push(scope);
+        scopes.len() - 1
+    }
+
+    /// Similar to [`register_scope`][`Self::register_scope`],
+    /// but return an error if the stack of scopes cannot grow.
+    fn try_register_scope(&self, scope: &[Cell<UnsafeHandle<'h>>])
+        -> Result<usize, AllocError>
+    {
+        // SAFETY: We only borrow these for short periods of time.
+        let mut scopes = unsafe { self.scopes.borrow_mut() };
+        scopes.try_reserve(1).map_err(|_| {
+            array_out_of_memory::<*const [Cell<UnsafeHandle>]>(1)
+        })?;
+        scopes.push(scope);
+        Ok(scopes.len() - 1)
+    }
+
+    /// Pop the scope at the given index off the stack of scopes.
+    ///
+    /// Scopes must be unregistered in the reverse order of registration.
+    /// In debug mode, this is checked.
+    fn unregister_scope(&self, index: usize)
+    {
+        // SAFETY: We only borrow these for short periods of time.
+        let mut scopes = unsafe { self.scopes.borrow_mut() };
+        debug_assert_eq!(
+            index + 1, scopes.len(),
+            "Scopes unregistered out of order",
+        );
+        scopes.pop();
+    }
+
+    /// The number of scopes currently registered with the heap.
+    ///
+    /// Scopes are registered and unregistered in stack order,
+    /// so code that creates scopes leaves this number as it found it.
+    /// Embedders can assert this around callbacks, for example
+    /// with `debug_assert_eq!`, to catch scopes that escape
+    /// through unsafe code.
+    pub fn scope_depth(&self) -> usize
+    {
+        // SAFETY: We only borrow these for short periods of time.
+        unsafe { self.scopes.borrow() }.len()
+    }
+
+    /// Obtain an empty buffer for a scope from the pool.
+    ///
+    /// The buffer is returned to the pool when the guard is dropped.
+    fn take_scope_buffer(&self)
+        -> ScopeGuard<ScopeBuffer<'h>, impl FnOnce(ScopeBuffer<'h>) + '_>
+    {
+        // SAFETY: We only borrow these for short periods of time.
+        let scope = unsafe { self.scope_pool.borrow_mut() }.pop();
+        let mut scope = scope.unwrap_or_else(|| Vec::new_in(self.allocator));
+
+        scope.clear();
+
+        guard(scope, |scope| {
+            unsafe { self.scope_pool.borrow_mut() }.push(scope);
+        })
+    }
+}
+
+/// The handles of a scope with N handles, as an array.
+///
+/// # Safety
+///
+/// The scope must have at least N handles.
+unsafe fn array_handles<'h, 's, const N: usize>(scope: &'s Scope<'h>)
+    -> [ScopedHandle<'h, 's>; N]
+{
+    let mut scoped_handles = MaybeUninit::uninit_array::<N>();
+
+    for (i, s) in scoped_handles.iter_mut().enumerate() {
+        s.write(scope.get_unchecked(i));
+    }
+
+    // SAFETY: We initialized all N elements of the array.
+    MaybeUninit::array_assume_init(scoped_handles)
+}
+
+/// Scope to which handles can be added and removed while it is in use.
+///
+/// Adding a handle may move the existing handles in memory,
+/// so handles cannot be borrowed across calls to [`push`][`Self::push`].
+/// The borrow checker enforces this, as `push` takes `&mut self`.
+pub (crate) struct GrowableScope<'g, 'h>
+{
+    heap: &'g Heap<'h>,
+
+    /// Where in the stack of scopes this scope is registered.
+    index: usize,
+
+    buffer: &'g mut ScopeBuffer<'h>,
+}
+
+impl<'g, 'h> GrowableScope<'g, 'h>
+{
+    /// The heap with which this scope is registered.
+    #[inline]
+    pub fn heap(&self) -> &'g Heap<'h>
+    {
+        self.heap
+    }
+
+    /// Add a handle to the end of the scope and return its index.
+    ///
+    /// The new handle initially refers to the interned null symbol.
+    pub fn push(&mut self) -> usize
+    {
+        let index = self.buffer.len();
+        self.buffer.push(Cell::new(self.heap.interned_null()));
+
+        // The buffer may have moved, so register it again.
+        // SAFETY: We only borrow these for short periods of time.
+        let mut scopes = unsafe { self.heap.scopes.borrow_mut() };
+        scopes[self.index] = &self.buffer[..];
+
+        index
+    }
+
+    /// Remove the handle at the end of the scope, if any.
+    pub fn pop(&mut self)
+    {
+        self.buffer.pop();
+
+        // The scope has fewer handles now, so register it again.
+        // SAFETY: We only borrow these for short periods of time.
+        let mut scopes = unsafe { self.heap.scopes.borrow_mut() };
+        scopes[self.index] = &self.buffer[..];
+    }
+
+    /// The handles that are currently in the scope.
+    #[inline]
+    pub fn as_scope(&self) -> &Scope<'h>
+    {
+        // SAFETY: The scope is registered with the heap.
+        unsafe { Scope::new(self.buffer) }
+    }
 }
 
 /// Collection of handles to objects that will not be destroyed.
@@ -135,6 +387,20 @@ impl<'h> Scope<'h>
         transmute(handles)
     }
 
+    /// The number of handles in this scope.
+    #[inline]
+    pub fn len(&self) -> usize
+    {
+        self.handles.len()
+    }
+
+    /// Whether this scope has no handles.
+    #[inline]
+    pub fn is_empty(&self) -> bool
+    {
+        self.handles.is_empty()
+    }
+
     /// Retrieve the handle at the given index.
     ///
     /// If the index is out of bounds, this method returns [`None`].
@@ -148,6 +414,26 @@ impl<'h> Scope<'h>
         }
     }
 
+    /// Retrieve `N` consecutive handles, starting at the given index.
+    ///
+    /// If any of the indices is out of bounds, this method returns [`None`].
+    /// This checks the bounds once, rather than once for each handle.
+    #[inline]
+    pub fn get_array<'s, const N: usize>(&'s self, start: usize)
+        -> Option<[ScopedHandle<'h, 's>; N]>
+    {
+        let handles = self.handles.get(start ..)?.get(.. N)?;
+
+        let mut scoped_handles = MaybeUninit::uninit_array::<N>();
+        for (s, h) in scoped_handles.iter_mut().zip(handles) {
+            // SAFETY: The handle is part of this scope.
+            s.write(unsafe { ScopedHandle::new(h) });
+        }
+
+        // SAFETY: There are N handles, so all N elements were initialized.
+        Some(unsafe { MaybeUninit::array_assume_init(scoped_handles) })
+    }
+
     /// Retrieve the handle at the given index.
     ///
     /// # Safety
@@ -162,6 +448,21 @@ impl<'h> Scope<'h>
         ScopedHandle::new(handle)
     }
 
+    /// Modify the handles in this scope to refer to the same objects
+    /// as the corresponding handles in the other scope.
+    ///
+    /// # Panics
+    ///
+    /// If the scopes have different lengths, this method panics.
+    #[inline]
+    pub fn copy_from(&self, other: &Scope<'h>)
+    {
+        assert_eq!(self.len(), other.len(), "Scopes have different lengths");
+        for (to, from) in self.handles.iter().zip(&other.handles) {
+            to.set(from.get());
+        }
+    }
+
     /// Iterator over the handles in this scope.
     #[inline]
     pub fn iter<'s>(&'s self)
@@ -173,3 +474,103 @@ impl<'h> Scope<'h>
         self.handles.iter().map(|h| unsafe { ScopedHandle::new(h) })
     }
 }
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::object::DeBruijn;
+
+    #[test]
+    fn swap_and_copy_from()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_boxed_scope(2, |from| {
+            heap.with_new_boxed_scope(2, |to| {
+                let [a, b] = [0, 1].map(|i| from.get(i).unwrap());
+                heap.new_symbol(a, b"A").unwrap();
+                heap.new_symbol(b, b"B").unwrap();
+                let (a_before, b_before) =
+                    (a.as_unsafe_handle(), b.as_unsafe_handle());
+
+                a.swap(b);
+                assert_eq!(a.as_unsafe_handle(), b_before);
+                assert_eq!(b.as_unsafe_handle(), a_before);
+
+                to.copy_from(from);
+                assert!(
+                    Iterator::eq(
+                        to.iter().map(|sh| sh.as_unsafe_handle()),
+                        from.iter().map(|sh| sh.as_unsafe_handle()),
+                    )
+                );
+            }); });
+        });
+    }
+
+    #[test]
+    fn get_array()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_boxed_scope(4, |scope| {
+                for (i, handle) in scope.iter().enumerate() {
+                    heap.new_variable(handle, DeBruijn(i as u32));
+                }
+                let [a, b] = scope.get_array(2).unwrap();
+                assert_eq!(a.as_variable(), Some(DeBruijn(2)));
+                assert_eq!(b.as_variable(), Some(DeBruijn(3)));
+                assert!(scope.get_array::<2>(3).is_none());
+                assert!(scope.get_array::<0>(4).is_some());
+                assert!(scope.get_array::<0>(5).is_none());
+            });
+        });
+    }
+
+    #[test]
+    fn scope_depth()
+    {
+        Heap::with_new(|heap| {
+            let depth = heap.scope_depth();
+            heap.with_new_array_scope(|[_]| {
+                assert_eq!(heap.scope_depth(), depth + 1);
+                heap.with_new_growable_scope(|mut scope| {
+                    scope.push();
+                    assert_eq!(heap.scope_depth(), depth + 2);
+                });
+                assert_eq!(heap.scope_depth(), depth + 1);
+            });
+            assert_eq!(heap.scope_depth(), depth);
+        });
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Scopes unregistered out of order")]
+    fn unregister_scope_out_of_order()
+    {
+        Heap::with_new(|heap| {
+            let outer = heap.register_scope(&[]);
+            heap.register_scope(&[]);
+            heap.unregister_scope(outer);
+        });
+    }
+
+    #[test]
+    fn with_new_scope_from_iter()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[a, b]| {
+                heap.new_symbol(a, b"A").unwrap();
+                heap.new_variable(b, DeBruijn(100));
+                heap.with_new_scope_from_iter([b, a, b], |scope| {
+                    assert!(
+                        Iterator::eq(
+                            scope.iter().map(|sh| sh.as_unsafe_handle()),
+                            [b, a, b].iter().map(|sh| sh.as_unsafe_handle()),
+                        )
+                    );
+                });
+            });
+        });
+    }
+}
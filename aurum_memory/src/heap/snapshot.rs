@@ -0,0 +1,164 @@
+use super::Heap;
+use super::ScopedHandle;
+use crate::object::Kind;
+
+use alloc::collections::BTreeMap;
+use core::cell::Cell;
+
+/// The reachable objects of a heap at some point in time,
+/// as returned by [`Heap::snapshot`].
+///
+/// Compare two snapshots with [`diff`][`Self::diff`]
+/// to find out which objects became reachable or unreachable.
+/// If objects keep piling up between snapshots,
+/// some scope or memo table entry is probably holding on to them.
+#[derive(Clone, Debug)]
+pub struct HeapSnapshot
+{
+    /// The kind and size of every reachable object, by address.
+    objects: BTreeMap<usize, (Kind, usize)>,
+}
+
+/// Differences between two [`HeapSnapshot`]s, by kind of object.
+///
+/// Kinds that did not change are absent.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SnapshotDiff
+{
+    #[allow(missing_docs)]
+    pub kinds: BTreeMap<Kind, KindDiff>,
+}
+
+/// Differences between two [`HeapSnapshot`]s for one kind of object.
+///
+/// Sizes are in bytes, as returned by [`PinnedHandle::object_size`].
+///
+/// [`PinnedHandle::object_size`]: `super::PinnedHandle::object_size`
+#[allow(missing_docs)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct KindDiff
+{
+    pub added_objects: usize,
+    pub added_bytes: usize,
+    pub removed_objects: usize,
+    pub removed_bytes: usize,
+}
+
+/// Methods for taking snapshots of the heap.
+impl<'h> Heap<'h>
+{
+    /// Record which objects are currently reachable.
+    ///
+    /// Objects are reachable from scopes, from the memo table,
+    /// and from the interned objects, as in [`verify`][`Self::verify`].
+    /// This visits every reachable object, so it is slow on large heaps.
+    pub fn snapshot(&self) -> HeapSnapshot
+    {
+        let mut objects = BTreeMap::new();
+        let mut stack = self.roots();
+        while let Some(handle) = stack.pop() {
+            let address = handle.as_ptr() as usize;
+            if objects.contains_key(&address) {
+                continue;
+            }
+
+            let cell = Cell::new(handle);
+            // SAFETY: Objects reachable from roots are not destroyed.
+            let object = unsafe { ScopedHandle::new(&cell) };
+            object.with_pin(|object| {
+                let entry = (object.header().kind, object.object_size());
+                objects.insert(address, entry);
+                object.visit_children(|child| {
+                    stack.push(child.as_unsafe_handle());
+                });
+            });
+        }
+        HeapSnapshot{objects}
+    }
+}
+
+impl HeapSnapshot
+{
+    /// The number of reachable objects.
+    pub fn num_objects(&self) -> usize
+    {
+        self.objects.len()
+    }
+
+    /// The number of bytes occupied by reachable objects.
+    pub fn reachable_bytes(&self) -> usize
+    {
+        self.objects.values().map(|&(_, size)| size).sum()
+    }
+
+    /// Compare this snapshot to a later one.
+    ///
+    /// Objects that are only in the later snapshot are added,
+    /// and objects that are only in this snapshot are removed.
+    pub fn diff(&self, later: &HeapSnapshot) -> SnapshotDiff
+    {
+        let mut diff = SnapshotDiff::default();
+        for (address, &(kind, size)) in &later.objects {
+            if !self.objects.contains_key(address) {
+                let kind = diff.kinds.entry(kind).or_default();
+                kind.added_objects += 1;
+                kind.added_bytes += size;
+            }
+        }
+        for (address, &(kind, size)) in &self.objects {
+            if !later.objects.contains_key(address) {
+                let kind = diff.kinds.entry(kind).or_default();
+                kind.removed_objects += 1;
+                kind.removed_bytes += size;
+            }
+        }
+        diff
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::object::HEADER_SIZE;
+    use crate::term;
+
+    use alloc::vec::Vec;
+
+    #[test]
+    fn diff()
+    {
+        Heap::with_new(|heap| {
+            let before = heap.snapshot();
+            let during = heap.with_new_array_scope(|[term]| {
+                term!(heap, term, (F #0 #0));
+                heap.snapshot()
+            });
+            let after = heap.snapshot();
+
+            // The variable is interned, so it was already reachable.
+            let added = before.diff(&during);
+            let application = KindDiff{
+                added_objects: 1,
+                added_bytes: HEADER_SIZE + 3 * 8,
+                ..KindDiff::default()
+            };
+            let symbol = KindDiff{
+                added_objects: 1,
+                added_bytes: HEADER_SIZE,
+                ..KindDiff::default()
+            };
+            assert_eq!(
+                added.kinds.into_iter().collect::<Vec<_>>(),
+                [(Kind::SmallSymbol, symbol), (Kind::Application, application)],
+            );
+            assert_eq!(during.num_objects(), before.num_objects() + 2);
+
+            // Once the scope is gone, so are the objects.
+            let removed = during.diff(&after);
+            assert_eq!(removed.kinds[&Kind::Application].removed_objects, 1);
+            assert_eq!(after.reachable_bytes(), before.reachable_bytes());
+            assert_eq!(before.diff(&after), SnapshotDiff::default());
+        });
+    }
+}
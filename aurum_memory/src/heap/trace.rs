@@ -0,0 +1,209 @@
+use super::Heap;
+use super::UnsafeHandle;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cell::Cell;
+use core::mem::transmute;
+use scopeguard::defer;
+
+/// Receives the handles found by [`Trace::trace`].
+///
+/// This is implemented for closures,
+/// so a tracer can be written as `&mut |handle| ...`.
+pub trait Tracer<'h>
+{
+    /// Called for each handle.
+    fn handle(&mut self, handle: &Cell<UnsafeHandle<'h>>);
+}
+
+impl<'h, F> Tracer<'h> for F
+    where F: FnMut(&Cell<UnsafeHandle<'h>>)
+{
+    fn handle(&mut self, handle: &Cell<UnsafeHandle<'h>>)
+    {
+        self(handle)
+    }
+}
+
+/// Data structures that contain handles.
+///
+/// Scopes are the usual place to keep handles,
+/// but embedders may want to keep handles in their own data structures.
+/// Such a data structure can be registered as a root
+/// with [`Heap::with_root`], provided it implements this trait,
+/// so that the objects it refers to are considered reachable.
+/// Handles are stored in the data structure
+/// as `Cell<UnsafeHandle<'h>>`, just like in a scope.
+///
+/// With the `derive` feature, this trait can be derived.
+/// The derived implementation traces every field in turn,
+/// except for fields marked `#[trace(skip)]`,
+/// which need not implement this trait.
+pub trait Trace<'h>
+{
+    /// Pass every handle in the data structure to the tracer.
+    fn trace(&self, tracer: &mut dyn Tracer<'h>);
+}
+
+impl<'h> Trace<'h> for Cell<UnsafeHandle<'h>>
+{
+    #[inline]
+    fn trace(&self, tracer: &mut dyn Tracer<'h>)
+    {
+        tracer.handle(self);
+    }
+}
+
+impl<'h, T> Trace<'h> for Option<T>
+    where T: Trace<'h>
+{
+    fn trace(&self, tracer: &mut dyn Tracer<'h>)
+    {
+        if let Some(value) = self {
+            value.trace(tracer);
+        }
+    }
+}
+
+impl<'h, T> Trace<'h> for [T]
+    where T: Trace<'h>
+{
+    fn trace(&self, tracer: &mut dyn Tracer<'h>)
+    {
+        for element in self {
+            element.trace(tracer);
+        }
+    }
+}
+
+impl<'h, T, const N: usize> Trace<'h> for [T; N]
+    where T: Trace<'h>
+{
+    fn trace(&self, tracer: &mut dyn Tracer<'h>)
+    {
+        self[..].trace(tracer);
+    }
+}
+
+impl<'h, T> Trace<'h> for Vec<T>
+    where T: Trace<'h>
+{
+    fn trace(&self, tracer: &mut dyn Tracer<'h>)
+    {
+        self[..].trace(tracer);
+    }
+}
+
+impl<'h, T> Trace<'h> for Box<T>
+    where T: Trace<'h> + ?Sized
+{
+    fn trace(&self, tracer: &mut dyn Tracer<'h>)
+    {
+        (**self).trace(tracer);
+    }
+}
+
+/// Methods for registering data structures as roots.
+impl<'h> Heap<'h>
+{
+    /// Register the data structure as a root
+    /// while calling the given function.
+    ///
+    /// The objects that the data structure refers to
+    /// are reachable until the function returns or panics,
+    /// so handles to them can be obtained with [`ScopedHandle::new`].
+    /// The data structure may be modified through interior mutability,
+    /// and is traced again whenever the roots are needed.
+    ///
+    /// [`ScopedHandle::new`]: `super::ScopedHandle::new`
+    pub fn with_root<T, F, R>(&self, root: &T, then: F) -> R
+        where T: Trace<'h>,
+              F: FnOnce() -> R
+    {
+        // SAFETY: The root is unregistered before it is dropped.
+        let root: *const (dyn Trace<'h> + '_) = root;
+        let root: *const dyn Trace<'h> = unsafe { transmute(root) };
+
+        // SAFETY: We only borrow these for short periods of time.
+        unsafe { self.traced_roots.borrow_mut() }.push(root);
+        defer! { unsafe { self.traced_roots.borrow_mut() }.pop(); }
+
+        then()
+    }
+
+    /// Pass every handle in the registered roots to the tracer.
+    pub (super) fn trace_roots(&self, tracer: &mut dyn Tracer<'h>)
+    {
+        // Tracing runs embedder code, which must not observe a borrow.
+        // SAFETY: We only borrow these for short periods of time.
+        let roots = unsafe { self.traced_roots.borrow() }.clone();
+        for root in roots {
+            // SAFETY: Registered roots are alive.
+            unsafe { &*root }.trace(tracer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::heap::ScopedHandle;
+
+    use alloc::vec;
+    use aurum_memory_derive::Trace;
+
+    #[derive(Trace)]
+    struct State<'h>
+    {
+        terms: Vec<Cell<UnsafeHandle<'h>>>,
+        current: Option<Box<Cell<UnsafeHandle<'h>>>>,
+        #[trace(skip)]
+        #[allow(dead_code)]
+        name: &'static str,
+    }
+
+    #[derive(Trace)]
+    enum Either<'h>
+    {
+        Left(Cell<UnsafeHandle<'h>>),
+        Right{right: [Cell<UnsafeHandle<'h>>; 2]},
+        Neither,
+    }
+
+    #[test]
+    fn with_root()
+    {
+        Heap::with_new(|heap| {
+            let state = State{
+                terms: vec![Cell::new(heap.interned_null()); 2],
+                current: Some(Box::new(Cell::new(heap.interned_null()))),
+                name: "state",
+            };
+            let before = heap.snapshot().num_objects();
+            heap.with_root(&state, || {
+                let term = unsafe { ScopedHandle::new(&state.terms[1]) };
+                heap.new_symbol(term, b"F").unwrap();
+                assert_eq!(heap.snapshot().num_objects(), before + 1);
+                heap.verify();
+            });
+            assert_eq!(heap.snapshot().num_objects(), before);
+
+            let mut count = 0;
+            state.trace(&mut |_: &Cell<UnsafeHandle>| count += 1);
+            assert_eq!(count, 3);
+
+            let null = || Cell::new(heap.interned_null());
+            let mut count = 0;
+            for either in [
+                Either::Left(null()),
+                Either::Right{right: [null(), null()]},
+                Either::Neither,
+            ] {
+                either.trace(&mut |_: &Cell<UnsafeHandle>| count += 1);
+            }
+            assert_eq!(count, 3);
+        });
+    }
+}
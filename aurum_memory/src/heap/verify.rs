@@ -0,0 +1,235 @@
+use super::Heap;
+use super::HeapMap;
+use super::HeapVec;
+use super::Interrupted;
+use super::ScopedHandle;
+use super::UnsafeHandle;
+use crate::object::DeBruijn;
+use crate::object::Flags;
+use crate::object::FreeCache;
+use crate::object::WellKnown;
+
+use core::cell::Cell;
+
+impl<'h> Heap<'h>
+{
+    /// Check that the heap is in a consistent state.
+    ///
+    /// This visits every object that is reachable from a scope,
+    /// from a [traced root][`Self::with_root`],
+    /// from the interned objects, or from the memo table,
+    /// and checks the invariants that the other methods rely on.
+    /// This is slow, so it is intended for tests and fuzzing,
+    /// where it catches memory corruption close to its cause.
+    ///
+    /// # Panics
+    ///
+    /// If the heap is not in a consistent state, this method panics.
+    pub fn verify(&self)
+    {
+        match self.verify_with_safepoint(|| Ok(())) {
+            Ok(()) => (),
+            Err(Interrupted) => unreachable!(),
+        }
+    }
+
+    /// Similar to [`verify`][`Self::verify`],
+    /// but call [`safepoint`][`Self::safepoint`] before each object,
+    /// and stop verifying if it returns an error.
+    pub fn try_verify(&self) -> Result<(), Interrupted>
+    {
+        self.verify_with_safepoint(|| self.safepoint())
+    }
+
+    fn verify_with_safepoint(
+        &self,
+        mut safepoint: impl FnMut() -> Result<(), Interrupted>,
+    ) -> Result<(), Interrupted>
+    {
+        assert!(
+            self.allocated_bytes() <= self.memory_limit,
+            "Allocated bytes exceed memory limit",
+        );
+
+        let mut stack = self.roots();
+        let mut visited = HeapMap::new(self);
+        let mut reachable_bytes = 0;
+        while let Some(handle) = stack.pop() {
+            if visited.insert(handle.as_ptr() as usize, ()).is_some() {
+                continue;
+            }
+            safepoint()?;
+
+            let cell = Cell::new(handle);
+            // SAFETY: Objects reachable from roots are not destroyed.
+            let object = unsafe { ScopedHandle::new(&cell) };
+            // SAFETY: Likewise.
+            unsafe { self.debug_assert_owned(handle) };
+            verify_object(object);
+            object.with_pin(|object| {
+                reachable_bytes += object.object_size();
+                object.visit_children(|child| {
+                    stack.push(child.as_unsafe_handle());
+                });
+            });
+        }
+
+        assert!(
+            reachable_bytes <= self.allocated_bytes(),
+            "Reachable objects exceed allocated bytes",
+        );
+        Ok(())
+    }
+
+    /// Handles to the objects that are reachable by definition:
+    /// the interned objects, those in scopes and traced roots,
+    /// and those in the memo table.
+    pub (super) fn roots(&self) -> HeapVec<'h, UnsafeHandle<'h>>
+    {
+        let mut roots = self.new_vec();
+
+        roots.extend(WellKnown::ALL.map(|w| self.interned_symbol(w)));
+        roots.extend(
+            (0 ..).map_while(|i| self.interned_extra_symbol(i))
+        );
+        roots.extend(
+            (0 ..).map_while(|i| self.interned_variable(DeBruijn(i)))
+        );
+        roots.extend(
+            (0u8 ..= 0x7F).filter_map(|c| self.interned_char(char::from(c)))
+        );
+
+        // SAFETY: We only borrow these for short periods of time.
+        for &scope in unsafe { self.scopes.borrow() }.iter() {
+            // SAFETY: Registered scopes are alive.
+            roots.extend(unsafe { &*scope }.iter().map(Cell::get));
+        }
+
+        self.trace_roots(&mut |handle: &Cell<UnsafeHandle<'h>>| {
+            roots.push(handle.get());
+        });
+
+        // SAFETY: We only borrow these for short periods of time.
+        for interned in
+            unsafe { self.interned_qualified_symbols.borrow() }.iter()
+        {
+            roots.push(interned.get());
+        }
+
+        // SAFETY: We only borrow these for short periods of time.
+        for entry in unsafe { self.memo.borrow() }.iter() {
+            roots.push(entry.key.get());
+            roots.push(entry.value.get());
+        }
+
+        roots
+    }
+}
+
+/// Check the invariants of a single object.
+fn verify_object(object: ScopedHandle)
+{
+    let header = object.header();
+
+    assert!(
+        !header.flags.contains(Flags::MARKED),
+        "Object is marked outside of garbage collection",
+    );
+
+    // The free variables cache must not have false negatives.
+    let free = |cache: FreeCache| {
+        (0 .. 16).map(DeBruijn)
+            .filter(move |&i| cache.contains(i) == Some(true))
+    };
+    let not_free = |cache: FreeCache, de_bruijn| {
+        cache.contains(de_bruijn) == Some(false)
+    };
+
+    let is_symbol = object.with_pin(|object| {
+        object.as_symbol().is_some() || object.as_qualified_symbol().is_some()
+    });
+    assert!(is_symbol || !object.is_gensym(), "Gensym is not a symbol");
+    assert!(
+        !header.flags.contains(Flags::NORMAL_FORM)
+            || header.flags.contains(Flags::WHNF),
+        "Normal form is not weak head normal form",
+    );
+    if is_symbol {
+        assert!(
+            free(header.free_cache).next().is_none(),
+            "Symbol has free variables",
+        );
+    }
+
+    if let Some(de_bruijn) = object.as_variable() {
+        assert!(
+            !not_free(header.free_cache, de_bruijn),
+            "Variable is not free in itself",
+        );
+    }
+
+    object.with_pin(|object| {
+        object.visit_children(|child| {
+            for de_bruijn in free(child.header().free_cache) {
+                assert!(
+                    !not_free(header.free_cache, de_bruijn),
+                    "Object lacks free variables of its children",
+                );
+            }
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::heap::HeapConfig;
+
+    use core::sync::atomic::AtomicBool;
+
+    #[test]
+    fn verify()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[f, x, y]| {
+                heap.new_symbol(f, b"Function").unwrap();
+                heap.new_variable(x, DeBruijn(3));
+                heap.new_application(y, f, [x, x]).unwrap();
+                heap.new_application(y, y, [y, f]).unwrap();
+                heap.verify();
+            });
+        });
+    }
+
+    #[test]
+    fn interrupt()
+    {
+        let flag = AtomicBool::new(true);
+        let config = HeapConfig{
+            interrupt: Some(&flag),
+            ..HeapConfig::default()
+        };
+        Heap::with_new_config(config, |heap| {
+            assert!(matches!(heap.try_verify(), Err(Interrupted)));
+            heap.verify();
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Object is marked")]
+    fn verify_marked()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[f]| {
+                heap.new_symbol(f, b"Function").unwrap();
+                // SAFETY: The handle refers to an object, as it is scoped.
+                unsafe {
+                    (*f.as_unsafe_handle().header()).flags
+                        .insert(Flags::MARKED);
+                }
+                heap.verify();
+            });
+        });
+    }
+}
@@ -1,5 +1,6 @@
 //! This crate implements the virtual machine.
 
+#![feature(allocator_api)]
 #![feature(as_array_of_cells)]
 #![feature(extern_types)]
 #![feature(maybe_uninit_array_assume_init)]
@@ -14,5 +15,17 @@
 extern crate alloc;
 extern crate core;
 
+// Lets tests use the derive macros, which refer to this crate by name.
+#[cfg(test)]
+extern crate self as aurum_memory;
+
+pub use self::error::*;
+
 pub mod heap;
 pub mod object;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+mod error;
+mod macros;
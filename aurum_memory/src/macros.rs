@@ -0,0 +1,145 @@
+/// Create a nested term from a compact description.
+///
+/// The first two arguments are the heap and the scoped handle
+/// that will refer to the created term.
+/// The remaining tokens describe the term, as follows:
+///
+///  - An identifier creates a symbol with that name.
+///  - `#` followed by an integer creates a variable with that De Bruijn index.
+///  - `?` followed by an integer creates that metavariable.
+///  - Parenthesized terms create an application,
+///    where the first term is the function and the others are the arguments.
+///  - A Rust expression in braces uses an existing scoped handle.
+///
+/// The term is created using a [`TermBuilder`].
+/// This macro is intended for tests, so it panics on errors,
+/// rather than returning them.
+///
+/// [`TermBuilder`]: `crate::object::TermBuilder`
+///
+/// # Examples
+///
+/// ```
+/// # use aurum_memory::heap::Heap;
+/// # use aurum_memory::term;
+/// # Heap::with_new(|heap| {
+/// heap.with_new_array_scope(|[pi, term]| {
+///     heap.new_symbol(pi, b"Pi").unwrap();
+///     term!(heap, term, (Add {pi} (Negate #0)));
+/// });
+/// # });
+/// ```
+#[macro_export]
+macro_rules! term
+{
+    (@node $builder:ident, # $de_bruijn:literal) => {
+        $builder.var($crate::object::DeBruijn($de_bruijn))
+    };
+
+    (@node $builder:ident, ? $metavariable:literal) => {
+        $builder.metavar($crate::object::Metavariable($metavariable))
+    };
+
+    (@node $builder:ident, $symbol:ident) => {
+        $builder.symbol(stringify!($symbol).as_bytes()).unwrap()
+    };
+
+    (@node $builder:ident, { $handle:expr }) => {
+        $builder.handle($handle)
+    };
+
+    (@node $builder:ident, ( $($terms:tt)+ )) => {{
+        let nodes = $crate::term!(@nodes $builder, [] $($terms)+);
+        let (function, arguments) = nodes.split_first().unwrap();
+        $builder.app(*function, arguments).unwrap()
+    }};
+
+    // Variables and metavariables consist of two tokens,
+    // so we munch the terms one by one.
+
+    (@nodes $builder:ident, [$($nodes:expr,)*]) => {
+        [$($nodes,)*]
+    };
+
+    (@nodes $builder:ident, [$($nodes:expr,)*]
+        # $de_bruijn:literal $($rest:tt)*) => {
+        $crate::term!(
+            @nodes $builder,
+            [$($nodes,)* $crate::term!(@node $builder, # $de_bruijn),]
+            $($rest)*
+        )
+    };
+
+    (@nodes $builder:ident, [$($nodes:expr,)*]
+        ? $metavariable:literal $($rest:tt)*) => {
+        $crate::term!(
+            @nodes $builder,
+            [$($nodes,)* $crate::term!(@node $builder, ? $metavariable),]
+            $($rest)*
+        )
+    };
+
+    (@nodes $builder:ident, [$($nodes:expr,)*]
+        $term:tt $($rest:tt)*) => {
+        $crate::term!(
+            @nodes $builder,
+            [$($nodes,)* $crate::term!(@node $builder, $term),]
+            $($rest)*
+        )
+    };
+
+    ($heap:expr, $into:expr, $($term:tt)+) => {
+        $heap.with_new_term_builder(|builder| {
+            let node = $crate::term!(@node builder, $($term)+);
+            builder.build(node, $into);
+        })
+    };
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::heap::Heap;
+    use crate::object::DeBruijn;
+
+    #[test]
+    fn term()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[pi, term]| {
+                heap.new_symbol(pi, b"Pi").unwrap();
+                term!(heap, term, (Add {pi} (Negate #0) #1));
+
+                let [add, pi_, negate, one] = term.with_pin(|term| {
+                    let (add, arguments) = term.as_application().unwrap();
+                    let [pi, negate, one] = [0, 1, 2].map(|i| {
+                        arguments.get(i).unwrap().as_unsafe_handle()
+                    });
+                    [add.as_unsafe_handle(), pi, negate, one]
+                });
+                // SAFETY: No objects are created while using these.
+                unsafe {
+                    heap.with_new_array_scope(|[h]| {
+                        h.copy_from_unsafe_handle(add);
+                        h.with_pin(|h| {
+                            assert_eq!(h.as_symbol(), Some(&b"Add"[..]));
+                        });
+                        h.copy_from_unsafe_handle(pi_);
+                        assert!(h.ptr_eq(pi));
+                        h.copy_from_unsafe_handle(one);
+                        assert_eq!(h.as_variable(), Some(DeBruijn(1)));
+                        h.copy_from_unsafe_handle(negate);
+                        h.with_pin(|h| {
+                            let (f, arguments) = h.as_application().unwrap();
+                            f.with_pin(|f| {
+                                assert_eq!(f.as_symbol(), Some(&b"Negate"[..]));
+                            });
+                            let zero = arguments.get(0).unwrap();
+                            assert_eq!(zero.as_variable(), Some(DeBruijn(0)));
+                        });
+                    });
+                }
+            });
+        });
+    }
+}
@@ -0,0 +1,181 @@
+use crate::heap::AllocError;
+use crate::heap::Heap;
+use crate::heap::PinnedHandle;
+use crate::heap::ScopedHandle;
+use crate::heap::UnsafeHandle;
+use super::Flags;
+use super::Header;
+use super::Kind;
+use super::Payload;
+
+use core::cell::Cell;
+use core::mem::MaybeUninit;
+use core::mem::size_of;
+
+/// Location in source code that a term was created from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Span
+{
+    /// Identifies the source file; its meaning is up to the embedder.
+    pub file: u32,
+
+    /// Byte offset of the start of the span.
+    pub start: u32,
+
+    /// Byte offset just past the end of the span.
+    pub end: u32,
+}
+
+// The payload stores the term, followed by the byte range.
+const PAYLOAD_SIZE: usize =
+    size_of::<UnsafeHandle>() + 2 * size_of::<u32>();
+
+/// Methods for creating annotated objects.
+///
+/// An annotated object wraps a term with the [`Span`] it came from,
+/// so that error messages can point at the source code.
+/// Annotations are transparent: an annotated term is equal to,
+/// and hashes the same as, the term without the annotation.
+/// Traversals that rebuild terms, such as substitution,
+/// keep the annotation around the rebuilt term.
+impl<'h> Heap<'h>
+{
+    /// Create an annotated object with the given term and span.
+    #[inline]
+    pub fn new_annotated<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        term: ScopedHandle<'h, 's>,
+        span: Span,
+    )
+    {
+        unsafe {
+            self.new(into, PAYLOAD_SIZE, |payload| {
+                init_annotated(payload, term, span)
+            });
+        }
+    }
+
+    /// Similar to [`new_annotated`][`Self::new_annotated`],
+    /// but return an error if memory cannot be allocated.
+    #[inline]
+    pub fn try_new_annotated<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        term: ScopedHandle<'h, 's>,
+        span: Span,
+    ) -> Result<(), AllocError>
+    {
+        unsafe {
+            self.try_new(into, PAYLOAD_SIZE, |payload| {
+                init_annotated(payload, term, span)
+            })
+        }
+    }
+}
+
+/// Initialize an annotated object with the given term and span.
+///
+/// # Safety
+///
+/// The payload must be [`PAYLOAD_SIZE`] bytes large.
+unsafe fn init_annotated<'h, 's>(
+    payload: *mut Payload,
+    term: ScopedHandle<'h, 's>,
+    span: Span,
+) -> Header
+{
+    // The extra field stores the file.
+    let mut extra = MaybeUninit::uninit_array();
+    MaybeUninit::write_slice(&mut extra, &span.file.to_ne_bytes());
+
+    // The payload stores the term, then the start and end offsets.
+    *(payload as *mut Cell<UnsafeHandle>) = Cell::new(term.as_unsafe_handle());
+    let range = (payload as *mut u8).add(size_of::<UnsafeHandle>());
+    *(range as *mut [u32; 2]) = [span.start, span.end];
+
+    Header{
+        kind: Kind::Annotated,
+        flags: Flags::empty(),
+        free_cache: term.header().free_cache,
+        extra,
+    }
+}
+
+/// Methods for inspecting annotated objects.
+impl<'h, 'p> PinnedHandle<'h, 'p>
+{
+    /// Get the term and the span of the annotated object.
+    ///
+    /// If the object is not annotated, this method returns [`None`].
+    #[inline]
+    pub fn as_annotated(self) -> Option<(ScopedHandle<'h, 'p>, Span)>
+    {
+        let header = self.header();
+        match header.kind {
+            Kind::Annotated => unsafe {
+                let extra = MaybeUninit::array_assume_init(header.extra);
+                let payload = self.payload();
+
+                // SAFETY: The handle resides in a pinned object.
+                let term = &*(payload as *const Cell<UnsafeHandle>);
+                let term = ScopedHandle::new(term);
+
+                let range = (payload as *const u8)
+                    .add(size_of::<UnsafeHandle>());
+                let [start, end] = *(range as *const [u32; 2]);
+                let span = Span{file: u32::from_ne_bytes(extra), start, end};
+
+                Some((term, span))
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::object::DeBruijn;
+    use crate::term;
+
+    #[test]
+    fn transparent()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_boxed_scope(0, |bindings| {
+            heap.with_new_array_scope(|[inner, term, plain, levels]| {
+                let span = Span{file: 1, start: 10, end: 20};
+                term!(heap, inner, (G #0));
+                heap.new_annotated(inner, inner, span);
+                term!(heap, term, (F {inner} A));
+                term!(heap, plain, (F (G #0) A));
+
+                assert!(heap.match_pattern(term, plain, bindings));
+                assert!(heap.match_pattern(plain, term, bindings));
+                assert_eq!(
+                    heap.structural_hash(term),
+                    heap.structural_hash(plain),
+                );
+
+                // Rebuilding keeps the annotation.
+                heap.indices_to_levels(levels, term, 2);
+                levels.with_pin(|levels| {
+                    let (_, arguments) = levels.as_application().unwrap();
+                    arguments.get(0).unwrap().with_pin(|annotated| {
+                        let (g, got) = annotated.as_annotated().unwrap();
+                        assert_eq!(got, span);
+                        g.with_pin(|g| {
+                            let (_, arguments) = g.as_application().unwrap();
+                            let level = arguments.get(0).unwrap().as_variable();
+                            assert_eq!(level, Some(DeBruijn(1)));
+                        });
+                    });
+                });
+
+                heap.verify();
+            }); });
+        });
+    }
+}
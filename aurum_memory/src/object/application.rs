@@ -1,3 +1,4 @@
+use crate::heap::AllocError;
 use crate::heap::Heap;
 use crate::heap::PinnedHandle;
 use crate::heap::Scope;
@@ -7,6 +8,7 @@ use super::Flags;
 use super::FreeCache;
 use super::Header;
 use super::Kind;
+use super::Payload;
 
 use core::cell::Cell;
 use core::iter::TrustedLen;
@@ -19,6 +21,31 @@ use core::slice;
 #[derive(Debug)]
 pub struct NumArgumentsError;
 
+/// Raised when [`Heap::try_new_application`] fails.
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub enum TryNewApplicationError
+{
+    NumArguments(NumArgumentsError),
+    Alloc(AllocError),
+}
+
+impl From<NumArgumentsError> for TryNewApplicationError
+{
+    fn from(other: NumArgumentsError) -> Self
+    {
+        Self::NumArguments(other)
+    }
+}
+
+impl From<AllocError> for TryNewApplicationError
+{
+    fn from(other: AllocError) -> Self
+    {
+        Self::Alloc(other)
+    }
+}
+
 /// Convenient constant for computing payload size.
 const PTR_SIZE: u32 = size_of::<UnsafeHandle>() as u32;
 
@@ -46,37 +73,219 @@ impl<'h> Heap<'h>
     {
         let arguments = arguments.into_iter();
         let payload_size = payload_size(arguments.len())?;
-
         unsafe {
             self.new(into, payload_size as usize, |payload| {
+                init_application(payload, payload_size, function, arguments)
+            });
+        }
+        Ok(())
+    }
+
+    /// Similar to [`new_application`][`Self::new_application`],
+    /// but return an error if memory cannot be allocated.
+    #[inline]
+    pub fn try_new_application<'s, I>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        function: ScopedHandle<'h, 's>,
+        arguments: impl IntoIterator<IntoIter=I>,
+    ) -> Result<(), TryNewApplicationError>
+        where I: ExactSizeIterator<Item=ScopedHandle<'h, 's>> + TrustedLen
+    {
+        let arguments = arguments.into_iter();
+        let payload_size = payload_size(arguments.len())?;
+        unsafe {
+            self.try_new(into, payload_size as usize, |payload| {
+                init_application(payload, payload_size, function, arguments)
+            })?;
+        }
+        Ok(())
+    }
 
-                // The extra field stores the number of fields,
-                // which is 1 (for the function) + the number of arguments.
-                let num_fields = payload_size / PTR_SIZE;
-                let mut extra = MaybeUninit::uninit_array();
-                MaybeUninit::write_slice(&mut extra, &num_fields.to_ne_bytes());
-
-                // The payload first stores the function,
-                // then all the arguments in order.
-                let mut free_cache = FreeCache::EMPTY;
-                let payload = payload as *mut Cell<UnsafeHandle>;
-                let fields = iter::once(function).chain(arguments);
-                for (i, field) in fields.enumerate() {
-                    free_cache |= field.header().free_cache;
-                    *payload.add(i) = Cell::new(field.as_unsafe_handle());
+    /// Create an application with the given function
+    /// and the objects referred to by the given scope as arguments.
+    #[inline]
+    pub fn new_application_from_scope<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        function: ScopedHandle<'h, 's>,
+        arguments: &'s Scope<'h>,
+    ) -> Result<(), NumArgumentsError>
+    {
+        self.new_application(into, function, arguments.iter())
+    }
+
+    /// Similar to
+    /// [`new_application_from_scope`][`Self::new_application_from_scope`],
+    /// but return an error if memory cannot be allocated.
+    #[inline]
+    pub fn try_new_application_from_scope<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        function: ScopedHandle<'h, 's>,
+        arguments: &'s Scope<'h>,
+    ) -> Result<(), TryNewApplicationError>
+    {
+        self.try_new_application(into, function, arguments.iter())
+    }
+
+    /// Create a nested application that applies the function
+    /// to each of the arguments in turn.
+    ///
+    /// That is, for arguments _a_, _b_, and _c_, this creates
+    /// the application ((_f_ _a_) _b_) _c_, which consists of
+    /// three application objects that each have a single argument.
+    /// If there are no arguments, `into` is set to the function itself.
+    pub fn new_application_curried<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        function: ScopedHandle<'h, 's>,
+        arguments: impl IntoIterator<Item=ScopedHandle<'h, 's>>,
+    )
+    {
+        // The spine is built in a separate handle,
+        // in case `into` is also one of the arguments.
+        self.with_new_array_scope(|[spine]| {
+            spine.copy_from(function);
+            for argument in arguments {
+                // A single argument is never too many.
+                self.new_application(spine, spine, [argument]).unwrap();
+            }
+            into.copy_from(spine);
+        });
+    }
+
+    /// Similar to
+    /// [`new_application_curried`][`Self::new_application_curried`],
+    /// but return an error if memory cannot be allocated.
+    ///
+    /// If an error is returned, `into` is left unchanged.
+    pub fn try_new_application_curried<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        function: ScopedHandle<'h, 's>,
+        arguments: impl IntoIterator<Item=ScopedHandle<'h, 's>>,
+    ) -> Result<(), AllocError>
+    {
+        self.with_new_array_scope(|[spine]| {
+            spine.copy_from(function);
+            for argument in arguments {
+                match self.try_new_application(spine, spine, [argument]) {
+                    Ok(()) => (),
+                    Err(TryNewApplicationError::Alloc(err)) => return Err(err),
+                    Err(TryNewApplicationError::NumArguments(_)) =>
+                        unreachable!(),
                 }
+            }
+            into.copy_from(spine);
+            Ok(())
+        })
+    }
+
+    /// Flatten a nested application into its head and all its arguments.
+    ///
+    /// This follows the function of the application
+    /// for as long as it is an application itself.
+    /// The function that is not an application is called the head.
+    /// For example, the head of ((_f_ _a_ _b_) _c_) is _f_,
+    /// and its arguments are _a_, _b_, and _c_, in that order.
+    /// If the term is not an application, it is its own head,
+    /// and it has no arguments.
+    ///
+    /// The head is written to `head_into`,
+    /// and the arguments are written to the first handles of `arguments`.
+    /// The total number of arguments is returned.
+    /// If `arguments` has too few handles to hold all the arguments,
+    /// nothing is written and the caller may try again with a larger scope.
+    pub fn unfold_application<'s>(
+        &self,
+        term: ScopedHandle<'h, 's>,
+        head_into: ScopedHandle<'h, 's>,
+        arguments: &Scope<'h>,
+    ) -> usize
+    {
+        self.with_new_array_scope(|[cursor]| {
 
-                Header{
-                    kind: Kind::Application,
-                    flags: Flags::empty(),
-                    free_cache,
-                    extra,
+            // Step along the function of each application.
+            // The closure is called for the arguments of each application
+            // and returns whether to continue.
+            let walk = |f: &mut dyn FnMut(&Scope<'h>) -> bool| {
+                cursor.copy_from(term);
+                loop {
+                    let function = cursor.with_pin(|cursor| {
+                        let (function, arguments) = cursor.as_application()?;
+                        f(arguments).then(|| function.as_unsafe_handle())
+                    });
+                    match function {
+                        // SAFETY: The function is reachable from the cursor.
+                        Some(f) => unsafe { cursor.copy_from_unsafe_handle(f) },
+                        None => break,
+                    }
                 }
+            };
 
+            let mut num_arguments = 0;
+            walk(&mut |level| { num_arguments += level.len(); true });
+
+            if num_arguments > arguments.len() {
+                return num_arguments;
+            }
+
+            // The innermost arguments come first,
+            // so fill in the arguments from the back.
+            let mut end = num_arguments;
+            walk(&mut |level| {
+                let start = end - level.len();
+                for (i, argument) in level.iter().enumerate() {
+                    // SAFETY: We checked that there are enough handles.
+                    unsafe { arguments.get_unchecked(start + i) }
+                        .copy_from(argument);
+                }
+                end = start;
+                true
             });
-        }
 
-        Ok(())
+            head_into.copy_from(cursor);
+            num_arguments
+
+        })
+    }
+}
+
+/// Initialize an application object with the given function and arguments.
+///
+/// # Safety
+///
+/// The payload size must have been computed by [`payload_size`]
+/// from the number of arguments, and the payload must be that large.
+unsafe fn init_application<'h, 's>(
+    payload: *mut Payload,
+    payload_size: u32,
+    function: ScopedHandle<'h, 's>,
+    arguments: impl Iterator<Item=ScopedHandle<'h, 's>>,
+) -> Header
+{
+    // The extra field stores the number of fields,
+    // which is 1 (for the function) + the number of arguments.
+    let num_fields = payload_size / PTR_SIZE;
+    let mut extra = MaybeUninit::uninit_array();
+    MaybeUninit::write_slice(&mut extra, &num_fields.to_ne_bytes());
+
+    // The payload first stores the function,
+    // then all the arguments in order.
+    let mut free_cache = FreeCache::EMPTY;
+    let payload = payload as *mut Cell<UnsafeHandle>;
+    let fields = iter::once(function).chain(arguments);
+    for (i, field) in fields.enumerate() {
+        free_cache |= field.header().free_cache;
+        *payload.add(i) = Cell::new(field.as_unsafe_handle());
+    }
+
+    Header{
+        kind: Kind::Application,
+        flags: Flags::empty(),
+        free_cache,
+        extra,
     }
 }
 
@@ -115,6 +324,47 @@ impl<'h, 'p> PinnedHandle<'h, 'p>
             _ => None,
         }
     }
+
+    /// Get the number of arguments of the application object.
+    ///
+    /// If the object is not an application, this method returns [`None`].
+    /// Unlike [`as_application`][`Self::as_application`],
+    /// this only reads the header of the object.
+    #[inline]
+    pub fn application_arity(self) -> Option<usize>
+    {
+        arity(self.header())
+    }
+}
+
+/// Methods for inspecting application objects.
+impl<'h, 's> ScopedHandle<'h, 's>
+{
+    /// Get the number of arguments of the application object.
+    ///
+    /// If the object is not an application, this method returns [`None`].
+    /// This does not require the object to be pinned.
+    #[inline]
+    pub fn application_arity(self) -> Option<usize>
+    {
+        arity(self.header())
+    }
+}
+
+/// The number of arguments of an application, given its header.
+///
+/// The extra field stores the number of fields,
+/// which includes the function.
+#[inline]
+fn arity(header: Header) -> Option<usize>
+{
+    match header.kind {
+        Kind::Application => {
+            let extra = unsafe { MaybeUninit::array_assume_init(header.extra) };
+            Some(u32::from_ne_bytes(extra) as usize - 1)
+        },
+        _ => None,
+    }
 }
 
 #[cfg(test)]
@@ -127,6 +377,114 @@ mod tests
     use proptest::collection::vec as pvec;
     use proptest::proptest;
 
+    #[test]
+    fn from_scope()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[application, function]| {
+            heap.with_new_boxed_scope(3, |arguments| {
+                heap.new_symbol(function, b"F").unwrap();
+                for (i, h) in arguments.iter().enumerate() {
+                    heap.new_variable(h, DeBruijn(i as u32));
+                }
+                heap.new_application_from_scope(
+                    application,
+                    function,
+                    arguments,
+                ).unwrap();
+                assert_eq!(application.application_arity(), Some(3));
+                assert_eq!(function.application_arity(), None);
+                application.with_pin(|application| {
+                    assert_eq!(application.application_arity(), Some(3));
+                    let result = application.as_application().unwrap();
+                    assert!(result.0.ptr_eq(function));
+                    assert!(
+                        Iterator::eq(
+                            result.1.iter().map(|sh| sh.as_unsafe_handle()),
+                            arguments.iter().map(|sh| sh.as_unsafe_handle()),
+                        )
+                    );
+                });
+            }); });
+        });
+    }
+
+    #[test]
+    fn curried()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[f, a, b, into]| {
+                heap.new_symbol(f, b"F").unwrap();
+                heap.new_symbol(a, b"A").unwrap();
+                heap.new_symbol(b, b"B").unwrap();
+                heap.new_symbol(into, b"C").unwrap();
+
+                // The last argument is also where the result goes.
+                heap.new_application_curried(into, f, [a, b, into]);
+
+                into.with_pin(|fabc| {
+                    let (fab, [c]) = unary(fabc);
+                    c.with_pin(|c| {
+                        assert_eq!(c.as_symbol(), Some(&b"C"[..]));
+                    });
+                    fab.with_pin(|fab| {
+                        let (fa, [b_]) = unary(fab);
+                        assert!(b_.ptr_eq(b));
+                        fa.with_pin(|fa| {
+                            let (f_, [a_]) = unary(fa);
+                            assert!(f_.ptr_eq(f));
+                            assert!(a_.ptr_eq(a));
+                        });
+                    });
+                });
+            });
+        });
+    }
+
+    #[test]
+    fn unfold()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[f, a, b, c, fab, term, head]| {
+            heap.with_new_boxed_scope(3, |arguments| {
+                heap.new_symbol(f, b"F").unwrap();
+                heap.new_symbol(a, b"A").unwrap();
+                heap.new_symbol(b, b"B").unwrap();
+                heap.new_symbol(c, b"C").unwrap();
+                heap.new_application(fab, f, [a, b]).unwrap();
+                heap.new_application(term, fab, [c]).unwrap();
+
+                // Too few handles, so nothing is written.
+                heap.with_new_boxed_scope(2, |too_few| {
+                    assert_eq!(heap.unfold_application(term, head, too_few), 3);
+                    assert_eq!(head.as_unsafe_handle(), heap.interned_null());
+                });
+
+                assert_eq!(heap.unfold_application(term, head, arguments), 3);
+                assert!(head.ptr_eq(f));
+                assert!(
+                    Iterator::eq(
+                        arguments.iter().map(|sh| sh.as_unsafe_handle()),
+                        [a, b, c].iter().map(|sh| sh.as_unsafe_handle()),
+                    )
+                );
+
+                // Not an application, so it is its own head.
+                assert_eq!(heap.unfold_application(c, head, arguments), 0);
+                assert!(head.ptr_eq(c));
+            }); });
+        });
+    }
+
+    /// Destructure an application with a single argument.
+    fn unary<'h, 'p>(application: PinnedHandle<'h, 'p>)
+        -> (ScopedHandle<'h, 'p>, [ScopedHandle<'h, 'p>; 1])
+    {
+        let (function, arguments) = application.as_application().unwrap();
+        assert_eq!(arguments.len(), 1);
+        (function, [arguments.get(0).unwrap()])
+    }
+
     proptest!
     {
         #[test]
@@ -154,10 +512,7 @@ mod tests
                 // Check that the correct object was created.
                 application.with_pin(|application| {
                     let result = application.as_application().unwrap();
-                    assert_eq!(
-                        result.0.as_unsafe_handle(),
-                        function.as_unsafe_handle(),
-                    );
+                    assert!(result.0.ptr_eq(function));
                     assert!(
                         Iterator::eq(
                             result.1.iter().map(|sh| sh.as_unsafe_handle()),
@@ -0,0 +1,273 @@
+use super::DeBruijn;
+use super::Metavariable;
+use super::NumArgumentsError;
+use super::SymbolLenError;
+use crate::heap::GrowableScope;
+use crate::heap::Heap;
+use crate::heap::ScopedHandle;
+
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+/// Source of the identities of term builders,
+/// for telling their nodes apart.
+static NEXT_BUILDER_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Identifies an object created by a [`TermBuilder`].
+///
+/// Node identifiers are only meaningful to the builder that created them.
+/// Passing one to another builder causes a panic.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct NodeId
+{
+    index: usize,
+
+    /// The builder that created the node.
+    builder: usize,
+}
+
+/// Convenient way to create nested terms.
+///
+/// Creating a nested term with the `new_*` methods on [`Heap`]
+/// requires a scoped handle for every intermediate object.
+/// The term builder keeps track of these for you:
+/// each method creates an object and returns a [`NodeId`] for it,
+/// which can then be passed to other methods of the builder.
+/// Once the term is complete, [`build`][`Self::build`] stores it
+/// in a scoped handle of your choice.
+///
+/// # Examples
+///
+/// ```
+/// # use aurum_memory::heap::Heap;
+/// # use aurum_memory::object::DeBruijn;
+/// # Heap::with_new(|heap| {
+/// heap.with_new_array_scope(|[term]| {
+///     heap.with_new_term_builder(|builder| {
+///         let add = builder.symbol(b"Add").unwrap();
+///         let pi = builder.symbol(b"Pi").unwrap();
+///         let x = builder.var(DeBruijn(0));
+///         let app = builder.app(add, &[pi, x]).unwrap();
+///         builder.build(app, term);
+///     });
+/// });
+/// # });
+/// ```
+pub struct TermBuilder<'g, 'h>
+{
+    nodes: GrowableScope<'g, 'h>,
+
+    /// Stored in the nodes created by this builder.
+    id: usize,
+}
+
+impl<'h> Heap<'h>
+{
+    /// Create a new term builder and pass it to the given function.
+    ///
+    /// The objects created by the builder are kept alive
+    /// until the given function returns or panics.
+    pub fn with_new_term_builder<F, R>(&self, then: F) -> R
+        where F: for<'g> FnOnce(&mut TermBuilder<'g, 'h>) -> R
+    {
+        self.with_new_growable_scope(|nodes| {
+            then(&mut TermBuilder{
+                nodes,
+                id: NEXT_BUILDER_ID.fetch_add(1, Ordering::Relaxed),
+            })
+        })
+    }
+}
+
+impl<'g, 'h> TermBuilder<'g, 'h>
+{
+    /// Add an existing object to the builder.
+    pub fn handle(&mut self, handle: ScopedHandle<'h, '_>) -> NodeId
+    {
+        let node = self.push();
+        self.get(node).copy_from(handle);
+        node
+    }
+
+    /// Create a symbol with the given name.
+    ///
+    /// See [`Heap::new_symbol`] for more information.
+    pub fn symbol(&mut self, name: &[u8]) -> Result<NodeId, SymbolLenError>
+    {
+        let node = self.push();
+        self.nodes.heap().new_symbol(self.get(node), name)?;
+        Ok(node)
+    }
+
+    /// Create a qualified symbol with the given namespace and name.
+    ///
+    /// See [`Heap::new_qualified_symbol`] for more information.
+    pub fn qualified_symbol(&mut self, namespace: &[u8], name: &[u8])
+        -> Result<NodeId, SymbolLenError>
+    {
+        let node = self.push();
+        let into = self.get(node);
+        self.nodes.heap().new_qualified_symbol(into, namespace, name)?;
+        Ok(node)
+    }
+
+    /// Create a variable with the given De Bruijn index.
+    ///
+    /// See [`Heap::new_variable`] for more information.
+    pub fn var(&mut self, de_bruijn: DeBruijn) -> NodeId
+    {
+        let node = self.push();
+        self.nodes.heap().new_variable(self.get(node), de_bruijn);
+        node
+    }
+
+    /// Create a metavariable object.
+    ///
+    /// See [`Heap::new_metavariable`] for more information.
+    pub fn metavar(&mut self, metavariable: Metavariable) -> NodeId
+    {
+        let node = self.push();
+        let into = self.get(node);
+        self.nodes.heap().new_metavariable(into, metavariable);
+        node
+    }
+
+    /// Create a character object.
+    ///
+    /// See [`Heap::new_char`] for more information.
+    pub fn char(&mut self, char: char) -> NodeId
+    {
+        let node = self.push();
+        self.nodes.heap().new_char(self.get(node), char);
+        node
+    }
+
+    /// Create an application with the given function and arguments.
+    ///
+    /// See [`Heap::new_application`] for more information.
+    pub fn app(&mut self, function: NodeId, arguments: &[NodeId])
+        -> Result<NodeId, NumArgumentsError>
+    {
+        let node = self.push();
+        self.nodes.heap().new_application(
+            self.get(node),
+            self.get(function),
+            arguments.iter().map(|&argument| self.get(argument)),
+        )?;
+        Ok(node)
+    }
+
+    /// Create a cons cell with the given head and tail.
+    ///
+    /// See [`Heap::new_cons`] for more information.
+    pub fn cons(&mut self, head: NodeId, tail: NodeId) -> NodeId
+    {
+        let node = self.push();
+        self.nodes.heap().new_cons(
+            self.get(node),
+            self.get(head),
+            self.get(tail),
+        );
+        node
+    }
+
+    /// Modify the given handle to refer to the object of the given node.
+    pub fn build(&self, node: NodeId, into: ScopedHandle<'h, '_>)
+    {
+        into.copy_from(self.get(node));
+    }
+
+    /// Add a node, which initially refers to the interned null symbol.
+    fn push(&mut self) -> NodeId
+    {
+        NodeId{
+            index: self.nodes.push(),
+            builder: self.id,
+        }
+    }
+
+    /// Scoped handle that refers to the object of the given node.
+    ///
+    /// # Panics
+    ///
+    /// If the node was not created by this builder, this method panics.
+    fn get(&self, node: NodeId) -> ScopedHandle<'h, '_>
+    {
+        assert_eq!(
+            node.builder, self.id,
+            "Node was not created by this builder",
+        );
+        self.nodes.as_scope().get(node.index).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn nested()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[term, outside]| {
+                heap.new_symbol(outside, b"Outside").unwrap();
+
+                heap.with_new_term_builder(|builder| {
+                    // Enough nodes to make the scope grow a few times.
+                    let mut node = builder.handle(outside);
+                    for i in 0 .. 100 {
+                        let x = builder.var(DeBruijn(i));
+                        let f = builder.symbol(b"F").unwrap();
+                        node = builder.app(f, &[x, node]).unwrap();
+                    }
+                    builder.build(node, term);
+                });
+
+                let mut depth = 0;
+                loop {
+                    let next = term.with_pin(|pinned| {
+                        match pinned.as_application() {
+                            Some((f, arguments)) => {
+                                f.with_pin(|f| {
+                                    assert_eq!(f.as_symbol(), Some(&b"F"[..]));
+                                });
+                                let [x, rest] = [0, 1].map(|i| {
+                                    arguments.get(i).unwrap()
+                                });
+                                let i = DeBruijn(99 - depth);
+                                assert_eq!(x.as_variable(), Some(i));
+                                Some(rest.as_unsafe_handle())
+                            },
+                            None => None,
+                        }
+                    });
+                    match next {
+                        // SAFETY: The argument is reachable from the term.
+                        Some(h) => unsafe { term.copy_from_unsafe_handle(h) },
+                        None => break,
+                    }
+                    depth += 1;
+                }
+                assert_eq!(depth, 100);
+                assert!(term.ptr_eq(outside));
+            });
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Node was not created by this builder")]
+    fn other_builder()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_term_builder(|outer| {
+                let f = outer.symbol(b"F").unwrap();
+                heap.with_new_term_builder(|inner| {
+                    // The inner builder has a node with the same index.
+                    let x = inner.var(DeBruijn(0));
+                    inner.app(f, &[x]).unwrap();
+                });
+            });
+        });
+    }
+}
@@ -0,0 +1,147 @@
+use crate::heap::AllocError;
+use crate::heap::Heap;
+use crate::heap::ScopedHandle;
+use super::Flags;
+use super::FreeCache;
+use super::Header;
+use super::Kind;
+use super::Payload;
+
+use core::mem::MaybeUninit;
+
+// Characters store all info in the header.
+const PAYLOAD_SIZE: usize = 0;
+
+/// Methods for creating character objects.
+///
+/// A character object stores a Unicode scalar value,
+/// and is the element type of strings.
+/// It is only equal to characters with the same value,
+/// and never to symbols, even ones whose name is that character.
+impl<'h> Heap<'h>
+{
+    /// Create a character object.
+    ///
+    /// Characters in the ASCII range are interned:
+    /// only the first call for such a character allocates,
+    /// and later calls return the same object.
+    #[inline]
+    pub fn new_char<'s>(&self, into: ScopedHandle<'h, 's>, char: char)
+    {
+        match self.interned_char(char) {
+            Some(result) => unsafe { into.copy_from_unsafe_handle(result) },
+            None => {
+                self.new_char_not_interned(into, char);
+                self.set_interned_char(char, into.as_unsafe_handle());
+            },
+        }
+    }
+
+    /// Similar to [`new_char`][`Self::new_char`],
+    /// but return an error if memory cannot be allocated.
+    #[inline]
+    pub fn try_new_char<'s>(&self, into: ScopedHandle<'h, 's>, char: char)
+        -> Result<(), AllocError>
+    {
+        match self.interned_char(char) {
+            Some(result) => unsafe { into.copy_from_unsafe_handle(result) },
+            None => {
+                self.try_new_char_not_interned(into, char)?;
+                self.set_interned_char(char, into.as_unsafe_handle());
+            },
+        }
+        Ok(())
+    }
+
+    /// Create a character object.
+    #[inline]
+    pub fn new_char_not_interned<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        char: char,
+    )
+    {
+        unsafe {
+            self.new(into, PAYLOAD_SIZE, |payload| init_char(payload, char));
+        }
+    }
+
+    /// Similar to [`new_char_not_interned`][`Self::new_char_not_interned`],
+    /// but return an error if memory cannot be allocated.
+    #[inline]
+    pub fn try_new_char_not_interned<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        char: char,
+    ) -> Result<(), AllocError>
+    {
+        unsafe {
+            self.try_new(into, PAYLOAD_SIZE, |payload| init_char(payload, char))
+        }
+    }
+}
+
+/// Initialize a character object.
+fn init_char(_payload: *mut Payload, char: char) -> Header
+{
+    // The character is stored in the extra field.
+    let mut extra = MaybeUninit::uninit_array();
+    let extra_bytes = (char as u32).to_ne_bytes();
+    MaybeUninit::write_slice(&mut extra, &extra_bytes);
+
+    Header{
+        kind: Kind::Char,
+        flags: Flags::empty(),
+        free_cache: FreeCache::EMPTY,
+        extra,
+    }
+}
+
+/// Methods for inspecting character objects.
+impl<'h, 's> ScopedHandle<'h, 's>
+{
+    /// Get the character of the character object.
+    ///
+    /// If the object is not a character, this method returns [`None`].
+    #[inline]
+    pub fn as_char(self) -> Option<char>
+    {
+        let header = self.header();
+        match header.kind {
+            Kind::Char => {
+                let extra = header.extra;
+                let extra = unsafe { MaybeUninit::array_assume_init(extra) };
+                let char = u32::from_ne_bytes(extra);
+                // SAFETY: Character objects store valid scalar values.
+                Some(unsafe { char::from_u32_unchecked(char) })
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    use proptest::proptest;
+
+    proptest!
+    {
+        #[test]
+        fn roundtrip(char: char)
+        {
+            Heap::with_new(|heap| {
+                heap.with_new_array_scope(|[handle, other]| {
+                    heap.new_char(handle, char);
+                    assert_eq!(handle.as_char(), Some(char));
+                    assert_eq!(handle.as_variable(), None);
+
+                    heap.new_char(other, char);
+                    assert_eq!(handle.ptr_eq(other), char.is_ascii());
+                });
+            });
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use crate::heap::GrowableScope;
+use crate::heap::Heap;
+use crate::heap::HeapMap;
+use crate::heap::HeapVec;
+use crate::heap::ScopedHandle;
+use super::Visitor;
+use super::hash::hash_object;
+use super::terms_equal;
+
+/// Methods for eliminating common subterms.
+impl<'h> Heap<'h>
+{
+    /// Create a term like the given term,
+    /// in which structurally equal subterms are the same object.
+    ///
+    /// Terms produced by parsers and code generators
+    /// often contain many equal subterms that are separate objects.
+    /// Sharing them saves memory, and makes later passes faster
+    /// when they skip objects they have seen before.
+    /// Only the applications above replaced subterms are recreated;
+    /// the rest of the term is shared with the original.
+    /// Custom objects are only equal to themselves,
+    /// so their children are left alone.
+    ///
+    /// This takes time proportional to the size of the term,
+    /// counting shared subterms once for every path to them.
+    pub fn share_common_subterms<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        term: ScopedHandle<'h, 's>,
+    )
+    {
+        self.with_new_growable_scope(|results| {
+        self.with_new_growable_scope(|shared| {
+            let mut sharer = Sharer{
+                heap: self,
+                results,
+                hashes: self.new_vec(),
+                shared,
+                table: HeapMap::new(self),
+            };
+            self.walk(term, &mut sharer);
+            into.copy_from(sharer.results.as_scope().get(0).unwrap());
+        })
+        })
+    }
+}
+
+/// Visitor that shares equal subterms,
+/// for [`Heap::share_common_subterms`].
+struct Sharer<'a, 'g, 'h>
+{
+    heap: &'a Heap<'h>,
+
+    /// Shared versions of the children of the objects being walked,
+    /// along with their hashes.
+    results: GrowableScope<'g, 'h>,
+    hashes: HeapVec<'h, u64>,
+
+    /// Every distinct subterm encountered so far.
+    shared: GrowableScope<'g, 'h>,
+
+    /// Indices into `shared`, by hash.
+    table: HeapMap<'h, u64, HeapVec<'h, usize>>,
+}
+
+impl<'a, 'g, 'h> Visitor<'h> for Sharer<'a, 'g, 'h>
+{
+    fn pre(&mut self, object: ScopedHandle<'h, '_>) -> bool
+    {
+        object.with_pin(|object| object.num_rebuilt_fields().is_some())
+    }
+
+    fn post(&mut self, object: ScopedHandle<'h, '_>)
+    {
+        let num_fields = object.with_pin(|object| object.num_rebuilt_fields())
+            .unwrap_or(0);
+
+        // Recreate the object if any of its fields were replaced.
+        let index = self.results.push();
+        let start = index - num_fields;
+        let results = self.results.as_scope();
+        let fields = (start .. index).map(|i| results.get(i).unwrap());
+        let result = results.get(index).unwrap();
+        result.copy_from(object);
+        if num_fields != 0 {
+            self.heap.new_rebuilt_if_changed(result, object, fields);
+        }
+
+        let hash = hash_object(result, &self.hashes[start ..]);
+
+        // Use an equal subterm that was encountered before, if any.
+        // As the fields of both are shared,
+        // comparing them takes constant time per field.
+        let heap = self.heap;
+        let candidates = self.table.get_or_insert_with(hash, || heap.new_vec());
+        let shared = self.shared.as_scope();
+        let existing = candidates.iter()
+            .map(|&i| shared.get(i).unwrap())
+            .find(|&other| terms_equal(self.heap, result, other));
+        match existing {
+            Some(existing) => result.copy_from(existing),
+            None => {
+                let i = self.shared.push();
+                self.shared.as_scope().get(i).unwrap().copy_from(result);
+                candidates.push(i);
+            },
+        }
+
+        // Replace the fields by the result.
+        let results = self.results.as_scope();
+        results.get(start).unwrap().copy_from(results.get(index).unwrap());
+        for _ in 0 .. num_fields {
+            self.results.pop();
+        }
+        self.hashes.truncate(start);
+        self.hashes.push(hash);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::term;
+    use crate::testing::TermTree;
+
+    #[test]
+    fn share_common_subterms()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[term, result]| {
+                term!(heap, term, (F (G (H #0) A) (G (H #0) A) (H #0)));
+                heap.share_common_subterms(result, term);
+                assert_eq!(
+                    TermTree::read(heap, result),
+                    TermTree::read(heap, term),
+                );
+
+                result.with_pin(|result| {
+                    let (_, arguments) = result.as_application().unwrap();
+                    let argument = |i| arguments.get(i).unwrap();
+                    assert!(argument(0).ptr_eq(argument(1)));
+                    argument(0).with_pin(|inner| {
+                        let (_, inner) = inner.as_application().unwrap();
+                        assert!(inner.get(0).unwrap().ptr_eq(argument(2)));
+                    });
+                });
+
+                // Sharing again changes nothing.
+                heap.share_common_subterms(term, result);
+                assert!(term.ptr_eq(result));
+            });
+        });
+    }
+}
@@ -0,0 +1,358 @@
+use crate::heap::AllocError;
+use crate::heap::Heap;
+#[allow(unused)] use crate::heap::HeapConfig;
+use crate::heap::PinnedHandle;
+use crate::heap::ScopedHandle;
+use crate::heap::UnsafeHandle;
+use super::Flags;
+use super::FreeCache;
+use super::Header;
+use super::Kind;
+use super::Payload;
+
+use core::cell::Cell;
+use core::mem::MaybeUninit;
+use core::mem::size_of;
+
+/// Identifies a kind of object that is defined outside this crate.
+///
+/// This is the index of the kind in [`HeapConfig::custom_kinds`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CustomKind(pub u32);
+
+/// Describes a kind of object that is defined outside this crate.
+///
+/// The garbage collector and other code that handles all objects
+/// consult the descriptor to deal with objects of the kind.
+/// Each callback receives the part of the payload
+/// that was initialized by [`Heap::new_custom`].
+pub struct KindDescriptor
+{
+    /// Name of the kind, for debugging and for serialization.
+    pub name: &'static str,
+
+    /// The number of bytes occupied by the payload.
+    pub payload_size: unsafe fn(payload: *const Payload) -> usize,
+
+    /// Call the given function for every handle embedded in the payload.
+    ///
+    /// See [`visit_children`][`super::visit_children`].
+    pub visit_children: VisitChildren,
+
+    /// Release resources owned by the payload.
+    ///
+    /// This is called when an object of the kind is destroyed.
+    /// Until there is a garbage collector, objects are never destroyed.
+    pub finalize: Option<unsafe fn(payload: *mut Payload)>,
+}
+
+/// Type of [`KindDescriptor::visit_children`].
+pub type VisitChildren =
+    for<'h> unsafe fn(
+        payload: *mut Payload,
+        f: &mut dyn FnMut(&Cell<UnsafeHandle<'h>>),
+    );
+
+/// The payload of a custom object starts with a pointer to its descriptor,
+/// so that it can be inspected without access to the heap.
+/// What follows is up to the descriptor.
+const PREFIX_SIZE: usize = size_of::<*const KindDescriptor>();
+
+/// Methods for creating custom objects.
+impl<'h> Heap<'h>
+{
+    /// Create an object of the given custom kind.
+    ///
+    /// The `init` function is called to initialize the payload,
+    /// which is `payload_size` bytes large and 8-byte aligned.
+    ///
+    /// # Panics
+    ///
+    /// If the kind was not registered with [`HeapConfig::custom_kinds`],
+    /// this method panics.
+    ///
+    /// # Safety
+    ///
+    /// Once `init` returns, the callbacks of the descriptor
+    /// must behave correctly on the payload.
+    /// In particular, the payload size callback must return `payload_size`,
+    /// and each embedded handle must refer to an object that is reachable
+    /// from a scope (which is the case if it was obtained from a scope).
+    /// The conditions on `init` are the same as for [`Heap::alloc`].
+    pub unsafe fn new_custom<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        kind: CustomKind,
+        payload_size: usize,
+        init: impl FnOnce(*mut Payload),
+    )
+    {
+        let descriptor = self.expect_custom_kind(kind);
+        self.new(into, PREFIX_SIZE + payload_size, |payload| {
+            init_custom(payload, kind, descriptor, init)
+        });
+    }
+
+    /// Similar to [`new_custom`][`Self::new_custom`],
+    /// but return an error if memory cannot be allocated.
+    ///
+    /// # Safety
+    ///
+    /// See the safety section of [`new_custom`][`Self::new_custom`].
+    pub unsafe fn try_new_custom<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        kind: CustomKind,
+        payload_size: usize,
+        init: impl FnOnce(*mut Payload),
+    ) -> Result<(), AllocError>
+    {
+        let descriptor = self.expect_custom_kind(kind);
+        self.try_new(into, PREFIX_SIZE + payload_size, |payload| {
+            init_custom(payload, kind, descriptor, init)
+        })
+    }
+
+    fn expect_custom_kind(&self, kind: CustomKind) -> &'static KindDescriptor
+    {
+        self.custom_kind(kind).expect("Custom kind is not registered")
+    }
+}
+
+/// Initialize a custom object.
+///
+/// # Safety
+///
+/// The payload must be large enough for the prefix.
+unsafe fn init_custom(
+    payload: *mut Payload,
+    kind: CustomKind,
+    descriptor: &'static KindDescriptor,
+    init: impl FnOnce(*mut Payload),
+) -> Header
+{
+    // The payload stores the descriptor, followed by whatever.
+    *(payload as *mut *const KindDescriptor) = descriptor;
+    init(custom_payload(payload));
+
+    // The extra field stores the custom kind.
+    let mut extra = MaybeUninit::uninit_array();
+    MaybeUninit::write_slice(&mut extra, &kind.0.to_ne_bytes());
+
+    Header{
+        kind: Kind::Custom,
+        flags: Flags::empty(),
+        // We cannot tell which variables are free in the object.
+        free_cache: FreeCache::UNKNOWN,
+        extra,
+    }
+}
+
+/// The descriptor of a custom object.
+///
+/// # Safety
+///
+/// The payload must be that of a custom object.
+pub (super) unsafe fn custom_descriptor(payload: *const Payload)
+    -> &'static KindDescriptor
+{
+    &**(payload as *const *const KindDescriptor)
+}
+
+/// The part of the payload of a custom object after the descriptor.
+///
+/// # Safety
+///
+/// The payload must be that of a custom object.
+pub (super) unsafe fn custom_payload(payload: *mut Payload) -> *mut Payload
+{
+    (payload as *mut u8).add(PREFIX_SIZE) as *mut Payload
+}
+
+/// Methods for inspecting custom objects.
+impl<'h, 'p> PinnedHandle<'h, 'p>
+{
+    /// Get the custom kind and the payload of the custom object.
+    ///
+    /// The payload is the part that was initialized by [`Heap::new_custom`].
+    /// If the object is not a custom object, this method returns [`None`].
+    #[inline]
+    pub fn as_custom(self) -> Option<(CustomKind, *mut Payload)>
+    {
+        let header = self.header();
+        match header.kind {
+            Kind::Custom => {
+                let extra = header.extra;
+                let extra = unsafe { MaybeUninit::array_assume_init(extra) };
+                let kind = CustomKind(u32::from_ne_bytes(extra));
+                let payload = unsafe { custom_payload(self.payload()) };
+                Some((kind, payload))
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::object::HEADER_SIZE;
+    use crate::object::RuleSet;
+    use crate::object::Visitor;
+    use crate::term;
+
+    /// Custom object with two handles.
+    static PAIR: KindDescriptor = KindDescriptor{
+        name: "Pair",
+        payload_size: |_| 2 * size_of::<UnsafeHandle>(),
+        visit_children: visit_pair,
+        finalize: None,
+    };
+
+    unsafe fn visit_pair<'h>(
+        payload: *mut Payload,
+        f: &mut dyn FnMut(&Cell<UnsafeHandle<'h>>),
+    )
+    {
+        let fields = payload as *const Cell<UnsafeHandle<'h>>;
+        f(&*fields);
+        f(&*fields.add(1));
+    }
+
+    #[test]
+    fn pair()
+    {
+        struct Count(usize);
+        impl<'h> Visitor<'h> for Count
+        {
+            fn post(&mut self, _object: ScopedHandle<'h, '_>)
+            {
+                self.0 += 1;
+            }
+        }
+
+        let config = HeapConfig{
+            custom_kinds: &[&PAIR],
+            ..HeapConfig::default()
+        };
+        Heap::with_new_config(config, |heap| {
+            heap.with_new_array_scope(|[a, b, pair]| {
+                heap.new_symbol(a, b"A").unwrap();
+                heap.new_symbol(b, b"B").unwrap();
+                let payload_size = 2 * size_of::<UnsafeHandle>();
+                unsafe {
+                    heap.new_custom(pair, CustomKind(0), payload_size, |p| {
+                        let fields = p as *mut Cell<UnsafeHandle>;
+                        *fields = Cell::new(a.as_unsafe_handle());
+                        *fields.add(1) = Cell::new(b.as_unsafe_handle());
+                    });
+                }
+
+                pair.with_pin(|pinned| {
+                    let (kind, _) = pinned.as_custom().unwrap();
+                    assert_eq!(kind, CustomKind(0));
+                    assert_eq!(heap.custom_kind(kind).unwrap().name, "Pair");
+                    assert_eq!(
+                        pinned.object_size(),
+                        HEADER_SIZE + 8 + payload_size,
+                    );
+                });
+
+                let mut count = Count(0);
+                heap.walk(pair, &mut count);
+                assert_eq!(count.0, 3);
+                heap.verify();
+            });
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Custom kind is not registered")]
+    fn unregistered()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[object]| {
+                unsafe { heap.new_custom(object, CustomKind(0), 0, |_| ()) };
+            });
+        });
+    }
+
+    /// Create a pair of the given objects.
+    fn new_pair<'h>(
+        heap: &Heap<'h>,
+        into: ScopedHandle<'h, '_>,
+        first: ScopedHandle<'h, '_>,
+        second: ScopedHandle<'h, '_>,
+    )
+    {
+        let payload_size = 2 * size_of::<UnsafeHandle>();
+        // SAFETY: The payload is initialized with two handles.
+        unsafe {
+            heap.new_custom(into, CustomKind(0), payload_size, |p| {
+                let fields = p as *mut Cell<UnsafeHandle>;
+                *fields = Cell::new(first.as_unsafe_handle());
+                *fields.add(1) = Cell::new(second.as_unsafe_handle());
+            });
+        }
+    }
+
+    #[test]
+    fn rewrite()
+    {
+        let config = HeapConfig{
+            custom_kinds: &[&PAIR],
+            ..HeapConfig::default()
+        };
+        Heap::with_new_config(config, |heap| {
+            heap.with_new_boxed_scope(2, |rules| {
+            heap.with_new_array_scope(|[a, b, pair, term, result]| {
+                term!(heap, a, A);
+                term!(heap, b, B);
+                new_pair(heap, pair, a, b);
+
+                // The pair in the template is copied as is.
+                term!(heap, rules.get(0).unwrap(), (F ?0));
+                term!(heap, rules.get(1).unwrap(), (G {pair} ?0));
+                let rules = RuleSet::new(heap, rules);
+
+                term!(heap, term, (F C));
+                assert!(heap.rewrite_once(result, term, &rules));
+                result.with_pin(|result| {
+                    let (function, arguments) =
+                        result.as_application().unwrap();
+                    function.with_pin(|function| {
+                        assert_eq!(function.as_symbol(), Some(&b"G"[..]));
+                    });
+                    assert_eq!(arguments.len(), 2);
+                    assert!(arguments.get(0).unwrap().ptr_eq(pair));
+                });
+                heap.verify();
+            }); });
+        });
+    }
+
+    #[test]
+    fn egraph()
+    {
+        let config = HeapConfig{
+            custom_kinds: &[&PAIR],
+            ..HeapConfig::default()
+        };
+        Heap::with_new_config(config, |heap| {
+            heap.with_new_array_scope(|[a, b, pair, term]| {
+                term!(heap, a, A);
+                term!(heap, b, B);
+                new_pair(heap, pair, a, b);
+                term!(heap, term, (F {pair}));
+
+                // The pair is a leaf, so its children are not added.
+                heap.with_new_egraph(|egraph| {
+                    let root = egraph.add(term);
+                    assert_eq!(egraph.num_classes(), 3);
+                    assert_eq!(egraph.add(term), root);
+                });
+            });
+        });
+    }
+}
@@ -57,6 +57,16 @@ impl FreeCache
         }
     }
 
+    /// Whether the free variables cache is known to contain no variables.
+    ///
+    /// If this returns true, the object has no free variables at all,
+    /// not even ones with De Bruijn indices larger than 15.
+    #[inline]
+    pub fn is_empty(self) -> bool
+    {
+        self.bits == Self::EMPTY.bits
+    }
+
     /// Check whether the free variables cache contains a variable.
     ///
     /// If the free variables cache is in the “unknown” state,
@@ -0,0 +1,571 @@
+use crate::heap::GrowableScope;
+use crate::heap::Heap;
+use crate::heap::HeapMap;
+use crate::heap::HeapVec;
+use crate::heap::ScopedHandle;
+use super::DeBruijn;
+use super::Metavariable;
+use super::NodeId;
+use super::Visitor;
+
+use core::mem;
+
+/// Identifies an equivalence class of an [`EGraph`].
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ClassId(pub u32);
+
+/// Compact representation of a set of equivalent terms.
+///
+/// An e-graph consists of equivalence classes of nodes.
+/// A node is either an object without children,
+/// or an application or cons cell whose fields
+/// are equivalence classes rather than terms.
+/// A class therefore represents all the terms
+/// that can be obtained by choosing a node from it,
+/// and recursively terms from the classes of the node.
+///
+/// Terms are [added][`Self::add`] to the e-graph,
+/// and classes are declared equivalent with [`union`][`Self::union`].
+/// Applications whose fields become equivalent are equivalent too,
+/// but this is only taken into account after [`rebuild`][`Self::rebuild`],
+/// so that many unions can be performed before paying for it.
+/// Finally, the best term of a class can be
+/// [extracted][`Self::extract`] back onto the heap.
+///
+/// Custom objects and gensyms are only equivalent to themselves,
+/// unless declared otherwise.
+pub struct EGraph<'g, 'h>
+{
+    /// Objects without children that occur in nodes.
+    leaves: GrowableScope<'g, 'h>,
+
+    /// Index into `leaves` of each object without children.
+    leaf_indices: HeapMap<'h, Leaf<'h>, usize>,
+
+    /// Every node with its class, as of the last rebuild or add.
+    nodes: HeapVec<'h, (Node<'h>, ClassId)>,
+
+    /// The class of every node, for finding existing nodes.
+    classes: HeapMap<'h, Node<'h>, ClassId>,
+
+    /// Union-find forest of classes, with the size of each tree.
+    parents: HeapVec<'h, ClassId>,
+    sizes: HeapVec<'h, u32>,
+}
+
+/// Node of an [`EGraph`], as passed to the cost function of
+/// [`EGraph::extract`].
+pub enum ENode<'a, 'h>
+{
+    /// Object without children.
+    Leaf(ScopedHandle<'h, 'a>),
+
+    /// Application with the given classes as fields.
+    ///
+    /// The first class is that of the function,
+    /// and the others are those of the arguments.
+    Application(&'a [ClassId]),
+
+    /// Cons cell with the given classes as head and tail.
+    Cons(ClassId, ClassId),
+}
+
+/// Identity of an object without children.
+#[derive(Eq, Hash, PartialEq)]
+enum Leaf<'h>
+{
+    Symbol(HeapVec<'h, u8>),
+    QualifiedSymbol(HeapVec<'h, u8>, HeapVec<'h, u8>),
+    Variable(u32),
+    Metavariable(u32),
+    Char(char),
+    /// Custom objects and gensyms, by address.
+    Unique(usize),
+}
+
+#[derive(Clone, Eq, Hash, PartialEq)]
+enum Node<'h>
+{
+    /// Index into [`EGraph::leaves`].
+    Leaf(usize),
+
+    /// Classes of the function and the arguments.
+    Application(HeapVec<'h, ClassId>),
+
+    /// Classes of the head and the tail.
+    Cons([ClassId; 2]),
+}
+
+impl<'h> Node<'h>
+{
+    /// Classes of the fields of the node.
+    fn fields(&self) -> &[ClassId]
+    {
+        match self {
+            Self::Leaf(_) => &[],
+            Self::Application(fields) => fields,
+            Self::Cons(fields) => fields,
+        }
+    }
+}
+
+impl<'h> Heap<'h>
+{
+    /// Create an empty e-graph and pass it to the given function.
+    ///
+    /// The objects in the e-graph are kept alive
+    /// until the given function returns or panics.
+    pub fn with_new_egraph<F, R>(&self, then: F) -> R
+        where F: for<'g> FnOnce(&mut EGraph<'g, 'h>) -> R
+    {
+        self.with_new_growable_scope(|leaves| {
+            then(&mut EGraph{
+                leaves,
+                leaf_indices: HeapMap::new(self),
+                nodes: self.new_vec(),
+                classes: HeapMap::new(self),
+                parents: self.new_vec(),
+                sizes: self.new_vec(),
+            })
+        })
+    }
+}
+
+impl<'g, 'h> EGraph<'g, 'h>
+{
+    /// Add the given term to the e-graph and return its class.
+    ///
+    /// Subterms that are already in the e-graph are not added again.
+    pub fn add(&mut self, term: ScopedHandle<'h, '_>) -> ClassId
+    {
+        let heap = self.leaves.heap();
+        let mut adder = Adder{egraph: self, classes: heap.new_vec()};
+        heap.walk(term, &mut adder);
+        adder.classes[0]
+    }
+
+    /// The canonical class of the classes equivalent to the given class.
+    pub fn find(&self, mut class: ClassId) -> ClassId
+    {
+        while self.parents[class.0 as usize] != class {
+            class = self.parents[class.0 as usize];
+        }
+        class
+    }
+
+    /// Whether two classes are equivalent.
+    #[inline]
+    pub fn equivalent(&self, a: ClassId, b: ClassId) -> bool
+    {
+        self.find(a) == self.find(b)
+    }
+
+    /// The number of distinct classes in the e-graph.
+    pub fn num_classes(&self) -> usize
+    {
+        (0 .. self.parents.len())
+            .filter(|&i| self.parents[i].0 as usize == i)
+            .count()
+    }
+
+    /// Declare two classes equivalent.
+    ///
+    /// This returns false if they were already equivalent.
+    /// Call [`rebuild`][`Self::rebuild`] before relying on
+    /// the equivalence of applications of these classes.
+    pub fn union(&mut self, a: ClassId, b: ClassId) -> bool
+    {
+        let a = self.find(a);
+        let b = self.find(b);
+        if a == b {
+            return false;
+        }
+
+        // Attach the smaller tree to the larger tree.
+        let (root, child) =
+            if self.sizes[a.0 as usize] >= self.sizes[b.0 as usize] {
+                (a, b)
+            } else {
+                (b, a)
+            };
+        self.parents[child.0 as usize] = root;
+        self.sizes[root.0 as usize] += self.sizes[child.0 as usize];
+        true
+    }
+
+    /// Make applications with equivalent fields equivalent.
+    ///
+    /// This repeats until no more classes become equivalent,
+    /// as merging classes may make more applications equivalent.
+    pub fn rebuild(&mut self)
+    {
+        loop {
+            let mut changed = false;
+
+            // Equal nodes are merged, so only one of each remains.
+            self.classes.clear();
+            let heap = self.leaves.heap();
+            let nodes = mem::replace(&mut self.nodes, heap.new_vec());
+            for (mut node, class) in nodes {
+                self.canonicalize(&mut node);
+                let class = self.find(class);
+                match self.classes.get(&node) {
+                    Some(&other) => changed |= self.union(class, other),
+                    None => {
+                        self.nodes.push((node.clone(), class));
+                        self.classes.insert(node, class);
+                    },
+                }
+            }
+
+            if !changed {
+                break;
+            }
+        }
+    }
+
+    /// Create the cheapest term of the given class.
+    ///
+    /// The cost function gives the cost of a node by itself,
+    /// and the cost of a term is the sum of the costs of its nodes.
+    /// The created term is written to `into`, and its cost is returned.
+    /// Costs add up without overflowing, saturating at [`u64::MAX`].
+    ///
+    /// The e-graph must have been [rebuilt][`Self::rebuild`]
+    /// since the last union, or the result may not be the cheapest.
+    pub fn extract(
+        &self,
+        into: ScopedHandle<'h, '_>,
+        class: ClassId,
+        mut cost: impl FnMut(ENode<'_, 'h>) -> u64,
+    ) -> u64
+    {
+        let heap = self.leaves.heap();
+        let leaves = self.leaves.as_scope();
+        let mut costs = heap.new_vec();
+        costs.extend(
+            self.nodes.iter().map(|(node, _)| match node {
+                Node::Leaf(i) => cost(ENode::Leaf(leaves.get(*i).unwrap())),
+                Node::Application(fields) => cost(ENode::Application(fields)),
+                Node::Cons([head, tail]) => cost(ENode::Cons(*head, *tail)),
+            })
+        );
+
+        // For each class, the cheapest node and the cost of its term.
+        // Iterate until the costs no longer improve.
+        let mut best: HeapVec<Option<(u64, usize)>> = heap.new_vec();
+        best.resize(self.parents.len(), None);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (index, (node, class)) in self.nodes.iter().enumerate() {
+                let mut fields = node.fields().iter();
+                let total = fields.try_fold(costs[index], |acc, &field| {
+                    let (field_cost, _) = best[self.find(field).0 as usize]?;
+                    Some(acc.saturating_add(field_cost))
+                });
+                let class = self.find(*class).0 as usize;
+                if let Some(total) = total {
+                    let improves = match best[class] {
+                        Some((cost, _)) => total < cost,
+                        None => true,
+                    };
+                    if improves {
+                        best[class] = Some((total, index));
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        let best_node = |class: ClassId| {
+            let (_, index) = best[self.find(class).0 as usize]
+                .expect("Class has no finite term");
+            &self.nodes[index].0
+        };
+
+        // Create the terms of the classes in postorder,
+        // reusing the terms of classes that were already created.
+        heap.with_new_term_builder(|builder| {
+            // The node of each canonical class that was created.
+            let mut created: HeapVec<Option<NodeId>> = heap.new_vec();
+            created.resize(self.parents.len(), None);
+            let created_node = |created: &[Option<NodeId>], class| {
+                created[self.find(class).0 as usize].unwrap()
+            };
+
+            let mut stack = heap.new_vec();
+            stack.push((self.find(class), false));
+            let mut arguments = heap.new_vec();
+            while let Some((class, expanded)) = stack.pop() {
+                if created[class.0 as usize].is_some() {
+                    continue;
+                }
+                match best_node(class) {
+                    Node::Leaf(i) => {
+                        let node = builder.handle(leaves.get(*i).unwrap());
+                        created[class.0 as usize] = Some(node);
+                    },
+                    node if !expanded => {
+                        stack.push((class, true));
+                        stack.extend(
+                            node.fields().iter()
+                                .map(|&field| (self.find(field), false))
+                        );
+                    },
+                    Node::Application(fields) => {
+                        arguments.clear();
+                        arguments.extend(
+                            fields[1 ..].iter()
+                                .map(|&field| created_node(&created, field))
+                        );
+                        let function = created_node(&created, fields[0]);
+                        let node = builder.app(function, &arguments).expect(
+                            "Application has as many arguments as before",
+                        );
+                        created[class.0 as usize] = Some(node);
+                    },
+                    Node::Cons([head, tail]) => {
+                        let head = created_node(&created, *head);
+                        let tail = created_node(&created, *tail);
+                        let node = builder.cons(head, tail);
+                        created[class.0 as usize] = Some(node);
+                    },
+                }
+            }
+            builder.build(created_node(&created, class), into);
+        });
+
+        best[self.find(class).0 as usize].unwrap().0
+    }
+
+    /// Replace the fields of the node by their canonical classes.
+    fn canonicalize(&self, node: &mut Node)
+    {
+        let fields = match node {
+            Node::Leaf(_) => return,
+            Node::Application(fields) => &mut fields[..],
+            Node::Cons(fields) => &mut fields[..],
+        };
+        for field in fields {
+            *field = self.find(*field);
+        }
+    }
+
+    /// Add a node whose fields are already in the e-graph.
+    fn add_node(&mut self, mut node: Node<'h>) -> ClassId
+    {
+        self.canonicalize(&mut node);
+        if let Some(&class) = self.classes.get(&node) {
+            return self.find(class);
+        }
+
+        let class = ClassId(self.parents.len() as u32);
+        self.parents.push(class);
+        self.sizes.push(1);
+        self.nodes.push((node.clone(), class));
+        self.classes.insert(node, class);
+        class
+    }
+}
+
+/// Visitor that adds the nodes of a term, for [`EGraph::add`].
+struct Adder<'a, 'g, 'h>
+{
+    egraph: &'a mut EGraph<'g, 'h>,
+
+    /// Classes of the children of the objects being walked.
+    classes: HeapVec<'h, ClassId>,
+}
+
+impl<'a, 'g, 'h> Visitor<'h> for Adder<'a, 'g, 'h>
+{
+    fn pre(&mut self, object: ScopedHandle<'h, '_>) -> bool
+    {
+        // Other objects are leaves, whose children are not added.
+        object.with_pin(|object| object.num_rebuilt_fields().is_some())
+    }
+
+    fn post(&mut self, object: ScopedHandle<'h, '_>)
+    {
+        // Annotations are transparent,
+        // so an annotated term is in the class of the term.
+        if object.with_pin(|object| object.as_annotated().is_some()) {
+            return;
+        }
+
+        let (num_fields, is_cons) = object.with_pin(|object| {
+            (object.num_rebuilt_fields(), object.as_cons().is_some())
+        });
+
+        let node = match num_fields {
+            Some(num_fields) => {
+                let start = self.classes.len() - num_fields;
+                let fields = self.classes.split_off(start);
+                if is_cons {
+                    Node::Cons([fields[0], fields[1]])
+                } else {
+                    Node::Application(fields)
+                }
+            },
+            None => {
+                let leaf = leaf(self.egraph.leaves.heap(), object);
+                let index = match self.egraph.leaf_indices.get(&leaf) {
+                    Some(&index) => index,
+                    None => {
+                        let leaves = &mut self.egraph.leaves;
+                        let index = leaves.push();
+                        leaves.as_scope().get(index).unwrap().copy_from(object);
+                        self.egraph.leaf_indices.insert(leaf, index);
+                        index
+                    },
+                };
+                Node::Leaf(index)
+            },
+        };
+
+        let class = self.egraph.add_node(node);
+        self.classes.push(class);
+    }
+}
+
+/// The identity of an object without children.
+fn leaf<'h>(heap: &Heap<'h>, object: ScopedHandle<'h, '_>) -> Leaf<'h>
+{
+    let copy = |bytes: &[u8]| {
+        let mut copy = heap.new_vec();
+        copy.extend_from_slice(bytes);
+        copy
+    };
+
+    if let Some(DeBruijn(de_bruijn)) = object.as_variable() {
+        return Leaf::Variable(de_bruijn);
+    }
+    if let Some(Metavariable(m)) = object.as_metavariable() {
+        return Leaf::Metavariable(m);
+    }
+    if let Some(char) = object.as_char() {
+        return Leaf::Char(char);
+    }
+    let is_gensym = object.is_gensym();
+    object.with_pin(|object| {
+        match object.as_symbol() {
+            Some(name) if !is_gensym => Leaf::Symbol(copy(name)),
+            _ => match object.as_qualified_symbol() {
+                Some((namespace, name)) =>
+                    Leaf::QualifiedSymbol(copy(namespace), copy(name)),
+                None => {
+                    let address = object.as_unsafe_handle().as_ptr();
+                    Leaf::Unique(address as usize)
+                },
+            },
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::term;
+    use crate::testing::TermTree;
+
+    /// Every node costs one.
+    fn size(_: ENode) -> u64
+    {
+        1
+    }
+
+    #[test]
+    fn congruence()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[a, b, fa, fb]| {
+                term!(heap, a, (G A));
+                term!(heap, b, B);
+                term!(heap, fa, (F (G A) C));
+                term!(heap, fb, (F B C));
+
+                heap.with_new_egraph(|egraph| {
+                    let a = egraph.add(a);
+                    let b = egraph.add(b);
+                    let fa = egraph.add(fa);
+                    let fb = egraph.add(fb);
+                    assert!(!egraph.equivalent(fa, fb));
+
+                    assert!(egraph.union(a, b));
+                    assert!(!egraph.union(b, a));
+                    egraph.rebuild();
+                    assert!(egraph.equivalent(fa, fb));
+                });
+            });
+        });
+    }
+
+    #[test]
+    fn sharing()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[term, result]| {
+                term!(heap, term, (F (G #0) (G #0) ?0));
+                heap.with_new_egraph(|egraph| {
+                    let root = egraph.add(term);
+                    // F, G, #0, (G #0), ?0, and the whole term.
+                    assert_eq!(egraph.num_classes(), 6);
+                    assert_eq!(egraph.extract(result, root, size), 9);
+                });
+                assert_eq!(
+                    TermTree::read(heap, result),
+                    TermTree::read(heap, term),
+                );
+            });
+        });
+    }
+
+    #[test]
+    fn extract()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[long, short, result, expected]| {
+                // (Mul X Two) is equivalent to (Shl X One).
+                term!(heap, long, (Add (Mul X Two) (Mul X Two)));
+                term!(heap, short, (Shl X One));
+
+                heap.with_new_egraph(|egraph| {
+                    let root = egraph.add(long);
+                    let shl = egraph.add(short);
+                    assert_eq!(egraph.add(long), root);
+
+                    // Make the multiplication artificially expensive.
+                    let cost = |node: ENode| match node {
+                        ENode::Leaf(leaf) => leaf.with_pin(|leaf| {
+                            match leaf.as_symbol() {
+                                Some(b"Mul") => 10,
+                                _ => 1,
+                            }
+                        }),
+                        ENode::Application(_) | ENode::Cons(..) => 1,
+                    };
+
+                    assert_eq!(egraph.extract(result, root, cost), 28);
+
+                    heap.with_new_array_scope(|[mul_term]| {
+                        term!(heap, mul_term, (Mul X Two));
+                        let mul = egraph.add(mul_term);
+                        egraph.union(mul, shl);
+                    });
+                    egraph.rebuild();
+
+                    assert_eq!(egraph.extract(result, root, cost), 10);
+                    assert_eq!(egraph.extract(result, shl, cost), 4);
+                });
+
+                term!(heap, expected, (Shl X One));
+                assert_eq!(
+                    TermTree::read(heap, result),
+                    TermTree::read(heap, expected),
+                );
+            });
+        });
+    }
+}
@@ -0,0 +1,271 @@
+use crate::heap::AllocError;
+use crate::heap::Heap;
+use crate::heap::PinnedHandle;
+use crate::heap::Scope;
+use crate::heap::ScopedHandle;
+use crate::heap::UnsafeHandle;
+use super::DeBruijn;
+use super::Flags;
+use super::FreeCache;
+use super::Header;
+use super::Kind;
+use super::Payload;
+
+use core::cell::Cell;
+use core::iter::TrustedLen;
+use core::iter;
+use core::mem::MaybeUninit;
+use core::mem::size_of;
+use core::slice;
+
+/// Raised when attempting to create an environment frame
+/// with too many slots.
+#[derive(Debug)]
+pub struct NumSlotsError;
+
+/// Raised when [`Heap::try_new_env_frame`] fails.
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub enum TryNewEnvFrameError
+{
+    NumSlots(NumSlotsError),
+    Alloc(AllocError),
+}
+
+impl From<NumSlotsError> for TryNewEnvFrameError
+{
+    fn from(other: NumSlotsError) -> Self
+    {
+        Self::NumSlots(other)
+    }
+}
+
+impl From<AllocError> for TryNewEnvFrameError
+{
+    fn from(other: AllocError) -> Self
+    {
+        Self::Alloc(other)
+    }
+}
+
+/// Convenient constant for computing payload size.
+const PTR_SIZE: u32 = size_of::<UnsafeHandle>() as u32;
+
+/// The payload stores the parent and the slots.
+fn payload_size(num_slots: usize) -> Result<u32, NumSlotsError>
+{
+    const ERR: NumSlotsError = NumSlotsError;
+    let as_u32: u32 = num_slots.try_into().map_err(|_| ERR)?;
+    let num_fields = as_u32.checked_add(1).ok_or(ERR)?;
+    num_fields.checked_mul(PTR_SIZE).ok_or(ERR)
+}
+
+/// Methods for creating and searching environment frames.
+///
+/// An environment maps the variables of a term to their values,
+/// so that an evaluator need not substitute values into terms.
+/// It is a chain of frames, each of which holds the values
+/// bound by one binder, and points to the frame of the enclosing binder.
+/// The chain ends at the first parent that is not a frame;
+/// by convention this is the interned [`Nil`][`super::WellKnown::Nil`]
+/// symbol, which is the empty environment.
+///
+/// The values of a frame are stored from the outermost to the innermost,
+/// so the last value is bound to De Bruijn index 0.
+/// Extending a frame with a single value thus behaves
+/// like a binder that binds a single variable.
+impl<'h> Heap<'h>
+{
+    /// Create an environment frame that extends the given parent
+    /// with the given values.
+    #[inline]
+    pub fn new_env_frame<'s, I>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        parent: ScopedHandle<'h, 's>,
+        values: impl IntoIterator<IntoIter=I>,
+    ) -> Result<(), NumSlotsError>
+        where I: ExactSizeIterator<Item=ScopedHandle<'h, 's>> + TrustedLen
+    {
+        let values = values.into_iter();
+        let payload_size = payload_size(values.len())?;
+        unsafe {
+            self.new(into, payload_size as usize, |payload| {
+                init_env_frame(payload, payload_size, parent, values)
+            });
+        }
+        Ok(())
+    }
+
+    /// Similar to [`new_env_frame`][`Self::new_env_frame`],
+    /// but return an error if memory cannot be allocated.
+    #[inline]
+    pub fn try_new_env_frame<'s, I>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        parent: ScopedHandle<'h, 's>,
+        values: impl IntoIterator<IntoIter=I>,
+    ) -> Result<(), TryNewEnvFrameError>
+        where I: ExactSizeIterator<Item=ScopedHandle<'h, 's>> + TrustedLen
+    {
+        let values = values.into_iter();
+        let payload_size = payload_size(values.len())?;
+        unsafe {
+            self.try_new(into, payload_size as usize, |payload| {
+                init_env_frame(payload, payload_size, parent, values)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Find the value of the variable with the given De Bruijn index.
+    ///
+    /// This takes time linear in the number of frames
+    /// that are skipped to find the variable.
+    /// The value is written to `into` and true is returned.
+    /// If the environment does not bind the variable,
+    /// `into` is left unchanged and false is returned.
+    pub fn env_lookup<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        env: ScopedHandle<'h, 's>,
+        de_bruijn: DeBruijn,
+    ) -> bool
+    {
+        self.with_new_array_scope(|[frame]| {
+            frame.copy_from(env);
+            let mut index = de_bruijn.0 as usize;
+            let mut found = false;
+            loop {
+                // Look in the frame, or find the frame to look in next.
+                let parent = frame.with_pin(|frame| {
+                    let (parent, values) = frame.as_env_frame()?;
+                    match values.len().checked_sub(index + 1) {
+                        Some(slot) => {
+                            into.copy_from(values.get(slot).unwrap());
+                            found = true;
+                            None
+                        },
+                        None => {
+                            index -= values.len();
+                            Some(parent.as_unsafe_handle())
+                        },
+                    }
+                });
+                match parent {
+                    // SAFETY: The parent is reachable from the frame.
+                    Some(p) => unsafe { frame.copy_from_unsafe_handle(p) },
+                    None => return found,
+                }
+            }
+        })
+    }
+}
+
+/// Initialize an environment frame with the given parent and values.
+///
+/// # Safety
+///
+/// The payload size must have been computed by [`payload_size`]
+/// from the number of values, and the payload must be that large.
+unsafe fn init_env_frame<'h, 's>(
+    payload: *mut Payload,
+    payload_size: u32,
+    parent: ScopedHandle<'h, 's>,
+    values: impl Iterator<Item=ScopedHandle<'h, 's>>,
+) -> Header
+{
+    // The extra field stores the number of slots.
+    let num_slots = payload_size / PTR_SIZE - 1;
+    let mut extra = MaybeUninit::uninit_array();
+    MaybeUninit::write_slice(&mut extra, &num_slots.to_ne_bytes());
+
+    // The payload first stores the parent,
+    // then all the values in order.
+    let mut free_cache = FreeCache::EMPTY;
+    let payload = payload as *mut Cell<UnsafeHandle>;
+    let fields = iter::once(parent).chain(values);
+    for (i, field) in fields.enumerate() {
+        free_cache |= field.header().free_cache;
+        *payload.add(i) = Cell::new(field.as_unsafe_handle());
+    }
+
+    Header{
+        kind: Kind::EnvFrame,
+        flags: Flags::empty(),
+        free_cache,
+        extra,
+    }
+}
+
+/// Methods for inspecting environment frames.
+impl<'h, 'p> PinnedHandle<'h, 'p>
+{
+    /// Get the parent and the values of the environment frame.
+    ///
+    /// If the object is not an environment frame,
+    /// this method returns [`None`].
+    #[inline]
+    pub fn as_env_frame(self) -> Option<(ScopedHandle<'h, 'p>, &'p Scope<'h>)>
+    {
+        let header = self.header();
+        match header.kind {
+            Kind::EnvFrame => {
+                let extra = header.extra;
+                let extra = unsafe { MaybeUninit::array_assume_init(extra) };
+                let num_slots = u32::from_ne_bytes(extra);
+                let fields = self.payload() as *const Cell<UnsafeHandle>;
+
+                // SAFETY: The parent is always there.
+                let parent = unsafe { &*fields };
+                let values = unsafe {
+                    slice::from_raw_parts(fields.add(1), num_slots as usize)
+                };
+
+                // SAFETY: The handles reside in a pinned object.
+                let parent = unsafe { ScopedHandle::new(parent) };
+                let values = unsafe { Scope::new(values) };
+
+                Some((parent, values))
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::object::WellKnown;
+
+    #[test]
+    fn env_lookup()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[a, b, c, outer, inner, value]| {
+                heap.new_symbol(a, b"A").unwrap();
+                heap.new_symbol(b, b"B").unwrap();
+                heap.new_symbol(c, b"C").unwrap();
+
+                // SAFETY: Interned objects are not destroyed.
+                let nil = heap.interned_symbol(WellKnown::Nil);
+                unsafe { outer.copy_from_unsafe_handle(nil) };
+                heap.new_env_frame(outer, outer, [a, b]).unwrap();
+                heap.new_env_frame(inner, outer, [c]).unwrap();
+
+                for (i, expected) in [c, b, a].into_iter().enumerate() {
+                    let i = DeBruijn(i as u32);
+                    assert!(heap.env_lookup(value, inner, i));
+                    assert!(value.ptr_eq(expected));
+                }
+
+                value.copy_from(c);
+                assert!(!heap.env_lookup(value, inner, DeBruijn(3)));
+                assert!(value.ptr_eq(c));
+
+                heap.verify();
+            });
+        });
+    }
+}
@@ -0,0 +1,142 @@
+use crate::heap::Heap;
+use crate::heap::HeapVec;
+use crate::heap::ScopedHandle;
+use super::Flags;
+use super::FreeCache;
+use super::Header;
+use super::Kind;
+use super::Payload;
+use super::SymbolLenError;
+use super::TryNewSymbolError;
+use super::symbol::init_large_name;
+use super::symbol::large_payload_size;
+
+use core::mem::MaybeUninit;
+
+/// Methods for creating fresh symbols.
+///
+/// Code generators must often invent names
+/// that cannot clash with names already in use.
+/// A gensym is a symbol that is only equal to itself:
+/// structural comparisons, such as [`Heap::match_pattern`],
+/// treat it as distinct from every other symbol, even one with its name.
+/// Its name consists of the given prefix and a number
+/// that is unique within the heap, so that gensyms can be told apart
+/// when they are printed.
+impl<'h> Heap<'h>
+{
+    /// Create a gensym whose name starts with the given prefix.
+    pub fn new_gensym<'s>(&self, into: ScopedHandle<'h, 's>, prefix: &[u8])
+        -> Result<(), SymbolLenError>
+    {
+        let name = self.gensym_name(prefix);
+        let payload_size = large_payload_size(&name)?;
+        unsafe {
+            self.new(into, payload_size, |payload| {
+                init_gensym(payload, &name)
+            });
+        }
+        Ok(())
+    }
+
+    /// Similar to [`new_gensym`][`Self::new_gensym`],
+    /// but return an error if memory cannot be allocated.
+    pub fn try_new_gensym<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        prefix: &[u8],
+    ) -> Result<(), TryNewSymbolError>
+    {
+        let name = self.gensym_name(prefix);
+        let payload_size = large_payload_size(&name)?;
+        unsafe {
+            self.try_new(into, payload_size, |payload| {
+                init_gensym(payload, &name)
+            })?;
+        }
+        Ok(())
+    }
+
+    fn gensym_name(&self, prefix: &[u8]) -> HeapVec<'h, u8>
+    {
+        // The decimal digits of the number, least significant first.
+        let mut number = self.next_gensym_number();
+        let mut digits = [0; 20];
+        let mut len = 0;
+        loop {
+            digits[len] = b'0' + (number % 10) as u8;
+            len += 1;
+            number /= 10;
+            if number == 0 {
+                break;
+            }
+        }
+
+        let mut name = self.new_vec();
+        name.reserve(prefix.len() + 1 + len);
+        name.extend_from_slice(prefix);
+        name.push(b'%');
+        name.extend(digits[.. len].iter().rev());
+        name
+    }
+}
+
+/// Initialize a gensym object with the given name.
+///
+/// # Safety
+///
+/// See [`init_large_name`].
+unsafe fn init_gensym(payload: *mut Payload, name: &[u8]) -> Header
+{
+    init_large_name(payload, name);
+    Header{
+        kind: Kind::Gensym,
+        flags: Flags::empty(),
+        free_cache: FreeCache::EMPTY,
+        extra: MaybeUninit::uninit_array(),
+    }
+}
+
+/// Methods for inspecting gensym objects.
+impl<'h, 's> ScopedHandle<'h, 's>
+{
+    /// Whether the object is a gensym.
+    #[inline]
+    pub fn is_gensym(self) -> bool
+    {
+        self.header().kind == Kind::Gensym
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn gensym()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_boxed_scope(2, |bindings| {
+            heap.with_new_array_scope(|[a, b, symbol]| {
+                heap.new_gensym(a, b"x").unwrap();
+                heap.new_gensym(b, b"x").unwrap();
+                assert!(a.is_gensym());
+                a.with_pin(|a| b.with_pin(|b| {
+                    assert_eq!(a.as_symbol(), Some(&b"x%0"[..]));
+                    assert_eq!(b.as_symbol(), Some(&b"x%1"[..]));
+                }));
+
+                // A symbol with the same name is a different symbol.
+                heap.new_symbol(symbol, b"x%0").unwrap();
+                assert!(!symbol.is_gensym());
+                assert!(!heap.match_pattern(a, symbol, bindings));
+                assert!(heap.match_pattern(a, a, bindings));
+                assert_ne!(
+                    heap.structural_hash(a),
+                    heap.structural_hash(symbol),
+                );
+            }); });
+        });
+    }
+}
@@ -0,0 +1,204 @@
+use crate::heap::Heap;
+use crate::heap::ScopedHandle;
+use super::DeBruijn;
+use super::Metavariable;
+use super::Visitor;
+
+/// Methods for hashing terms.
+impl<'h> Heap<'h>
+{
+    /// Compute a hash of the structure of the given term.
+    ///
+    /// Structurally equal terms have equal hashes,
+    /// regardless of whether they share objects,
+    /// so the hash can be used to look up terms by their contents.
+    /// Custom objects and gensyms are only equal to themselves,
+    /// so they are hashed by their address.
+    ///
+    /// For terms without custom objects and gensyms,
+    /// the hash is the same on every run of the program;
+    /// for other terms it depends on where those objects were allocated.
+    /// It is not cryptographically secure.
+    /// Computing it takes time proportional to the size of the term,
+    /// counting shared subterms once for every path to them.
+    /// With the `wide-header` feature, the hash is cached in the object,
+    /// so that hashing the same object again takes constant time.
+    pub fn structural_hash<'s>(&self, term: ScopedHandle<'h, 's>) -> u64
+    {
+        if let Some(hash) = cached_hash(term) {
+            return hash;
+        }
+        let mut hasher = Hasher(FNV_OFFSET_BASIS);
+        self.walk(term, &mut hasher);
+        cache_hash(term, hasher.0);
+        hasher.0
+    }
+}
+
+/// The structural hash cached in the header extension, if any.
+#[cfg(feature = "wide-header")]
+fn cached_hash(term: ScopedHandle) -> Option<u64>
+{
+    let object = term.as_unsafe_handle().as_ptr();
+    // SAFETY: The object is reachable from the scope.
+    let hash = unsafe { (*object).extension.structural_hash.get() };
+    (hash != 0).then_some(hash)
+}
+
+/// Cache the structural hash in the header extension.
+///
+/// Objects never change, so neither do their hashes.
+/// A hash of zero means no hash was cached, so it is not cached.
+#[cfg(feature = "wide-header")]
+fn cache_hash(term: ScopedHandle, hash: u64)
+{
+    let object = term.as_unsafe_handle().as_ptr();
+    // SAFETY: The object is reachable from the scope.
+    unsafe { (*object).extension.structural_hash.set(hash) };
+}
+
+#[cfg(not(feature = "wide-header"))]
+fn cached_hash(_term: ScopedHandle) -> Option<u64>
+{
+    None
+}
+
+#[cfg(not(feature = "wide-header"))]
+fn cache_hash(_term: ScopedHandle, _hash: u64)
+{
+}
+
+/// Hash an object given the hashes of its children.
+///
+/// This is consistent with structural equality,
+/// like [`Heap::structural_hash`], but with a different result,
+/// and it can be computed bottom-up without walking the children again.
+pub (super) fn hash_object(object: ScopedHandle, children: &[u64]) -> u64
+{
+    // Annotations are transparent.
+    if object.with_pin(|object| object.as_annotated().is_some()) {
+        return children[0];
+    }
+
+    let mut hasher = Hasher(FNV_OFFSET_BASIS);
+    hasher.pre(object);
+    for child in children {
+        hasher.write(&child.to_le_bytes());
+    }
+    hasher.0
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Visitor that computes the FNV-1a hash of a term in preorder,
+/// for [`Heap::structural_hash`].
+///
+/// Each object contributes a tag and its contents.
+/// Applications contribute their number of arguments,
+/// so that the preorder sequence determines the term.
+struct Hasher(u64);
+
+impl Hasher
+{
+    fn write(&mut self, bytes: &[u8])
+    {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+}
+
+impl<'h> Visitor<'h> for Hasher
+{
+    fn pre(&mut self, object: ScopedHandle<'h, '_>) -> bool
+    {
+        if let Some(DeBruijn(de_bruijn)) = object.as_variable() {
+            self.write(b"V");
+            self.write(&de_bruijn.to_le_bytes());
+            return true;
+        }
+
+        if let Some(Metavariable(m)) = object.as_metavariable() {
+            self.write(b"M");
+            self.write(&m.to_le_bytes());
+            return true;
+        }
+
+        if let Some(char) = object.as_char() {
+            self.write(b"U");
+            self.write(&(char as u32).to_le_bytes());
+            return true;
+        }
+
+        let is_gensym = object.is_gensym();
+        object.with_pin(|object| {
+            if let (Some(name), false) = (object.as_symbol(), is_gensym) {
+                self.write(b"S");
+                self.write(&(name.len() as u64).to_le_bytes());
+                self.write(name);
+            } else if let Some((namespace, name)) =
+                object.as_qualified_symbol()
+            {
+                self.write(b"Q");
+                self.write(&(namespace.len() as u64).to_le_bytes());
+                self.write(namespace);
+                self.write(&(name.len() as u64).to_le_bytes());
+                self.write(name);
+            } else if let Some((_, arguments)) = object.as_application() {
+                self.write(b"A");
+                self.write(&(arguments.len() as u64).to_le_bytes());
+            } else if object.as_cons().is_some() {
+                self.write(b"L");
+            } else if object.as_annotated().is_some() {
+                // Annotations are transparent.
+            } else {
+                let address = object.as_unsafe_handle().as_ptr() as usize;
+                self.write(b"C");
+                self.write(&(address as u64).to_le_bytes());
+            }
+        });
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::term;
+
+    #[test]
+    fn structural_hash()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[a, b, c, d]| {
+                term!(heap, a, (F (G #0) ?1 Hello));
+                term!(heap, b, (F (G #0) ?1 Hello));
+                term!(heap, c, (F (G #0) ?1 Hallo));
+                term!(heap, d, ((F (G #0)) ?1 Hello));
+                assert!(!a.ptr_eq(b));
+                assert_eq!(heap.structural_hash(a), heap.structural_hash(b));
+                assert_ne!(heap.structural_hash(a), heap.structural_hash(c));
+                assert_ne!(heap.structural_hash(a), heap.structural_hash(d));
+            });
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "wide-header")]
+    fn cached()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[term]| {
+                term!(heap, term, (F (G #0) ?1 Hello));
+                assert_eq!(cached_hash(term), None);
+                let hash = heap.structural_hash(term);
+                assert_eq!(cached_hash(term), Some(hash));
+                assert_eq!(heap.structural_hash(term), hash);
+            });
+        });
+    }
+}
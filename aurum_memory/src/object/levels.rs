@@ -0,0 +1,225 @@
+use crate::heap::GrowableScope;
+use crate::heap::Heap;
+use crate::heap::HeapMap;
+use crate::heap::ScopedHandle;
+use super::DeBruijn;
+use super::Visitor;
+
+/// Methods for converting between De Bruijn indices and levels.
+///
+/// A De Bruijn index counts binders from the variable outwards,
+/// whereas a De Bruijn level counts binders from the outside inwards.
+/// Levels do not change when a term is moved under more binders,
+/// which some algorithms, such as normalization by evaluation, rely on.
+/// Levels are stored in variable objects, just like indices;
+/// which of the two a term uses is up to the caller.
+impl<'h> Heap<'h>
+{
+    /// Convert the De Bruijn indices in the term to De Bruijn levels.
+    ///
+    /// `depth` is the number of binders around the term.
+    /// Subterms without variables are shared with the original,
+    /// as are applications none of whose fields changed.
+    /// A subterm that is shared in the original
+    /// is converted once and shared in the result.
+    ///
+    /// # Panics
+    ///
+    /// If a variable does not refer to one of the `depth` binders,
+    /// this method panics.
+    pub fn indices_to_levels<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        term: ScopedHandle<'h, 's>,
+        depth: u32,
+    )
+    {
+        self.flip_variables(into, term, depth);
+    }
+
+    /// Convert the De Bruijn levels in the term to De Bruijn indices.
+    ///
+    /// This is the inverse of
+    /// [`indices_to_levels`][`Self::indices_to_levels`],
+    /// and it has the same panics.
+    pub fn levels_to_indices<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        term: ScopedHandle<'h, 's>,
+        depth: u32,
+    )
+    {
+        // Both conversions map i to depth - 1 - i.
+        self.flip_variables(into, term, depth);
+    }
+
+    fn flip_variables<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        term: ScopedHandle<'h, 's>,
+        depth: u32,
+    )
+    {
+        self.with_new_growable_scope(|results| {
+        self.with_new_growable_scope(|converted| {
+            let mut flipper = Flipper{
+                heap: self,
+                depth,
+                results,
+                converted,
+                cache: HeapMap::new(self),
+            };
+            self.walk(term, &mut flipper);
+            into.copy_from(flipper.results.as_scope().get(0).unwrap());
+        })
+        });
+    }
+}
+
+/// Visitor that maps each variable _i_ to _depth_ - 1 - _i_,
+/// for [`Heap::flip_variables`].
+struct Flipper<'a, 'g, 'h>
+{
+    heap: &'a Heap<'h>,
+    depth: u32,
+
+    /// Converted children of the objects being walked.
+    results: GrowableScope<'g, 'h>,
+
+    /// Every subterm with variables that was converted so far.
+    converted: GrowableScope<'g, 'h>,
+
+    /// Indices into `converted`, by address of the original subterm.
+    cache: HeapMap<'h, usize, usize>,
+}
+
+impl<'a, 'g, 'h> Visitor<'h> for Flipper<'a, 'g, 'h>
+{
+    fn pre(&mut self, object: ScopedHandle<'h, '_>) -> bool
+    {
+        // Subterms without variables need not be visited,
+        // and neither do subterms that were already converted.
+        !object.header().free_cache.is_empty()
+            && !self.cache.contains_key(&address(object))
+            && object.with_pin(|object| object.num_rebuilt_fields().is_some())
+    }
+
+    fn post(&mut self, object: ScopedHandle<'h, '_>)
+    {
+        let index = self.results.push();
+        let results = self.results.as_scope();
+        let result = results.get(index).unwrap();
+        result.copy_from(object);
+
+        if object.header().free_cache.is_empty() {
+            return;
+        }
+
+        let address = address(object);
+        if let Some(&cached) = self.cache.get(&address) {
+            result.copy_from(self.converted.as_scope().get(cached).unwrap());
+            return;
+        }
+
+        if let Some(DeBruijn(i)) = object.as_variable() {
+            let flipped = self.depth.checked_sub(1)
+                .and_then(|max| max.checked_sub(i))
+                .expect("Variable is not bound within the given depth");
+            self.heap.new_variable(result, DeBruijn(flipped));
+        } else {
+            let num_fields =
+                match object.with_pin(|object| object.num_rebuilt_fields()) {
+                    Some(num_fields) => num_fields,
+                    None => return,
+                };
+
+            let start = index - num_fields;
+            let fields = (start .. index).map(|i| results.get(i).unwrap());
+            self.heap.new_rebuilt_if_changed(result, object, fields);
+            results.get(start).unwrap().copy_from(result);
+
+            for _ in 0 .. num_fields {
+                self.results.pop();
+            }
+        }
+
+        let results = self.results.as_scope();
+        let result = results.get(results.len() - 1).unwrap();
+        let cached = self.converted.push();
+        self.converted.as_scope().get(cached).unwrap().copy_from(result);
+        self.cache.insert(address, cached);
+    }
+}
+
+fn address(object: ScopedHandle) -> usize
+{
+    object.as_unsafe_handle().as_ptr() as usize
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::term;
+    use crate::testing::TermTree;
+
+    #[test]
+    fn indices_to_levels()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[closed, term, levels, expected]| {
+                term!(heap, closed, (G A));
+                term!(heap, term, (F #0 {closed} (H #2 #1)));
+                heap.indices_to_levels(levels, term, 3);
+                term!(heap, expected, (F #2 (G A) (H #0 #1)));
+                assert_eq!(
+                    TermTree::read(heap, levels),
+                    TermTree::read(heap, expected),
+                );
+
+                // Subterms without variables are shared.
+                levels.with_pin(|levels| {
+                    let (_, arguments) = levels.as_application().unwrap();
+                    assert!(arguments.get(1).unwrap().ptr_eq(closed));
+                });
+
+                heap.levels_to_indices(levels, levels, 3);
+                assert_eq!(
+                    TermTree::read(heap, levels),
+                    TermTree::read(heap, term),
+                );
+            });
+        });
+    }
+
+    #[test]
+    fn shared()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[inner, term, levels]| {
+                term!(heap, inner, (G #0));
+                term!(heap, term, (F {inner} {inner}));
+                heap.indices_to_levels(levels, term, 2);
+                levels.with_pin(|levels| {
+                    let (_, arguments) = levels.as_application().unwrap();
+                    let first = arguments.get(0).unwrap();
+                    let second = arguments.get(1).unwrap();
+                    assert!(!first.ptr_eq(inner));
+                    assert!(first.ptr_eq(second));
+                });
+            });
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Variable is not bound within the given depth")]
+    fn unbound()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[term]| {
+                term!(heap, term, (F #3));
+                heap.indices_to_levels(term, term, 3);
+            });
+        });
+    }
+}
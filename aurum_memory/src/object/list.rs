@@ -0,0 +1,295 @@
+use crate::heap::AllocError;
+use crate::heap::Heap;
+use crate::heap::PinnedHandle;
+use crate::heap::Scope;
+use crate::heap::ScopedHandle;
+use crate::heap::UnsafeHandle;
+use super::Flags;
+use super::Header;
+use super::Kind;
+use super::Payload;
+use super::WellKnown;
+
+use core::cell::Cell;
+use core::mem::MaybeUninit;
+use core::mem::size_of;
+
+// The payload stores the head and the tail.
+const PAYLOAD_SIZE: usize = 2 * size_of::<UnsafeHandle>();
+
+/// Methods for creating cons cells and lists.
+///
+/// A cons cell pairs a head with a tail.
+/// A list is a chain of cons cells whose last tail is
+/// the interned [`WellKnown::Nil`] symbol, with the elements as heads.
+/// Encoding a list as applications of [`WellKnown::Cons`]
+/// would cost an extra field per element,
+/// and patterns would have to spell out the function of every cell.
+impl<'h> Heap<'h>
+{
+    /// Create a cons cell with the given head and tail.
+    #[inline]
+    pub fn new_cons<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        head: ScopedHandle<'h, 's>,
+        tail: ScopedHandle<'h, 's>,
+    )
+    {
+        unsafe {
+            self.new(into, PAYLOAD_SIZE, |payload| {
+                init_cons(payload, head, tail)
+            });
+        }
+    }
+
+    /// Similar to [`new_cons`][`Self::new_cons`],
+    /// but return an error if memory cannot be allocated.
+    #[inline]
+    pub fn try_new_cons<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        head: ScopedHandle<'h, 's>,
+        tail: ScopedHandle<'h, 's>,
+    ) -> Result<(), AllocError>
+    {
+        unsafe {
+            self.try_new(into, PAYLOAD_SIZE, |payload| {
+                init_cons(payload, head, tail)
+            })
+        }
+    }
+
+    /// Create a list with the given elements.
+    ///
+    /// If there are no elements, `into` is set to
+    /// the interned [`WellKnown::Nil`] symbol.
+    pub fn new_list<'s, I>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        elements: impl IntoIterator<IntoIter=I>,
+    )
+        where I: DoubleEndedIterator<Item=ScopedHandle<'h, 's>>
+    {
+        // The list is built in a separate handle,
+        // in case `into` is also one of the elements.
+        self.with_new_array_scope(|[list]| {
+            // SAFETY: Interned objects are not destroyed.
+            let nil = self.interned_symbol(WellKnown::Nil);
+            unsafe { list.copy_from_unsafe_handle(nil) };
+            for element in elements.into_iter().rev() {
+                self.new_cons(list, element, list);
+            }
+            into.copy_from(list);
+        });
+    }
+
+    /// Similar to [`new_list`][`Self::new_list`],
+    /// but return an error if memory cannot be allocated.
+    ///
+    /// If an error is returned, `into` is left unchanged.
+    pub fn try_new_list<'s, I>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        elements: impl IntoIterator<IntoIter=I>,
+    ) -> Result<(), AllocError>
+        where I: DoubleEndedIterator<Item=ScopedHandle<'h, 's>>
+    {
+        self.with_new_array_scope(|[list]| {
+            // SAFETY: Interned objects are not destroyed.
+            let nil = self.interned_symbol(WellKnown::Nil);
+            unsafe { list.copy_from_unsafe_handle(nil) };
+            for element in elements.into_iter().rev() {
+                self.try_new_cons(list, element, list)?;
+            }
+            into.copy_from(list);
+            Ok(())
+        })
+    }
+
+    /// Create a list with the objects referred to by the given scope
+    /// as elements.
+    #[inline]
+    pub fn new_list_from_scope<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        elements: &'s Scope<'h>,
+    )
+    {
+        let elements = (0 .. elements.len()).map(|i| elements.get(i).unwrap());
+        self.new_list(into, elements);
+    }
+
+    /// Similar to [`new_list_from_scope`][`Self::new_list_from_scope`],
+    /// but return an error if memory cannot be allocated.
+    #[inline]
+    pub fn try_new_list_from_scope<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        elements: &'s Scope<'h>,
+    ) -> Result<(), AllocError>
+    {
+        let elements = (0 .. elements.len()).map(|i| elements.get(i).unwrap());
+        self.try_new_list(into, elements)
+    }
+
+    /// Find the elements of a list.
+    ///
+    /// This follows the tail of the term
+    /// for as long as it is a cons cell.
+    /// The heads of the cons cells are the elements,
+    /// and the tail that is not a cons cell is written to `tail_into`;
+    /// for a list created by [`new_list`][`Self::new_list`],
+    /// this is the interned [`WellKnown::Nil`] symbol.
+    ///
+    /// The elements are written to the first handles of `elements`,
+    /// as many as fit, and the total number of elements is returned.
+    /// Passing an empty scope thus computes the length of the list.
+    pub fn unfold_list<'s>(
+        &self,
+        term: ScopedHandle<'h, 's>,
+        tail_into: ScopedHandle<'h, 's>,
+        elements: &Scope<'h>,
+    ) -> usize
+    {
+        self.with_new_array_scope(|[cursor]| {
+            cursor.copy_from(term);
+            let mut num_elements = 0;
+            loop {
+                let tail = cursor.with_pin(|cursor| {
+                    let (head, tail) = cursor.as_cons()?;
+                    if let Some(element) = elements.get(num_elements) {
+                        element.copy_from(head);
+                    }
+                    Some(tail.as_unsafe_handle())
+                });
+                match tail {
+                    // SAFETY: The tail is reachable from the cursor.
+                    Some(t) => unsafe { cursor.copy_from_unsafe_handle(t) },
+                    None => break,
+                }
+                num_elements += 1;
+            }
+            tail_into.copy_from(cursor);
+            num_elements
+        })
+    }
+}
+
+/// Initialize a cons cell with the given head and tail.
+///
+/// # Safety
+///
+/// The payload must be [`PAYLOAD_SIZE`] bytes large.
+unsafe fn init_cons<'h, 's>(
+    payload: *mut Payload,
+    head: ScopedHandle<'h, 's>,
+    tail: ScopedHandle<'h, 's>,
+) -> Header
+{
+    // The payload stores the head, then the tail.
+    let payload = payload as *mut Cell<UnsafeHandle>;
+    *payload = Cell::new(head.as_unsafe_handle());
+    *payload.add(1) = Cell::new(tail.as_unsafe_handle());
+
+    Header{
+        kind: Kind::Cons,
+        flags: Flags::empty(),
+        free_cache: head.header().free_cache | tail.header().free_cache,
+        extra: MaybeUninit::uninit_array(),
+    }
+}
+
+/// Methods for inspecting cons cells.
+impl<'h, 'p> PinnedHandle<'h, 'p>
+{
+    /// Get the head and the tail of the cons cell.
+    ///
+    /// If the object is not a cons cell, this method returns [`None`].
+    #[inline]
+    pub fn as_cons(self) -> Option<(ScopedHandle<'h, 'p>, ScopedHandle<'h, 'p>)>
+    {
+        match self.header().kind {
+            Kind::Cons => {
+                let fields = self.payload() as *const Cell<UnsafeHandle>;
+                // SAFETY: The handles reside in a pinned object.
+                let head = unsafe { ScopedHandle::new(&*fields) };
+                let tail = unsafe { ScopedHandle::new(&*fields.add(1)) };
+                Some((head, tail))
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::object::DeBruijn;
+    use crate::object::Metavariable;
+    use crate::term;
+
+    #[test]
+    fn new_list()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_boxed_scope(3, |elements| {
+            heap.with_new_array_scope(|[list, tail, x]| {
+                term!(heap, x, (F #0));
+                let [a, b, c] = [0, 1, 2].map(|i| elements.get(i).unwrap());
+                heap.new_symbol(a, b"A").unwrap();
+                heap.new_variable(b, DeBruijn(2));
+                c.copy_from(x);
+                heap.new_list_from_scope(list, elements);
+                assert_eq!(
+                    list.header().free_cache.contains(DeBruijn(2)),
+                    Some(true),
+                );
+
+                // Unfold into a fresh scope and compare.
+                heap.with_new_boxed_scope(3, |unfolded| {
+                    assert_eq!(heap.unfold_list(list, tail, unfolded), 3);
+                    assert!(tail.as_unsafe_handle() ==
+                        heap.interned_symbol(WellKnown::Nil));
+                    for (a, b) in elements.iter().zip(unfolded.iter()) {
+                        assert!(a.ptr_eq(b));
+                    }
+                });
+
+                // Too few handles still yields the length.
+                heap.with_new_boxed_scope(1, |unfolded| {
+                    assert_eq!(heap.unfold_list(list, tail, unfolded), 3);
+                    assert!(unfolded.get(0).unwrap().ptr_eq(a));
+                });
+
+                heap.new_list(list, []);
+                assert_eq!(heap.unfold_list(list, tail, elements), 0);
+                assert!(tail.ptr_eq(list));
+
+                heap.verify();
+            }); });
+        });
+    }
+
+    #[test]
+    fn match_cons()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_boxed_scope(1, |bindings| {
+            heap.with_new_array_scope(|[a, b, list, pattern, tail]| {
+                heap.new_symbol(a, b"A").unwrap();
+                heap.new_symbol(b, b"B").unwrap();
+                heap.new_list(list, [a, b]);
+                heap.new_metavariable(tail, Metavariable(0));
+                heap.new_cons(pattern, a, tail);
+                assert!(heap.match_pattern(pattern, list, bindings));
+                bindings.get(0).unwrap().with_pin(|rest| {
+                    let (head, _) = rest.as_cons().unwrap();
+                    assert!(head.ptr_eq(b));
+                });
+                assert!(!heap.match_pattern(pattern, b, bindings));
+            }); });
+        });
+    }
+}
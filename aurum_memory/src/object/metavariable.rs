@@ -0,0 +1,118 @@
+use crate::heap::AllocError;
+use crate::heap::Heap;
+use crate::heap::ScopedHandle;
+use super::Flags;
+use super::FreeCache;
+use super::Header;
+use super::Kind;
+use super::Payload;
+
+use core::mem::MaybeUninit;
+
+/// Identifies a metavariable.
+///
+/// Metavariables stand for arbitrary terms in patterns,
+/// such as the left-hand sides of rewrite rules.
+/// Unlike variables, they are not bound by anything in the term.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Metavariable(pub u32);
+
+// Metavariables store all info in the header.
+const PAYLOAD_SIZE: usize = 0;
+
+/// Methods for creating metavariable objects.
+impl<'h> Heap<'h>
+{
+    /// Create a metavariable object.
+    #[inline]
+    pub fn new_metavariable<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        metavariable: Metavariable,
+    )
+    {
+        unsafe {
+            self.new(into, PAYLOAD_SIZE, |payload| {
+                init_metavariable(payload, metavariable)
+            });
+        }
+    }
+
+    /// Similar to [`new_metavariable`][`Self::new_metavariable`],
+    /// but return an error if memory cannot be allocated.
+    #[inline]
+    pub fn try_new_metavariable<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        metavariable: Metavariable,
+    ) -> Result<(), AllocError>
+    {
+        unsafe {
+            self.try_new(into, PAYLOAD_SIZE, |payload| {
+                init_metavariable(payload, metavariable)
+            })
+        }
+    }
+}
+
+/// Initialize a metavariable object.
+fn init_metavariable(_payload: *mut Payload, metavariable: Metavariable)
+    -> Header
+{
+    // The metavariable is stored in the extra field.
+    let mut extra = MaybeUninit::uninit_array();
+    let extra_bytes = metavariable.0.to_ne_bytes();
+    MaybeUninit::write_slice(&mut extra, &extra_bytes);
+
+    Header{
+        kind: Kind::Metavariable,
+        flags: Flags::empty(),
+        free_cache: FreeCache::EMPTY,
+        extra,
+    }
+}
+
+/// Methods for inspecting metavariable objects.
+impl<'h, 's> ScopedHandle<'h, 's>
+{
+    /// Get the metavariable of the metavariable object.
+    ///
+    /// If the object is not a metavariable, this method returns [`None`].
+    #[inline]
+    pub fn as_metavariable(self) -> Option<Metavariable>
+    {
+        let header = self.header();
+        match header.kind {
+            Kind::Metavariable => {
+                let extra = header.extra;
+                let extra = unsafe { MaybeUninit::array_assume_init(extra) };
+                Some(Metavariable(u32::from_ne_bytes(extra)))
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    use proptest::proptest;
+
+    proptest!
+    {
+        #[test]
+        fn roundtrip(metavariable: u32)
+        {
+            let metavariable = Metavariable(metavariable);
+            Heap::with_new(|heap| {
+                heap.with_new_array_scope(|[handle]| {
+                    heap.new_metavariable(handle, metavariable);
+                    assert_eq!(handle.as_metavariable(), Some(metavariable));
+                    assert_eq!(handle.as_variable(), None);
+                });
+            });
+        }
+    }
+}
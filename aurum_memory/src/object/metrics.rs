@@ -0,0 +1,134 @@
+use crate::heap::Heap;
+use crate::heap::HeapMap;
+use crate::heap::HeapVec;
+use crate::heap::ScopedHandle;
+use super::Visitor;
+
+/// Measurements of a term, as returned by [`Heap::term_size`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TermMetrics
+{
+    /// The number of objects in the term,
+    /// counting shared objects once for every path to them.
+    ///
+    /// This is the size the term would have without any sharing.
+    /// It saturates at [`usize::MAX`],
+    /// as sharing can make it exponential in the number of objects.
+    pub nodes: usize,
+
+    /// The number of objects on the longest path from the root,
+    /// including the root itself.
+    pub depth: usize,
+
+    /// The number of distinct objects in the term.
+    ///
+    /// This is the size of the term as it is stored on the heap.
+    pub shared_nodes: usize,
+}
+
+/// Methods for measuring terms.
+impl<'h> Heap<'h>
+{
+    /// Measure the size and depth of the given term.
+    ///
+    /// Every distinct object is visited once,
+    /// so this is fast even if [`TermMetrics::nodes`] is huge.
+    pub fn term_size<'s>(&self, term: ScopedHandle<'h, 's>) -> TermMetrics
+    {
+        let mut measurer = Measurer{
+            children: self.new_vec(),
+            seen: HeapMap::new(self),
+        };
+        self.walk(term, &mut measurer);
+
+        let (nodes, depth) = measurer.children[0];
+        TermMetrics{nodes, depth, shared_nodes: measurer.seen.len()}
+    }
+}
+
+/// Visitor that measures terms, for [`Heap::term_size`].
+struct Measurer<'h>
+{
+    /// Tree size and depth of the children of the objects being walked.
+    children: HeapVec<'h, (usize, usize)>,
+
+    /// Tree size and depth of every object that was measured,
+    /// by address.
+    seen: HeapMap<'h, usize, (usize, usize)>,
+}
+
+impl<'h> Visitor<'h> for Measurer<'h>
+{
+    fn pre(&mut self, object: ScopedHandle<'h, '_>) -> bool
+    {
+        !self.seen.contains_key(&address(object))
+    }
+
+    fn post(&mut self, object: ScopedHandle<'h, '_>)
+    {
+        let address = address(object);
+        if let Some(&measured) = self.seen.get(&address) {
+            self.children.push(measured);
+            return;
+        }
+
+        let mut num_children = 0;
+        object.with_pin(|object| {
+            object.visit_children(|_| num_children += 1);
+        });
+
+        let start = self.children.len() - num_children;
+        let measured = self.children.drain(start ..)
+            .fold((1usize, 1), |(nodes, depth), (child_nodes, child_depth)| {
+                (nodes.saturating_add(child_nodes), depth.max(1 + child_depth))
+            });
+
+        self.seen.insert(address, measured);
+        self.children.push(measured);
+    }
+}
+
+fn address(object: ScopedHandle) -> usize
+{
+    object.as_unsafe_handle().as_ptr() as usize
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::term;
+
+    #[test]
+    fn term_size()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[inner, term]| {
+                term!(heap, inner, (G A B));
+                term!(heap, term, (F {inner} {inner} (H {inner})));
+                assert_eq!(
+                    heap.term_size(term),
+                    TermMetrics{nodes: 16, depth: 4, shared_nodes: 8},
+                );
+            });
+        });
+    }
+
+    #[test]
+    fn exponential()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[f, term]| {
+                heap.new_symbol(f, b"F").unwrap();
+                heap.new_symbol(term, b"X").unwrap();
+                for _ in 0 .. 100 {
+                    heap.new_application(term, f, [term, term]).unwrap();
+                }
+                let metrics = heap.term_size(term);
+                assert_eq!(metrics.nodes, usize::MAX);
+                assert_eq!(metrics.depth, 101);
+                assert_eq!(metrics.shared_nodes, 102);
+            });
+        });
+    }
+}
@@ -1,19 +1,73 @@
 //! In-memory representation of objects.
 
+pub use self::annotated::*;
 pub use self::application::*;
+pub use self::builder::*;
+pub use self::custom::*;
 pub use self::de_bruijn::*;
+pub use self::egraph::*;
+pub use self::env::*;
+pub use self::metavariable::*;
+pub use self::metrics::*;
+pub use self::path::*;
+pub use self::rewrite::*;
+pub use self::send::*;
+pub use self::serialize::*;
+#[cfg(feature = "serde")]
+pub use self::serializable::*;
 pub use self::symbol::*;
+pub use self::unify::*;
 pub use self::variable::*;
+pub use self::walk::*;
+pub use self::zipper::*;
 
+pub (crate) use self::pattern::terms_equal;
+
+use self::qualified::qualified_symbol_lens;
+
+use crate::heap::Heap;
 use crate::heap::HeapId;
+use crate::heap::PinnedHandle;
+use crate::heap::ScopedHandle;
+use crate::heap::UnsafeHandle;
 
 use bitflags::bitflags;
+use core::cell::Cell;
+use core::iter::TrustedLen;
 use core::mem::MaybeUninit;
+use core::mem::size_of;
 
+mod annotated;
 mod application;
+mod builder;
+mod character;
+mod cse;
+mod custom;
 mod de_bruijn;
+mod egraph;
+mod env;
+mod gensym;
+mod levels;
+mod list;
+mod hash;
+mod metavariable;
+mod metrics;
+mod occurrences;
+mod path;
+mod pattern;
+mod qualified;
+mod reduced;
+mod rewrite;
+mod send;
+mod serialize;
+#[cfg(feature = "serde")]
+mod serializable;
 mod symbol;
+mod unify;
+mod user_flags;
 mod variable;
+mod walk;
+mod zipper;
 
 /// In-memory representation of an object.
 #[repr(C, align(8))]
@@ -25,10 +79,48 @@ pub struct Object<'h>
     /// See [`Header`].
     pub header: Header,
 
+    /// See [`HeaderExtension`].
+    #[cfg(feature = "wide-header")]
+    pub extension: HeaderExtension,
+
     /// See [`Payload`].
     pub payload: Payload,
 }
 
+/// The number of bytes that precede the payload of each object.
+///
+/// This is the size of the [`Header`], which is 8 bytes,
+/// plus that of the [`HeaderExtension`] if there is one.
+pub const HEADER_SIZE: usize =
+    size_of::<Header>() + size_of::<HeaderExtension>();
+
+/// Metadata that follows the header of each object,
+/// when the `wide-header` feature is enabled.
+///
+/// This makes the header 16 bytes rather than 8,
+/// which costs memory but leaves room for caches.
+/// Without the feature, this type is empty and unused.
+#[repr(C, align(8))]
+pub struct HeaderExtension
+{
+    /// The result of [`Heap::structural_hash`] for the object,
+    /// or zero if it was not computed yet.
+    #[cfg(feature = "wide-header")]
+    pub structural_hash: Cell<u64>,
+}
+
+impl HeaderExtension
+{
+    /// The extension of a new object, with empty caches.
+    pub const fn empty() -> Self
+    {
+        Self{
+            #[cfg(feature = "wide-header")]
+            structural_hash: Cell::new(0),
+        }
+    }
+}
+
 /// Metadata at the very start of each object.
 #[repr(C, align(8))]
 #[derive(Clone, Copy)]
@@ -50,14 +142,309 @@ pub struct Header
     pub extra: [MaybeUninit<u8>; 4],
 }
 
+/// Methods for inspecting any object.
+impl<'h, 'p> PinnedHandle<'h, 'p>
+{
+    /// The number of bytes occupied by the payload of the object.
+    ///
+    /// This is computed from the kind and the extra field of the object,
+    /// and for some kinds of objects from the payload itself.
+    #[inline]
+    pub fn payload_size(self) -> usize
+    {
+        let header = self.header();
+        match header.kind {
+            Kind::Symbol => {
+                // The extra field stores the length of the name.
+                let extra = header.extra;
+                let extra = unsafe { MaybeUninit::array_assume_init(extra) };
+                u32::from_ne_bytes(extra) as usize
+            },
+            Kind::LargeSymbol | Kind::Gensym => {
+                // The payload stores the length of the name.
+                let name_len = unsafe { *(self.payload() as *const u64) };
+                size_of::<u64>() + name_len as usize
+            },
+            Kind::SmallSymbol => 0,
+            Kind::QualifiedSymbol => {
+                let (namespace_len, name_len) = unsafe {
+                    qualified_symbol_lens(&header, self.payload())
+                };
+                size_of::<u32>() + namespace_len + name_len
+            },
+            Kind::Variable => 0,
+            Kind::Metavariable => 0,
+            Kind::Char => 0,
+            Kind::Application => {
+                // The extra field stores the number of fields.
+                let extra = header.extra;
+                let extra = unsafe { MaybeUninit::array_assume_init(extra) };
+                u32::from_ne_bytes(extra) as usize * size_of::<UnsafeHandle>()
+            },
+            Kind::Cons => 2 * size_of::<UnsafeHandle>(),
+            Kind::Annotated => size_of::<UnsafeHandle>() + 2 * size_of::<u32>(),
+            Kind::EnvFrame => {
+                // The extra field stores the number of slots,
+                // which come after the parent.
+                let extra = header.extra;
+                let extra = unsafe { MaybeUninit::array_assume_init(extra) };
+                (1 + u32::from_ne_bytes(extra) as usize)
+                    * size_of::<UnsafeHandle>()
+            },
+            Kind::Custom => unsafe {
+                let payload = self.payload();
+                let descriptor = custom_descriptor(payload);
+                let payload_size = descriptor.payload_size;
+                size_of::<*const KindDescriptor>()
+                    + payload_size(custom_payload(payload))
+            },
+        }
+    }
+}
+
+/// Call the given function for every handle embedded in an object.
+///
+/// This is the only place that knows where each kind of object
+/// stores handles to other objects.
+/// Code that needs to find all the children of an object,
+/// such as traversals and the garbage collector, must use this function.
+/// The handles are visited in the order in which they are stored.
+///
+/// # Safety
+///
+/// The header and payload must be those of the same object,
+/// and the object must not be destroyed while `f` is called.
+pub unsafe fn visit_children<'h, 'o>(
+    header: &Header,
+    payload: *mut Payload,
+    mut f: impl FnMut(&'o Cell<UnsafeHandle<'h>>),
+)
+    where 'h: 'o
+{
+    match header.kind {
+        Kind::Symbol | Kind::LargeSymbol | Kind::SmallSymbol => (),
+        Kind::Gensym => (),
+        Kind::QualifiedSymbol => (),
+        Kind::Variable | Kind::Metavariable => (),
+        Kind::Char => (),
+        Kind::Application => {
+            // The extra field stores the number of fields,
+            // and the payload stores the fields.
+            let extra = MaybeUninit::array_assume_init(header.extra);
+            let num_fields = u32::from_ne_bytes(extra);
+            let fields = payload as *const Cell<UnsafeHandle<'h>>;
+            for i in 0 .. num_fields as usize {
+                f(&*fields.add(i));
+            }
+        },
+        Kind::Cons => {
+            // The payload stores the head, then the tail.
+            let fields = payload as *const Cell<UnsafeHandle<'h>>;
+            f(&*fields);
+            f(&*fields.add(1));
+        },
+        Kind::Annotated => {
+            // The payload starts with the term.
+            f(&*(payload as *const Cell<UnsafeHandle<'h>>));
+        },
+        Kind::EnvFrame => {
+            // The payload stores the parent, then the slots.
+            let extra = MaybeUninit::array_assume_init(header.extra);
+            let num_slots = u32::from_ne_bytes(extra);
+            let fields = payload as *const Cell<UnsafeHandle<'h>>;
+            for i in 0 ..= num_slots as usize {
+                f(&*fields.add(i));
+            }
+        },
+        Kind::Custom => {
+            let descriptor = custom_descriptor(payload);
+            (descriptor.visit_children)(custom_payload(payload), &mut |child| {
+                // SAFETY: The child lives as long as the object.
+                f(&*(child as *const Cell<UnsafeHandle<'h>>))
+            });
+        },
+    }
+}
+
+/// Methods for finding the children of any object.
+impl<'h, 'p> PinnedHandle<'h, 'p>
+{
+    /// Call the given function for every handle embedded in the object.
+    ///
+    /// See [`visit_children`] for more information.
+    #[inline]
+    pub fn visit_children(self, mut f: impl FnMut(ScopedHandle<'h, 'p>))
+        where 'h: 'p
+    {
+        let header = self.header();
+        // SAFETY: The object is pinned, and so are its children.
+        unsafe {
+            visit_children(&header, self.payload(), |child| {
+                f(ScopedHandle::new(child))
+            });
+        }
+    }
+
+    /// The number of children of the object,
+    /// if it is an application, a cons cell, or annotated.
+    ///
+    /// Traversals that rebuild terms, such as substitution,
+    /// recreate these objects with new children
+    /// using [`Heap::new_rebuilt`].
+    /// Other objects, such as custom objects, are left alone.
+    pub (crate) fn num_rebuilt_fields(self) -> Option<usize>
+    {
+        if let Some((_, arguments)) = self.as_application() {
+            return Some(1 + arguments.len());
+        }
+        if self.as_annotated().is_some() {
+            return Some(1);
+        }
+        self.as_cons().map(|_| 2)
+    }
+}
+
+/// Methods for rebuilding objects.
+impl<'h> Heap<'h>
+{
+    /// Create an object of the same kind as `like`,
+    /// but with the given children.
+    ///
+    /// # Panics
+    ///
+    /// If `like` is not an application, a cons cell, or annotated,
+    /// or the number of children differs from that of `like`,
+    /// this method panics.
+    pub (crate) fn new_rebuilt<'s, I>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        like: ScopedHandle<'h, '_>,
+        mut fields: I,
+    )
+        where I: ExactSizeIterator<Item=ScopedHandle<'h, 's>> + TrustedLen
+    {
+        let num_fields = like.with_pin(|like| like.num_rebuilt_fields());
+        assert_eq!(
+            Some(fields.len()),
+            num_fields,
+            "Object has as many children as before",
+        );
+
+        let first = fields.next().unwrap();
+        let (is_cons, span) = like.with_pin(|like| {
+            (like.as_cons().is_some(), like.as_annotated().map(|(_, s)| s))
+        });
+        if let Some(span) = span {
+            self.new_annotated(into, first, span);
+        } else if is_cons {
+            self.new_cons(into, first, fields.next().unwrap());
+        } else {
+            self.new_application(into, first, fields)
+                .expect("Application has as many arguments as before");
+        }
+    }
+
+    /// Like [`new_rebuilt`][`Self::new_rebuilt`],
+    /// but if every given child is the same object
+    /// as the corresponding child of `like`,
+    /// `like` is shared instead of recreated.
+    ///
+    /// `into` may be one of the given children.
+    ///
+    /// # Panics
+    ///
+    /// The same as for [`new_rebuilt`][`Self::new_rebuilt`].
+    pub (crate) fn new_rebuilt_if_changed<'s, I>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        like: ScopedHandle<'h, '_>,
+        fields: I,
+    )
+        where I: Clone
+               + ExactSizeIterator<Item=ScopedHandle<'h, 's>>
+               + TrustedLen
+    {
+        let mut unchanged = true;
+        let mut field = fields.clone();
+        like.with_pin(|like| {
+            like.visit_children(|child| {
+                unchanged &= matches!(field.next(), Some(f) if child.ptr_eq(f));
+            });
+        });
+
+        if unchanged && field.next().is_none() {
+            into.copy_from(like);
+        } else {
+            self.new_rebuilt(into, like, fields);
+        }
+    }
+}
+
 /// Determines the types of the extra and payload fields of the object.
 #[allow(missing_docs)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub enum Kind
 {
     Symbol,
+
+    /// Symbol whose name is too long for its length to fit in the extra field.
+    ///
+    /// The payload stores the length of the name as a [`u64`],
+    /// followed by the bytes of the name.
+    LargeSymbol,
+
+    /// Symbol whose name is stored in the extra field.
+    ///
+    /// Names of at most four bytes are stored this way,
+    /// unless they end in a zero byte, as the name is padded with zeroes.
+    /// Such symbols have no payload.
+    SmallSymbol,
+
+    /// Symbol with a namespace.
+    ///
+    /// The extra field stores the length of the namespace.
+    /// The payload stores the length of the name as a [`u32`],
+    /// followed by the bytes of the namespace and the name.
+    QualifiedSymbol,
+
+    /// Symbol created by [`new_gensym`][`crate::heap::Heap::new_gensym`],
+    /// which is only equal to itself regardless of its name.
+    ///
+    /// The payload is laid out as that of a [`Kind::LargeSymbol`],
+    /// whatever the length of the name.
+    Gensym,
+
     Variable,
     Application,
+    Metavariable,
+
+    /// Unicode scalar value, stored in the extra field.
+    Char,
+
+    /// Cons cell, the building block of lists.
+    ///
+    /// The payload stores the head, then the tail.
+    Cons,
+
+    /// Term with the location in source code that it came from.
+    ///
+    /// The extra field stores the file.
+    /// The payload stores the term, then the start and end offsets.
+    Annotated,
+
+    /// Environment frame, which binds variables to values.
+    ///
+    /// The extra field stores the number of slots.
+    /// The payload stores the parent frame, then the slots.
+    EnvFrame,
+
+    /// Object of a kind that is defined outside this crate.
+    ///
+    /// The extra field stores the [`CustomKind`],
+    /// which identifies the kind among those registered with the heap.
+    /// The payload stores a pointer to the [`KindDescriptor`],
+    /// followed by whatever the descriptor says.
+    Custom,
 }
 
 bitflags!
@@ -79,6 +466,56 @@ bitflags!
         /// the garbage collector will not
         /// destroy or relocate the object.
         const PINNED = 1 << 1;
+
+        /// Set on objects known to be in weak head normal form.
+        /// See [`ScopedHandle::mark_whnf`].
+        const WHNF = 1 << 2;
+
+        /// Set on objects known to be in normal form,
+        /// together with [`Flags::WHNF`].
+        /// See [`ScopedHandle::mark_normal_form`].
+        const NORMAL_FORM = 1 << 3;
+
+        /// Free for use by embedders.
+        /// See [`ScopedHandle::user_flags`].
+        const USER0 = 1 << 4;
+
+        /// Free for use by embedders.
+        /// See [`ScopedHandle::user_flags`].
+        const USER1 = 1 << 5;
+
+        /// These bits are not a flag but store a counter,
+        /// namely the number of garbage collection cycles
+        /// the object has survived, up to a maximum of [`Flags::MAX_AGE`].
+        /// The generational garbage collector uses this
+        /// to decide when to promote objects to an older generation.
+        /// Use [`Flags::age`] to read the counter.
+        const AGE = 0b11 << 6;
+    }
+}
+
+impl Flags
+{
+    /// The age at which the age counter stops incrementing.
+    pub const MAX_AGE: u8 = Self::AGE.bits >> 6;
+
+    /// The flags that are free for use by embedders.
+    pub const USER: Self = Self{bits: Self::USER0.bits | Self::USER1.bits};
+
+    /// The number of garbage collection cycles the object has survived,
+    /// up to a maximum of [`MAX_AGE`][`Self::MAX_AGE`].
+    #[inline]
+    pub fn age(self) -> u8
+    {
+        (self & Self::AGE).bits >> 6
+    }
+
+    /// Increment the age counter, unless it is already at its maximum.
+    #[inline]
+    pub fn increment_age(&mut self)
+    {
+        let age = Self::MAX_AGE.min(self.age() + 1);
+        self.bits = self.bits & !Self::AGE.bits | age << 6;
     }
 }
 
@@ -92,12 +529,66 @@ extern
 mod tests
 {
     use super::*;
-
-    use core::mem::size_of;
+    use crate::heap::Heap;
 
     #[test]
     fn header_size()
     {
         assert_eq!(size_of::<Header>(), 8);
+        #[cfg(feature = "wide-header")]
+        assert_eq!(HEADER_SIZE, 16);
+        #[cfg(not(feature = "wide-header"))]
+        assert_eq!(HEADER_SIZE, 8);
+    }
+
+    #[test]
+    fn age()
+    {
+        let mut flags = Flags::PINNED;
+        for age in 0 ..= Flags::MAX_AGE {
+            assert_eq!(flags.age(), age);
+            flags.increment_age();
+        }
+        assert_eq!(flags.age(), Flags::MAX_AGE);
+        assert_eq!(flags - Flags::AGE, Flags::PINNED);
+    }
+
+    #[test]
+    fn visit_children()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[f, x, application]| {
+                heap.new_symbol(f, b"F").unwrap();
+                heap.new_variable(x, DeBruijn(0));
+                heap.new_application(application, f, [x, f]).unwrap();
+                let mut children = alloc::vec::Vec::new();
+                for h in [f, x, application] {
+                    h.with_pin(|h| {
+                        h.visit_children(|child| {
+                            children.push(child.as_unsafe_handle());
+                        });
+                    });
+                }
+                let expected = [f, x, f].map(|h| h.as_unsafe_handle());
+                assert_eq!(children, expected);
+            });
+        });
+    }
+
+    #[test]
+    fn object_size()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[symbol, variable, application]| {
+                heap.new_symbol(symbol, b"Apply").unwrap();
+                heap.new_variable_not_interned(variable, DeBruijn(0));
+                heap.new_application(application, symbol, [variable, variable])
+                    .unwrap();
+                let size = |h: ScopedHandle| h.with_pin(|h| h.object_size());
+                assert_eq!(size(symbol), HEADER_SIZE + 5);
+                assert_eq!(size(variable), HEADER_SIZE);
+                assert_eq!(size(application), HEADER_SIZE + 24);
+            });
+        });
     }
 }
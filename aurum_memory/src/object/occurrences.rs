@@ -0,0 +1,243 @@
+use crate::heap::Heap;
+use crate::heap::HeapMap;
+use crate::heap::HeapVec;
+use crate::heap::ScopedHandle;
+use super::DeBruijn;
+use super::Visitor;
+
+use alloc::vec::Vec;
+
+/// Methods for counting occurrences of variables.
+///
+/// Passes that inline or drop bindings need to know
+/// whether a variable is used zero times, once, or more often.
+/// Occurrences are counted once for every path to them,
+/// so a variable in a shared subterm counts as often as it is shared.
+impl<'h> Heap<'h>
+{
+    /// The number of occurrences of the given variable in the term.
+    ///
+    /// Every distinct object is visited once,
+    /// and subterms whose [free variables cache][`super::FreeCache`]
+    /// rules out the variable are skipped.
+    /// The count saturates at [`usize::MAX`],
+    /// as sharing can make it exponential in the number of objects.
+    pub fn count_occurrences<'s>(
+        &self,
+        term: ScopedHandle<'h, 's>,
+        de_bruijn: DeBruijn,
+    ) -> usize
+    {
+        let mut counter = Counter{
+            de_bruijn,
+            children: self.new_vec(),
+            seen: HeapMap::new(self),
+        };
+        self.walk(term, &mut counter);
+        counter.children[0]
+    }
+
+    /// The number of occurrences of every variable in the term.
+    ///
+    /// Element _i_ of the result is the number of occurrences
+    /// of the variable with De Bruijn index _i_.
+    /// The result ends with the highest variable that occurs.
+    /// Like [`count_occurrences`][`Self::count_occurrences`],
+    /// this visits every distinct object once and the counts saturate.
+    pub fn count_all_occurrences<'s>(&self, term: ScopedHandle<'h, 's>)
+        -> Vec<usize>
+    {
+        let mut collector = Collector{
+            objects: self.new_vec(),
+            edges: self.new_vec(),
+            seen: HeapMap::new(self),
+        };
+        self.walk(term, &mut collector);
+
+        // Parents come after their children in post-order,
+        // so going backwards finds all paths to an object
+        // before passing them on to its children.
+        let Collector{objects, edges, ..} = collector;
+        let mut paths: HeapVec<usize> = self.new_vec();
+        paths.resize(objects.len(), 0);
+        paths[objects.len() - 1] = 1;
+
+        let mut counts = Vec::new();
+        for (index, object) in objects.iter().enumerate().rev() {
+            let start = index.checked_sub(1).map_or(0, |i| objects[i].edges);
+            for &child in &edges[start .. object.edges] {
+                paths[child] = paths[child].saturating_add(paths[index]);
+            }
+            if let Some(DeBruijn(i)) = object.variable {
+                let i = i as usize;
+                if counts.len() <= i {
+                    counts.resize(i + 1, 0);
+                }
+                counts[i] = paths[index].saturating_add(counts[i]);
+            }
+        }
+        counts
+    }
+}
+
+/// Visitor that counts one variable, for [`Heap::count_occurrences`].
+struct Counter<'h>
+{
+    de_bruijn: DeBruijn,
+
+    /// Occurrences in the children of the objects being walked.
+    children: HeapVec<'h, usize>,
+
+    /// Occurrences in every object that was counted, by address.
+    seen: HeapMap<'h, usize, usize>,
+}
+
+impl<'h> Counter<'h>
+{
+    fn skip(&self, object: ScopedHandle<'h, '_>) -> bool
+    {
+        object.header().free_cache.contains(self.de_bruijn) == Some(false)
+    }
+}
+
+impl<'h> Visitor<'h> for Counter<'h>
+{
+    fn pre(&mut self, object: ScopedHandle<'h, '_>) -> bool
+    {
+        !self.seen.contains_key(&address(object)) && !self.skip(object)
+    }
+
+    fn post(&mut self, object: ScopedHandle<'h, '_>)
+    {
+        let address = address(object);
+        if let Some(&count) = self.seen.get(&address) {
+            self.children.push(count);
+            return;
+        }
+        if self.skip(object) {
+            self.children.push(0);
+            return;
+        }
+
+        let mut num_children = 0;
+        object.with_pin(|object| {
+            object.visit_children(|_| num_children += 1);
+        });
+
+        let own = (object.as_variable() == Some(self.de_bruijn)) as usize;
+        let start = self.children.len() - num_children;
+        let count = self.children.drain(start ..)
+            .fold(own, usize::saturating_add);
+
+        self.seen.insert(address, count);
+        self.children.push(count);
+    }
+}
+
+/// Visitor that lists the distinct objects of a term,
+/// for [`Heap::count_all_occurrences`].
+struct Collector<'h>
+{
+    /// The distinct objects in post-order.
+    objects: HeapVec<'h, Collected>,
+
+    /// The indices of the children of every object in `objects`.
+    edges: HeapVec<'h, usize>,
+
+    /// The index in `objects` of every object that was collected,
+    /// by address.
+    seen: HeapMap<'h, usize, usize>,
+}
+
+struct Collected
+{
+    /// The De Bruijn index of the object, if it is a variable.
+    variable: Option<DeBruijn>,
+
+    /// Where the children of the object end in [`Collector::edges`].
+    /// They start where those of the previous object end.
+    edges: usize,
+}
+
+impl<'h> Visitor<'h> for Collector<'h>
+{
+    fn pre(&mut self, object: ScopedHandle<'h, '_>) -> bool
+    {
+        !self.seen.contains_key(&address(object))
+    }
+
+    fn post(&mut self, object: ScopedHandle<'h, '_>)
+    {
+        let key = address(object);
+        if self.seen.contains_key(&key) {
+            return;
+        }
+
+        let Self{edges, seen, ..} = self;
+        object.with_pin(|object| {
+            object.visit_children(|child| edges.push(seen[&address(child)]));
+        });
+
+        self.seen.insert(key, self.objects.len());
+        self.objects.push(Collected{
+            variable: object.as_variable(),
+            edges: self.edges.len(),
+        });
+    }
+}
+
+fn address(object: ScopedHandle) -> usize
+{
+    object.as_unsafe_handle().as_ptr() as usize
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::term;
+
+    #[test]
+    fn count_occurrences()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[inner, term]| {
+                term!(heap, inner, (G #0 #3));
+                term!(heap, term, (F #0 {inner} {inner} A));
+
+                let count = |i| heap.count_occurrences(term, DeBruijn(i));
+                assert_eq!(count(0), 3);
+                assert_eq!(count(1), 0);
+                assert_eq!(count(3), 2);
+                assert_eq!(count(100), 0);
+
+                assert_eq!(heap.count_all_occurrences(term), [3, 0, 0, 2]);
+            });
+        });
+    }
+
+    #[test]
+    fn exponential()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[f, x, term]| {
+                heap.new_symbol(f, b"F").unwrap();
+                heap.new_variable(x, DeBruijn(1));
+                heap.new_application(term, f, [x, x]).unwrap();
+                for _ in 0 .. 100 {
+                    heap.new_application(term, f, [term, term]).unwrap();
+                }
+                let count = heap.count_occurrences(term, DeBruijn(1));
+                assert_eq!(count, usize::MAX);
+                assert_eq!(heap.count_occurrences(term, DeBruijn(0)), 0);
+                assert_eq!(heap.count_all_occurrences(term), [0, usize::MAX]);
+
+                heap.new_application(term, f, [x, x]).unwrap();
+                heap.new_application(term, f, [term, term]).unwrap();
+                heap.new_application(term, f, [x, term]).unwrap();
+                assert_eq!(heap.count_occurrences(term, DeBruijn(1)), 5);
+                assert_eq!(heap.count_all_occurrences(term), [0, 5]);
+            });
+        });
+    }
+}
@@ -0,0 +1,126 @@
+use crate::heap::Heap;
+use crate::heap::ScopedHandle;
+use super::Zipper;
+
+/// Raised when a path does not lead to a subterm.
+///
+/// Carries the number of steps of the path that could be taken.
+#[derive(Debug)]
+pub struct PathError(pub usize);
+
+/// Methods for addressing subterms by their path.
+///
+/// Each element of a path selects a field of an application,
+/// like [`Zipper::down`] does:
+/// 0 selects the function, and _i_ selects argument _i_ - 1.
+/// The empty path refers to the term itself.
+impl<'h> Heap<'h>
+{
+    /// Find the subterm at the given path.
+    ///
+    /// If the path does not lead to a subterm,
+    /// an error is returned and `into` is not modified.
+    pub fn subterm_at<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        root: ScopedHandle<'h, 's>,
+        path: &[u16],
+    ) -> Result<(), PathError>
+    {
+        self.with_new_zipper(root, |zipper| {
+            follow_path(zipper, path)?;
+            into.copy_from(zipper.focus());
+            Ok(())
+        })
+    }
+
+    /// Create a term like the given term,
+    /// but with the subterm at the given path replaced.
+    ///
+    /// Only the applications along the path are recreated.
+    /// If the path does not lead to a subterm,
+    /// an error is returned and `into` is not modified.
+    pub fn replace_at<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        root: ScopedHandle<'h, 's>,
+        path: &[u16],
+        replacement: ScopedHandle<'h, 's>,
+    ) -> Result<(), PathError>
+    {
+        self.with_new_zipper(root, |zipper| {
+            follow_path(zipper, path)?;
+            zipper.replace(replacement);
+            zipper.finish(into);
+            Ok(())
+        })
+    }
+}
+
+/// Move the zipper down along the given path.
+fn follow_path(zipper: &mut Zipper, path: &[u16]) -> Result<(), PathError>
+{
+    for (i, &field) in path.iter().enumerate() {
+        if !zipper.down(field.into()) {
+            return Err(PathError(i));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::term;
+    use crate::testing::TermTree;
+
+    #[test]
+    fn subterm_at()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[term, subterm, expected]| {
+                term!(heap, term, (F (G A #0) B));
+
+                heap.subterm_at(subterm, term, &[]).unwrap();
+                assert!(subterm.ptr_eq(term));
+
+                heap.subterm_at(subterm, term, &[1, 0]).unwrap();
+                term!(heap, expected, G);
+                assert_eq!(
+                    TermTree::read(heap, subterm),
+                    TermTree::read(heap, expected),
+                );
+
+                let result = heap.subterm_at(subterm, term, &[1, 2, 0]);
+                assert!(matches!(result, Err(PathError(2))));
+                let result = heap.subterm_at(subterm, term, &[3]);
+                assert!(matches!(result, Err(PathError(0))));
+            });
+        });
+    }
+
+    #[test]
+    fn replace_at()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[term, c, result, expected]| {
+                term!(heap, term, (F (G A #0) B));
+                heap.new_symbol(c, b"C").unwrap();
+
+                heap.replace_at(result, term, &[1, 2], c).unwrap();
+                term!(heap, expected, (F (G A C) B));
+                assert_eq!(
+                    TermTree::read(heap, result),
+                    TermTree::read(heap, expected),
+                );
+
+                heap.replace_at(result, term, &[], c).unwrap();
+                assert!(result.ptr_eq(c));
+
+                let result = heap.replace_at(result, term, &[2, 1], c);
+                assert!(matches!(result, Err(PathError(1))));
+            });
+        });
+    }
+}
@@ -0,0 +1,250 @@
+use crate::heap::Heap;
+use crate::heap::HeapVec;
+use crate::heap::Scope;
+use crate::heap::ScopedHandle;
+use crate::heap::UnsafeHandle;
+use super::Metavariable;
+
+use core::cell::Cell;
+
+/// Methods for matching terms against patterns.
+impl<'h> Heap<'h>
+{
+    /// Whether the subject matches the pattern.
+    ///
+    /// A pattern is a term that may contain [metavariables][`Metavariable`].
+    /// The subject matches the pattern if replacing each metavariable
+    /// by some term yields a term that is structurally equal to the subject.
+    /// Every occurrence of the same metavariable must match equal subterms.
+    /// Custom objects only match themselves.
+    ///
+    /// On success, the handle in `bindings` at the index of each metavariable
+    /// that occurs in the pattern is made to refer to the matched subterm.
+    /// Other handles in `bindings` are left alone.
+    /// On failure, the handles in `bindings` are unspecified.
+    ///
+    /// # Panics
+    ///
+    /// If a metavariable in the pattern has no handle in `bindings`,
+    /// this method panics.
+    pub fn match_pattern(
+        &self,
+        pattern: ScopedHandle<'h, '_>,
+        subject: ScopedHandle<'h, '_>,
+        bindings: &Scope<'h>,
+    ) -> bool
+    {
+        let mut bound = self.new_vec();
+        bound.resize(bindings.len(), false);
+        match_bound(self, pattern, subject, bindings, &mut bound)
+    }
+}
+
+/// Like [`Heap::match_pattern`], but with explicit tracking of bindings.
+///
+/// Metavariables that are not yet bound are bound to the matched subterms.
+/// On failure, some of the metavariables may have been bound nonetheless.
+pub (super) fn match_bound<'h>(
+    heap: &Heap<'h>,
+    pattern: ScopedHandle<'h, '_>,
+    subject: ScopedHandle<'h, '_>,
+    bindings: &Scope<'h>,
+    bound: &mut [bool],
+) -> bool
+{
+    // Nothing is allocated while matching, so unsafe handles are fine.
+    let mut pairs = heap.new_vec();
+    pairs.push((pattern.as_unsafe_handle(), subject.as_unsafe_handle()));
+
+    while let Some((pattern, subject)) = pairs.pop() {
+        let pattern = Cell::new(pattern);
+        let subject = Cell::new(subject);
+        // SAFETY: The objects are reachable from the given handles.
+        let pattern = unsafe { ScopedHandle::new(&pattern) };
+        let subject = unsafe { ScopedHandle::new(&subject) };
+
+        if let Some(Metavariable(m)) = pattern.as_metavariable() {
+            let binding = bindings.get(m as usize)
+                .expect("Metavariable has no binding");
+            if bound[m as usize] {
+                if !terms_equal(heap, binding, subject) {
+                    return false;
+                }
+            } else {
+                binding.copy_from(subject);
+                bound[m as usize] = true;
+            }
+            continue;
+        }
+
+        if !compare_shallow(pattern, subject, &mut pairs) {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Whether two terms are structurally equal.
+pub (crate) fn terms_equal<'h>(
+    heap: &Heap<'h>,
+    a: ScopedHandle<'h, '_>,
+    b: ScopedHandle<'h, '_>,
+) -> bool
+{
+    // Nothing is allocated while comparing, so unsafe handles are fine.
+    let mut pairs = heap.new_vec();
+    pairs.push((a.as_unsafe_handle(), b.as_unsafe_handle()));
+
+    while let Some((a, b)) = pairs.pop() {
+        let a = Cell::new(a);
+        let b = Cell::new(b);
+        // SAFETY: The objects are reachable from the given handles.
+        let a = unsafe { ScopedHandle::new(&a) };
+        let b = unsafe { ScopedHandle::new(&b) };
+        if !compare_shallow(a, b, &mut pairs) {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Compare two objects, except for their children.
+///
+/// If the objects are equal applications or cons cells,
+/// their pairs of children are pushed for comparison.
+/// Annotations are transparent: if either object is annotated,
+/// the pair with the annotated term instead is pushed.
+pub (super) fn compare_shallow<'h>(
+    a: ScopedHandle<'h, '_>,
+    b: ScopedHandle<'h, '_>,
+    pairs: &mut HeapVec<'h, (UnsafeHandle<'h>, UnsafeHandle<'h>)>,
+) -> bool
+{
+    if a.ptr_eq(b) {
+        return true;
+    }
+
+    let annotated = |object: ScopedHandle<'h, '_>| object.with_pin(|object| {
+        object.as_annotated().map(|(term, _)| term.as_unsafe_handle())
+    });
+    if let Some(a) = annotated(a) {
+        pairs.push((a, b.as_unsafe_handle()));
+        return true;
+    }
+    if let Some(b) = annotated(b) {
+        pairs.push((a.as_unsafe_handle(), b));
+        return true;
+    }
+
+    if let Some(a) = a.as_variable() {
+        return b.as_variable() == Some(a);
+    }
+
+    if let Some(a) = a.as_metavariable() {
+        return b.as_metavariable() == Some(a);
+    }
+
+    if let Some(a) = a.as_char() {
+        return b.as_char() == Some(a);
+    }
+
+    // Gensyms are only equal to themselves.
+    if a.is_gensym() || b.is_gensym() {
+        return false;
+    }
+
+    a.with_pin(|a| b.with_pin(|b| {
+        if let Some(a) = a.as_symbol() {
+            return b.as_symbol() == Some(a);
+        }
+
+        if let Some(a) = a.as_qualified_symbol() {
+            return b.as_qualified_symbol() == Some(a);
+        }
+
+        if let Some((a_head, a_tail)) = a.as_cons() {
+            let (b_head, b_tail) = match b.as_cons() {
+                Some(b) => b,
+                None => return false,
+            };
+            pairs.push((a_head.as_unsafe_handle(), b_head.as_unsafe_handle()));
+            pairs.push((a_tail.as_unsafe_handle(), b_tail.as_unsafe_handle()));
+            return true;
+        }
+
+        match (a.as_application(), b.as_application()) {
+            (Some((a_function, a_arguments)), Some((b_function, b_arguments)))
+                if a_arguments.len() == b_arguments.len() =>
+            {
+                pairs.push((
+                    a_function.as_unsafe_handle(),
+                    b_function.as_unsafe_handle(),
+                ));
+                pairs.extend(
+                    a_arguments.iter().zip(b_arguments.iter())
+                        .map(|(a, b)| {
+                            (a.as_unsafe_handle(), b.as_unsafe_handle())
+                        })
+                );
+                true
+            },
+
+            // Other objects are only equal to themselves.
+            _ => false,
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::term;
+    use crate::testing::TermTree;
+
+    #[test]
+    fn match_pattern()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_boxed_scope(2, |bindings| {
+            heap.with_new_array_scope(|[pattern, subject, expected]| {
+                term!(heap, pattern, (F ?0 (G ?1 ?0)));
+
+                term!(heap, subject, (F (H #0) (G A (H #0))));
+                assert!(heap.match_pattern(pattern, subject, bindings));
+                term!(heap, expected, (H #0));
+                assert_eq!(
+                    TermTree::read(heap, bindings.get(0).unwrap()),
+                    TermTree::read(heap, expected),
+                );
+                term!(heap, expected, A);
+                assert_eq!(
+                    TermTree::read(heap, bindings.get(1).unwrap()),
+                    TermTree::read(heap, expected),
+                );
+
+                term!(heap, subject, (F (H #0) (G A (H #1))));
+                assert!(!heap.match_pattern(pattern, subject, bindings));
+
+                term!(heap, subject, (F (H #0) (G A)));
+                assert!(!heap.match_pattern(pattern, subject, bindings));
+            }); });
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Metavariable has no binding")]
+    fn too_few_bindings()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_boxed_scope(1, |bindings| {
+            heap.with_new_array_scope(|[pattern, subject]| {
+                term!(heap, pattern, (F ?1));
+                term!(heap, subject, (F A));
+                heap.match_pattern(pattern, subject, bindings);
+            }); });
+        });
+    }
+}
@@ -0,0 +1,265 @@
+use crate::heap::Heap;
+use crate::heap::PinnedHandle;
+use crate::heap::ScopedHandle;
+use super::Flags;
+use super::HEADER_SIZE;
+use super::FreeCache;
+use super::Header;
+use super::Kind;
+use super::Payload;
+use super::SymbolLenError;
+use super::TryNewSymbolError;
+
+use core::cell::Cell;
+use core::mem::MaybeUninit;
+use core::mem::size_of;
+use core::slice;
+
+/// Methods for creating qualified symbol objects.
+///
+/// A qualified symbol consists of a namespace and a name,
+/// such as a module name and the name of a definition in it.
+/// Both parts are byte strings, and are kept apart,
+/// so there is no need to agree on a separator.
+/// A qualified symbol is never equal to an unqualified symbol,
+/// even one with an empty namespace.
+impl<'h> Heap<'h>
+{
+    /// Create a qualified symbol with the given namespace and name.
+    pub fn new_qualified_symbol<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        namespace: &[u8],
+        name: &[u8],
+    ) -> Result<(), SymbolLenError>
+    {
+        let payload_size = payload_size(namespace, name)?;
+        unsafe {
+            self.new(into, payload_size, |payload| {
+                init_qualified_symbol(payload, namespace, name)
+            });
+        }
+        Ok(())
+    }
+
+    /// Similar to [`new_qualified_symbol`][`Self::new_qualified_symbol`],
+    /// but return an error if memory cannot be allocated.
+    pub fn try_new_qualified_symbol<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        namespace: &[u8],
+        name: &[u8],
+    ) -> Result<(), TryNewSymbolError>
+    {
+        let payload_size = payload_size(namespace, name)?;
+        unsafe {
+            self.try_new(into, payload_size, |payload| {
+                init_qualified_symbol(payload, namespace, name)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Find or create the interned qualified symbol
+    /// with the given namespace and name.
+    ///
+    /// The heap remembers the qualified symbols created by this method,
+    /// so that calling it again with the same namespace and name
+    /// returns the same object.
+    /// This saves memory when the same names are used over and over,
+    /// and allows for comparison using [`ScopedHandle::ptr_eq`].
+    pub fn intern_qualified_symbol<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        namespace: &[u8],
+        name: &[u8],
+    ) -> Result<(), SymbolLenError>
+    {
+        let key = (namespace, name);
+
+        // SAFETY: We only borrow the table for short periods of time.
+        let found = unsafe {
+            self.interned_qualified_symbols.borrow()
+                .binary_search_by(|interned| {
+                    // SAFETY: Interned objects are not destroyed.
+                    let interned = ScopedHandle::new(interned);
+                    interned.with_pin(|interned| {
+                        interned.as_qualified_symbol().unwrap().cmp(&key)
+                    })
+                })
+        };
+
+        match found {
+            Ok(index) => {
+                let interned = unsafe {
+                    self.interned_qualified_symbols.borrow()[index].get()
+                };
+                // SAFETY: Interned objects are not destroyed.
+                unsafe { into.copy_from_unsafe_handle(interned) };
+            },
+            Err(index) => {
+                self.new_qualified_symbol(into, namespace, name)?;
+                let interned = Cell::new(into.as_unsafe_handle());
+                unsafe {
+                    self.interned_qualified_symbols.borrow_mut()
+                        .insert(index, interned);
+                }
+            },
+        }
+
+        Ok(())
+    }
+}
+
+/// The payload size of a qualified symbol with the given parts.
+fn payload_size(namespace: &[u8], name: &[u8]) -> Result<usize, SymbolLenError>
+{
+    u32::try_from(namespace.len()).map_err(|_| SymbolLenError)?;
+    u32::try_from(name.len()).map_err(|_| SymbolLenError)?;
+
+    // Leave room for the object header,
+    // and make sure the object size is a valid layout size.
+    let size = size_of::<u32>()
+        .checked_add(namespace.len())
+        .and_then(|size| size.checked_add(name.len()))
+        .filter(|&size| size + HEADER_SIZE + 7 <= isize::MAX as usize)
+        .ok_or(SymbolLenError)?;
+    Ok(size)
+}
+
+/// Initialize a qualified symbol object with the given parts.
+///
+/// # Safety
+///
+/// The payload size must have been computed by [`payload_size`]
+/// from the parts, and the payload must be that large.
+unsafe fn init_qualified_symbol(
+    payload: *mut Payload,
+    namespace: &[u8],
+    name: &[u8],
+) -> Header
+{
+    // The extra field stores the length of the namespace.
+    let mut extra = MaybeUninit::uninit_array();
+    let namespace_len = namespace.len() as u32;
+    MaybeUninit::write_slice(&mut extra, &namespace_len.to_ne_bytes());
+
+    // The payload stores the length of the name,
+    // followed by the bytes of the namespace and the name.
+    let name_len = payload as *mut [u8; 4];
+    *name_len = (name.len() as u32).to_ne_bytes();
+    let bytes = (payload as *mut u8).add(size_of::<u32>());
+    bytes.copy_from_nonoverlapping(namespace.as_ptr(), namespace.len());
+    bytes.add(namespace.len())
+        .copy_from_nonoverlapping(name.as_ptr(), name.len());
+
+    Header{
+        kind: Kind::QualifiedSymbol,
+        flags: Flags::empty(),
+        free_cache: FreeCache::EMPTY,
+        extra,
+    }
+}
+
+/// The lengths of the namespace and the name of a qualified symbol.
+///
+/// # Safety
+///
+/// The header and payload must be those of a qualified symbol.
+pub (super) unsafe fn qualified_symbol_lens(
+    header: &Header,
+    payload: *const Payload,
+) -> (usize, usize)
+{
+    let extra = MaybeUninit::array_assume_init(header.extra);
+    let namespace_len = u32::from_ne_bytes(extra);
+    let name_len = u32::from_ne_bytes(*(payload as *const [u8; 4]));
+    (namespace_len as usize, name_len as usize)
+}
+
+/// Methods for inspecting qualified symbol objects.
+impl<'h, 'p> PinnedHandle<'h, 'p>
+{
+    /// Get the namespace and the name of the qualified symbol object.
+    ///
+    /// If the object is not a qualified symbol,
+    /// this method returns [`None`].
+    #[inline]
+    pub fn as_qualified_symbol(self) -> Option<(&'p [u8], &'p [u8])>
+    {
+        let header = self.header();
+        match header.kind {
+            Kind::QualifiedSymbol => unsafe {
+                let payload = self.payload();
+                let (namespace_len, name_len) =
+                    qualified_symbol_lens(&header, payload);
+                let bytes = (payload as *const u8).add(size_of::<u32>());
+                let namespace = slice::from_raw_parts(bytes, namespace_len);
+                let name = slice::from_raw_parts(
+                    bytes.add(namespace_len),
+                    name_len,
+                );
+                Some((namespace, name))
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    use alloc::vec::Vec;
+    use proptest::proptest;
+
+    proptest!
+    {
+        #[test]
+        fn roundtrip(namespace: Vec<u8>, name: Vec<u8>)
+        {
+            Heap::with_new(|heap| {
+                heap.with_new_array_scope(|[handle]| {
+                    heap.new_qualified_symbol(handle, &namespace, &name)
+                        .unwrap();
+                    handle.with_pin(|handle| {
+                        let parts = (&namespace[..], &name[..]);
+                        assert_eq!(handle.as_qualified_symbol(), Some(parts));
+                        assert_eq!(handle.as_symbol(), None);
+                        assert_eq!(
+                            handle.object_size(),
+                            HEADER_SIZE + 4 + namespace.len() + name.len(),
+                        );
+                    });
+                });
+            });
+        }
+    }
+
+    #[test]
+    fn intern()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_boxed_scope(0, |bindings| {
+            heap.with_new_array_scope(|[a, b, c, d]| {
+                heap.intern_qualified_symbol(a, b"list", b"map").unwrap();
+                heap.intern_qualified_symbol(b, b"list", b"filter").unwrap();
+                heap.intern_qualified_symbol(c, b"list", b"map").unwrap();
+                assert!(a.ptr_eq(c));
+                assert!(!a.ptr_eq(b));
+
+                // Equal to qualified symbols that are not interned,
+                // but not to unqualified symbols.
+                heap.new_qualified_symbol(d, b"list", b"map").unwrap();
+                assert!(heap.match_pattern(a, d, bindings));
+                heap.new_symbol(d, b"listmap").unwrap();
+                assert!(!heap.match_pattern(a, d, bindings));
+                heap.new_qualified_symbol(d, b"lis", b"tmap").unwrap();
+                assert!(!heap.match_pattern(a, d, bindings));
+
+                heap.verify();
+            }); });
+        });
+    }
+}
@@ -0,0 +1,83 @@
+use crate::heap::ScopedHandle;
+use super::Flags;
+
+/// Methods for remembering that objects are reduced.
+///
+/// Reducing a term that is already reduced
+/// still takes a traversal to find out that nothing changes.
+/// Code that reduces terms can mark the results,
+/// and skip marked objects when it meets them again,
+/// which pays off for shared subterms that are reduced over and over.
+/// The marks are only as trustworthy as the code that sets them;
+/// the heap does not check them.
+impl<'h, 's> ScopedHandle<'h, 's>
+{
+    /// Whether the object was marked as being in weak head normal form.
+    ///
+    /// Objects in normal form are also in weak head normal form.
+    #[inline]
+    pub fn is_whnf(self) -> bool
+    {
+        self.header().flags.intersects(Flags::WHNF | Flags::NORMAL_FORM)
+    }
+
+    /// Whether the object was marked as being in normal form.
+    #[inline]
+    pub fn is_normal_form(self) -> bool
+    {
+        self.header().flags.contains(Flags::NORMAL_FORM)
+    }
+
+    /// Mark the object as being in weak head normal form.
+    #[inline]
+    pub fn mark_whnf(self)
+    {
+        self.insert_flags(Flags::WHNF);
+    }
+
+    /// Mark the object as being in normal form.
+    #[inline]
+    pub fn mark_normal_form(self)
+    {
+        self.insert_flags(Flags::WHNF | Flags::NORMAL_FORM);
+    }
+
+    fn insert_flags(self, flags: Flags)
+    {
+        // SAFETY: The handle refers to an object, as it is scoped.
+        unsafe { (*self.as_unsafe_handle().header()).flags.insert(flags) };
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use crate::heap::Heap;
+    use crate::term;
+
+    #[test]
+    fn mark()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[a, b]| {
+                term!(heap, a, (F A));
+                term!(heap, b, (F A));
+                assert!(!a.is_whnf());
+                assert!(!a.is_normal_form());
+
+                a.mark_whnf();
+                assert!(a.is_whnf());
+                assert!(!a.is_normal_form());
+
+                b.mark_normal_form();
+                assert!(b.is_whnf());
+                assert!(b.is_normal_form());
+
+                // Pinning does not disturb the marks.
+                b.with_pin(|_| ());
+                assert!(b.is_normal_form());
+                heap.verify();
+            });
+        });
+    }
+}
@@ -0,0 +1,418 @@
+use crate::heap::GrowableScope;
+use crate::heap::Heap;
+use crate::heap::Interrupted;
+use crate::heap::Scope;
+use crate::heap::ScopedHandle;
+use super::Metavariable;
+use super::Visitor;
+use super::pattern::match_bound;
+
+/// Collection of rewrite rules.
+///
+/// Each rule consists of a pattern and a template.
+/// A term that [matches][`Heap::match_pattern`] the pattern
+/// is rewritten to the template,
+/// with its metavariables replaced by the terms they were matched with.
+pub struct RuleSet<'h, 's>
+{
+    /// Patterns and templates, alternating.
+    rules: &'s Scope<'h>,
+
+    /// One more than the highest metavariable used by the rules.
+    num_metavariables: usize,
+}
+
+impl<'h, 's> RuleSet<'h, 's>
+{
+    /// Create a rule set from a scope with a pattern and a template per rule.
+    ///
+    /// The handles of the scope alternate between patterns and templates,
+    /// so the first rule consists of the first two handles, and so on.
+    /// Earlier rules take precedence over later rules.
+    ///
+    /// Metavariables are numbered from zero,
+    /// and rewriting keeps track of as many terms
+    /// as the number of the highest metavariable,
+    /// so it is best to number them densely.
+    ///
+    /// # Panics
+    ///
+    /// If the scope has an odd number of handles, this method panics.
+    pub fn new(heap: &Heap<'h>, rules: &'s Scope<'h>) -> Self
+    {
+        assert!(rules.len() & 1 == 0, "Rule lacks a template");
+
+        struct Highest(usize);
+        impl<'h> Visitor<'h> for Highest
+        {
+            fn pre(&mut self, object: ScopedHandle<'h, '_>) -> bool
+            {
+                if let Some(Metavariable(m)) = object.as_metavariable() {
+                    self.0 = self.0.max(m as usize + 1);
+                }
+                true
+            }
+        }
+
+        let mut highest = Highest(0);
+        for rule in rules.iter() {
+            heap.walk(rule, &mut highest);
+        }
+
+        Self{rules, num_metavariables: highest.0}
+    }
+
+    /// The number of rules in the rule set.
+    #[inline]
+    pub fn len(&self) -> usize
+    {
+        self.rules.len() / 2
+    }
+
+    /// Whether the rule set has no rules.
+    #[inline]
+    pub fn is_empty(&self) -> bool
+    {
+        self.rules.is_empty()
+    }
+}
+
+/// Methods for rewriting terms.
+impl<'h> Heap<'h>
+{
+    /// Rewrite the given term once, using the first rule that applies.
+    ///
+    /// Subterms are tried in leftmost-outermost order:
+    /// an application or cons cell is tried before its fields,
+    /// the function is tried before the arguments,
+    /// and the head is tried before the tail.
+    /// The rewritten term is written to `into`,
+    /// sharing everything but the spine above the rewritten subterm.
+    ///
+    /// If no rule applies to any subterm,
+    /// `into` is made to refer to the term and this method returns false.
+    pub fn rewrite_once<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        term: ScopedHandle<'h, 's>,
+        rules: &RuleSet<'h, '_>,
+    ) -> bool
+    {
+        let num_metavariables = rules.num_metavariables;
+        self.with_new_boxed_scope(num_metavariables, |bindings| {
+            let mut bound = self.new_vec();
+            bound.resize(num_metavariables, false);
+            self.with_new_zipper(term, |zipper| {
+                loop {
+
+                    for i in 0 .. rules.len() {
+                        let pattern = rules.rules.get(2 * i).unwrap();
+                        let template = rules.rules.get(2 * i + 1).unwrap();
+                        bound.fill(false);
+                        let focus = zipper.focus();
+                        let matched = match_bound(
+                            self, pattern, focus, bindings, &mut bound);
+                        if matched {
+                            self.with_new_array_scope(|[result]| {
+                                self.instantiate(
+                                    result, template, bindings, &bound);
+                                zipper.replace(result);
+                            });
+                            zipper.finish(into);
+                            return true;
+                        }
+                    }
+
+                    // Move on to the next subterm in preorder.
+                    if zipper.down(0) {
+                        continue;
+                    }
+                    loop {
+                        match zipper.path().last().copied() {
+                            Some(field) => {
+                                zipper.up();
+                                if zipper.down(field + 1) {
+                                    break;
+                                }
+                            },
+                            None => {
+                                zipper.finish(into);
+                                return false;
+                            },
+                        }
+                    }
+
+                }
+            })
+        })
+    }
+
+    /// Rewrite the given term until no rule applies.
+    ///
+    /// At most `max_steps` rewrites are performed,
+    /// as rewriting does not terminate for every rule set.
+    /// The final term is written to `into`.
+    /// This method returns whether a term was reached
+    /// to which no rule applies.
+    ///
+    /// Before each step this method calls [`safepoint`][`Self::safepoint`].
+    /// If it is interrupted, the term rewritten so far is written to `into`.
+    pub fn rewrite_fixpoint<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        term: ScopedHandle<'h, 's>,
+        rules: &RuleSet<'h, '_>,
+        max_steps: usize,
+    ) -> Result<bool, Interrupted>
+    {
+        self.with_new_array_scope(|[current, scratch]| {
+            current.copy_from(term);
+            let result = (|| {
+                for _ in 0 .. max_steps {
+                    self.safepoint()?;
+                    if !self.rewrite_once(current, current, rules) {
+                        return Ok(true);
+                    }
+                }
+                // The last step may have reached the fixpoint,
+                // so check without keeping the rewritten term.
+                Ok(!self.rewrite_once(scratch, current, rules))
+            })();
+            into.copy_from(current);
+            result
+        })
+    }
+
+    /// Create the given template with its bound metavariables replaced.
+    pub (super) fn instantiate<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        template: ScopedHandle<'h, 's>,
+        bindings: &Scope<'h>,
+        bound: &[bool],
+    )
+    {
+        self.with_new_growable_scope(|results| {
+            let mut instantiate =
+                Instantiate{heap: self, bindings, bound, results};
+            self.walk(template, &mut instantiate);
+            into.copy_from(instantiate.results.as_scope().get(0).unwrap());
+        });
+    }
+}
+
+/// Visitor that instantiates templates, for [`Heap::instantiate`].
+struct Instantiate<'a, 'g, 'h>
+{
+    heap: &'a Heap<'h>,
+    bindings: &'a Scope<'h>,
+    bound: &'a [bool],
+
+    /// Instantiated children of the objects being walked.
+    results: GrowableScope<'g, 'h>,
+}
+
+impl<'a, 'g, 'h> Visitor<'h> for Instantiate<'a, 'g, 'h>
+{
+    fn pre(&mut self, object: ScopedHandle<'h, '_>) -> bool
+    {
+        // Only applications and cons cells are recreated with new fields;
+        // the children of custom objects are left alone.
+        object.with_pin(|object| object.num_rebuilt_fields().is_some())
+    }
+
+    fn post(&mut self, object: ScopedHandle<'h, '_>)
+    {
+        let num_fields = object.with_pin(|object| object.num_rebuilt_fields());
+
+        let num_fields = match num_fields {
+            Some(num_fields) => num_fields,
+            None => {
+                let index = self.results.push();
+                let result = self.results.as_scope().get(index).unwrap();
+                match object.as_metavariable() {
+                    Some(Metavariable(m)) if self.bound[m as usize] => {
+                        result.copy_from(self.bindings.get(m as usize).unwrap())
+                    },
+                    _ => result.copy_from(object),
+                }
+                return;
+            },
+        };
+
+        let results = self.results.as_scope();
+        let start = results.len() - num_fields;
+        let fields = (start .. results.len()).map(|i| results.get(i).unwrap());
+        let result = results.get(start).unwrap();
+        self.heap.new_rebuilt_if_changed(result, object, fields);
+
+        for _ in 1 .. num_fields {
+            self.results.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::term;
+    use crate::heap::HeapConfig;
+    use crate::testing::TermTree;
+
+    use core::sync::atomic::AtomicBool;
+    use core::sync::atomic::Ordering;
+
+    #[test]
+    fn peano()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_boxed_scope(4, |rules| {
+            heap.with_new_array_scope(|[term, result, expected]| {
+                let rule = |i| rules.get(i).unwrap();
+                term!(heap, rule(0), (Add Zero ?0));
+                term!(heap, rule(1), ?0);
+                term!(heap, rule(2), (Add (Succ ?0) ?1));
+                term!(heap, rule(3), (Succ (Add ?0 ?1)));
+                let rules = RuleSet::new(heap, rules);
+                assert_eq!(rules.len(), 2);
+
+                // The fixpoint is three steps away.
+                term!(heap, term, (Add (Succ (Succ Zero)) (Succ Zero)));
+                term!(heap, expected, (Succ (Succ (Succ Zero))));
+                for max_steps in [3, 10] {
+                    let done =
+                        heap.rewrite_fixpoint(result, term, &rules, max_steps);
+                    assert!(done.unwrap());
+                    assert_eq!(
+                        TermTree::read(heap, result),
+                        TermTree::read(heap, expected),
+                    );
+                }
+
+                // Not enough steps to reach the fixpoint.
+                let done = heap.rewrite_fixpoint(result, term, &rules, 2);
+                assert!(!done.unwrap());
+                term!(heap, expected, (Succ (Succ (Add Zero (Succ Zero)))));
+                assert_eq!(
+                    TermTree::read(heap, result),
+                    TermTree::read(heap, expected),
+                );
+
+                let done = heap.rewrite_fixpoint(result, term, &rules, 0);
+                assert!(!done.unwrap());
+                assert!(result.ptr_eq(term));
+            }); });
+        });
+    }
+
+    #[test]
+    fn interrupt()
+    {
+        let flag = AtomicBool::new(false);
+        let config = HeapConfig{
+            interrupt: Some(&flag),
+            ..HeapConfig::default()
+        };
+        Heap::with_new_config(config, |heap| {
+            heap.with_new_boxed_scope(2, |rules| {
+            heap.with_new_array_scope(|[term, result]| {
+                // This rule set does not terminate.
+                term!(heap, rules.get(0).unwrap(), ?0);
+                term!(heap, rules.get(1).unwrap(), (F ?0));
+                let rules = RuleSet::new(heap, rules);
+
+                term!(heap, term, A);
+                let done = heap.rewrite_fixpoint(result, term, &rules, 3);
+                assert!(!done.unwrap());
+
+                heap.request_interrupt();
+                assert!(flag.load(Ordering::Relaxed));
+                let done = heap.rewrite_fixpoint(result, term, &rules, 3);
+                assert!(matches!(done, Err(Interrupted)));
+                assert!(result.ptr_eq(term));
+            }); });
+        });
+    }
+
+    #[test]
+    fn leftmost_outermost()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_boxed_scope(4, |rules| {
+            heap.with_new_array_scope(|[term, result, expected]| {
+                let rule = |i| rules.get(i).unwrap();
+                term!(heap, rule(0), (F ?0));
+                term!(heap, rule(1), (G ?0));
+                term!(heap, rule(2), A);
+                term!(heap, rule(3), B);
+                let rules = RuleSet::new(heap, rules);
+
+                term!(heap, term, (H (F A) (F A)));
+                assert!(heap.rewrite_once(result, term, &rules));
+                term!(heap, expected, (H (G A) (F A)));
+                assert_eq!(
+                    TermTree::read(heap, result),
+                    TermTree::read(heap, expected),
+                );
+
+                term!(heap, term, (H (G A) C));
+                assert!(heap.rewrite_once(result, term, &rules));
+                term!(heap, expected, (H (G B) C));
+                assert_eq!(
+                    TermTree::read(heap, result),
+                    TermTree::read(heap, expected),
+                );
+
+                term!(heap, term, (H C));
+                assert!(!heap.rewrite_once(result, term, &rules));
+                assert!(result.ptr_eq(term));
+            }); });
+        });
+    }
+
+    #[test]
+    fn list()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_boxed_scope(2, |rules| {
+            heap.with_new_array_scope(|[fa, a, term, result, expected]| {
+                term!(heap, rules.get(0).unwrap(), (F ?0));
+                term!(heap, rules.get(1).unwrap(), ?0);
+                let rules = RuleSet::new(heap, rules);
+
+                term!(heap, fa, (F A));
+                heap.new_list(term, [fa]);
+                assert!(heap.rewrite_once(result, term, &rules));
+                heap.new_symbol(a, b"A").unwrap();
+                heap.new_list(expected, [a]);
+                assert_eq!(
+                    TermTree::read(heap, result),
+                    TermTree::read(heap, expected),
+                );
+            }); });
+        });
+    }
+
+    #[test]
+    fn nonlinear()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_boxed_scope(2, |rules| {
+            heap.with_new_array_scope(|[term, result]| {
+                term!(heap, rules.get(0).unwrap(), (Equal ?0 ?0));
+                term!(heap, rules.get(1).unwrap(), True);
+                let rules = RuleSet::new(heap, rules);
+
+                term!(heap, term, (Equal (F #0) (F #0)));
+                assert!(heap.rewrite_once(result, term, &rules));
+                result.with_pin(|result| {
+                    assert_eq!(result.as_symbol(), Some(&b"True"[..]));
+                });
+
+                term!(heap, term, (Equal (F #0) (F #1)));
+                assert!(!heap.rewrite_once(result, term, &rules));
+            }); });
+        });
+    }
+}
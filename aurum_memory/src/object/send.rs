@@ -0,0 +1,93 @@
+use crate::heap::Heap;
+use crate::heap::ScopedHandle;
+use super::DeserializeError;
+use super::DeserializeLimits;
+use super::SerializeError;
+
+use alloc::vec::Vec;
+
+/// Term that is independent of any heap,
+/// and so can be sent to other threads.
+///
+/// Heaps are not [`Sync`], and handles cannot leave their heap,
+/// so threads that work on terms each have their own heap.
+/// To pass a term from one to another, export it from the one heap
+/// with [`Heap::export`], send it over, and import it into the other
+/// with [`Heap::import`].
+/// A sent term can be imported any number of times, into any heap.
+#[derive(Clone, Debug)]
+pub struct SendTerm
+{
+    /// The term in the format of [`Heap::serialize_term`].
+    bytes: Vec<u8>,
+}
+
+impl SendTerm
+{
+    /// The term in the format of [`Heap::serialize_term`].
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8]
+    {
+        &self.bytes
+    }
+}
+
+/// Methods for moving terms between heaps.
+impl<'h> Heap<'h>
+{
+    /// Copy the term out of the heap.
+    ///
+    /// If the term contains a custom object, an error is returned,
+    /// as custom objects cannot be serialized.
+    /// Like [`serialize_term`][`Self::serialize_term`],
+    /// exporting stops with an error if an interrupt is requested.
+    pub fn export<'s>(&self, term: ScopedHandle<'h, 's>)
+        -> Result<SendTerm, SerializeError>
+    {
+        let mut bytes = Vec::new();
+        self.serialize_term(term, |chunk| bytes.extend_from_slice(chunk))?;
+        Ok(SendTerm{bytes})
+    }
+
+    /// Copy the term into the heap.
+    ///
+    /// As the term was exported, it is well-formed,
+    /// so an error is only returned if memory cannot be allocated.
+    pub fn import<'s>(&self, into: ScopedHandle<'h, 's>, term: &SendTerm)
+        -> Result<(), DeserializeError>
+    {
+        self.deserialize_term(into, &term.bytes, DeserializeLimits::default())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::term;
+    use crate::testing::TermTree;
+
+    extern crate std;
+
+    #[test]
+    fn other_thread()
+    {
+        let (term, tree) = Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[term]| {
+                term!(heap, term, (F (G #0) ?1 Hello));
+                (heap.export(term).unwrap(), TermTree::read(heap, term))
+            })
+        });
+
+        let imported = std::thread::spawn(move || {
+            Heap::with_new(|heap| {
+                heap.with_new_array_scope(|[imported]| {
+                    heap.import(imported, &term).unwrap();
+                    TermTree::read(heap, imported)
+                })
+            })
+        }).join().unwrap();
+
+        assert_eq!(imported, tree);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::Error;
+use crate::heap::Heap;
+use crate::heap::ScopedHandle;
+use super::DeserializeLimits;
+
+use core::fmt;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+use serde::de::DeserializeSeed;
+use serde::de::SeqAccess;
+use serde::de::Visitor;
+use serde::de;
+use serde::ser;
+
+/// Adapter for serializing and deserializing terms with [serde].
+///
+/// This type is only available with the `serde` feature.
+/// The term is encoded as a byte string in the format
+/// of [`Heap::serialize_term`], so it can be embedded in any document
+/// that serde can write, such as JSON or CBOR.
+///
+/// Deserializing a term requires a heap to create it in,
+/// so this type implements [`DeserializeSeed`] rather than
+/// [`Deserialize`][`serde::Deserialize`]:
+/// the deserialized term is written to the wrapped handle,
+/// and the stream is subject to the wrapped limits.
+///
+/// [serde]: `serde`
+pub struct SerializableTerm<'h, 's>
+{
+    #[allow(missing_docs)]
+    pub heap: &'s Heap<'h>,
+
+    #[allow(missing_docs)]
+    pub term: ScopedHandle<'h, 's>,
+
+    /// Limits on the deserialized term.
+    /// Serialization ignores these.
+    pub limits: DeserializeLimits,
+}
+
+impl<'h, 's> SerializableTerm<'h, 's>
+{
+    /// Wrap the term, with no limits on deserialization.
+    pub fn new(heap: &'s Heap<'h>, term: ScopedHandle<'h, 's>) -> Self
+    {
+        Self{heap, term, limits: DeserializeLimits::default()}
+    }
+}
+
+impl<'h, 's> Serialize for SerializableTerm<'h, 's>
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let mut bytes = self.heap.new_vec();
+        self.heap.serialize_term(self.term, |chunk| {
+            bytes.extend_from_slice(chunk);
+        }).map_err(|err| ser::Error::custom(Error::from(err)))?;
+        serializer.serialize_bytes(&bytes)
+    }
+}
+
+impl<'de, 'h, 's> DeserializeSeed<'de> for SerializableTerm<'h, 's>
+{
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<(), D::Error>
+        where D: Deserializer<'de>
+    {
+        deserializer.deserialize_bytes(self)
+    }
+}
+
+impl<'de, 'h, 's> Visitor<'de> for SerializableTerm<'h, 's>
+{
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "a serialized term")
+    }
+
+    fn visit_bytes<E>(self, bytes: &[u8]) -> Result<(), E>
+        where E: de::Error
+    {
+        self.heap.deserialize_term(self.term, bytes, self.limits)
+            .map_err(custom)
+    }
+
+    /// Formats without byte strings, such as JSON,
+    /// write them as sequences of numbers.
+    fn visit_seq<A>(self, mut seq: A) -> Result<(), A::Error>
+        where A: SeqAccess<'de>
+    {
+        self.heap.with_new_deserializer(self.limits, |deserializer| {
+            // Feed the bytes in chunks, so that they need not all be kept.
+            let mut chunk = [0; 256];
+            let mut len = 0;
+            while let Some(byte) = seq.next_element()? {
+                chunk[len] = byte;
+                len += 1;
+                if len == chunk.len() {
+                    deserializer.feed(&chunk).map_err(custom)?;
+                    len = 0;
+                }
+            }
+            deserializer.feed(&chunk[.. len]).map_err(custom)?;
+            deserializer.finish(self.term).map_err(custom)
+        })
+    }
+}
+
+fn custom<E>(err: impl Into<Error>) -> E
+    where E: de::Error
+{
+    E::custom(err.into())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::term;
+    use crate::testing::TermTree;
+
+    #[test]
+    fn json()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[term, copy]| {
+                term!(heap, term, (F (G #0) ?1 Hello));
+                let json = SerializableTerm::new(heap, term);
+                let json = serde_json::to_string(&json).unwrap();
+
+                let mut de = serde_json::Deserializer::from_str(&json);
+                SerializableTerm::new(heap, copy)
+                    .deserialize(&mut de)
+                    .unwrap();
+                assert_eq!(
+                    TermTree::read(heap, copy),
+                    TermTree::read(heap, term),
+                );
+
+                let mut de = serde_json::Deserializer::from_str(&json);
+                let limits = DeserializeLimits{
+                    max_objects: 2,
+                    ..DeserializeLimits::default()
+                };
+                SerializableTerm{heap, term: copy, limits}
+                    .deserialize(&mut de)
+                    .unwrap_err();
+
+                let mut de = serde_json::Deserializer::from_str("[99]");
+                SerializableTerm::new(heap, copy)
+                    .deserialize(&mut de)
+                    .unwrap_err();
+            });
+        });
+    }
+}
@@ -0,0 +1,1301 @@
+use crate::heap::AllocError;
+use crate::heap::GrowableScope;
+use crate::heap::Heap;
+use crate::heap::HeapMap;
+use crate::heap::HeapVec;
+use crate::heap::Interrupted;
+use crate::heap::ScopedHandle;
+use crate::heap::UnsafeHandle;
+use super::DeBruijn;
+use super::Metavariable;
+use super::Span;
+use super::TryNewApplicationError;
+use super::TryNewEnvFrameError;
+use super::TryNewSymbolError;
+use super::Visitor;
+
+use core::mem::size_of;
+use core::mem;
+use core::slice;
+
+/// Raised when serialization fails.
+#[derive(Debug)]
+pub enum SerializeError
+{
+    /// The term contains a custom object,
+    /// which this crate does not know how to encode.
+    Custom,
+
+    #[allow(missing_docs)]
+    Interrupted(Interrupted),
+}
+
+impl From<Interrupted> for SerializeError
+{
+    fn from(other: Interrupted) -> Self
+    {
+        Self::Interrupted(other)
+    }
+}
+
+/// Raised when the bytes given to a [`Deserializer`]
+/// are not a serialized term.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FormatError
+{
+    /// The record at the given byte offset into the stream is invalid.
+    Corrupt
+    {
+        #[allow(missing_docs)]
+        offset: usize,
+    },
+
+    /// The stream ended before a complete term was read.
+    Truncated,
+
+    /// The stream was written by an incompatible version of this crate.
+    UnsupportedVersion
+    {
+        #[allow(missing_docs)]
+        version: u32,
+    },
+}
+
+/// Raised when a serialized term exceeds one of its [`DeserializeLimits`].
+///
+/// Each variant gives the byte offset of the offending record.
+#[allow(missing_docs)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LimitError
+{
+    NumObjects{offset: usize},
+    PayloadSize{offset: usize},
+    Depth{offset: usize},
+}
+
+/// Raised when deserialization fails.
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub enum DeserializeError
+{
+    Format(FormatError),
+    Limit(LimitError),
+    Alloc(AllocError),
+}
+
+impl From<FormatError> for DeserializeError
+{
+    fn from(other: FormatError) -> Self
+    {
+        Self::Format(other)
+    }
+}
+
+impl From<LimitError> for DeserializeError
+{
+    fn from(other: LimitError) -> Self
+    {
+        Self::Limit(other)
+    }
+}
+
+impl From<AllocError> for DeserializeError
+{
+    fn from(other: AllocError) -> Self
+    {
+        Self::Alloc(other)
+    }
+}
+
+/// Bounds on the terms that a [`Deserializer`] creates.
+///
+/// A serialized term from an untrusted source could otherwise
+/// describe far more objects than the size of the stream suggests,
+/// or a graph that is too deep for code that walks it recursively.
+/// Together with the [memory limit] of the heap,
+/// these limits make deserializing such streams safe.
+/// Streams that exceed a limit are rejected as soon as this is noticed,
+/// without buffering or creating the offending object.
+/// By default there are no limits;
+/// use [`untrusted`][`Self::untrusted`] for streams from untrusted sources.
+///
+/// [memory limit]: `crate::heap::HeapConfig::memory_limit`
+#[derive(Clone, Copy, Debug)]
+pub struct DeserializeLimits
+{
+    /// Maximum number of objects to create.
+    ///
+    /// Objects that are shared within the stream count once.
+    pub max_objects: usize,
+
+    /// Maximum number of bytes in the payload of each object,
+    /// such as the name of a symbol or the fields of an application.
+    pub max_payload_size: usize,
+
+    /// Maximum number of objects along any path from the term
+    /// through its children, including the term itself.
+    ///
+    /// Sharing lets a short stream describe a deep term,
+    /// as each record can refer back to the one before it.
+    pub max_depth: usize,
+}
+
+impl DeserializeLimits
+{
+    /// Conservative limits for streams from untrusted sources.
+    ///
+    /// These admit about a million objects,
+    /// payloads of up to 64 KiB, and a depth of up to 1024,
+    /// which is plenty for terms written by people,
+    /// but bounds the work an adversarial stream can cause.
+    pub fn untrusted() -> Self
+    {
+        Self{
+            max_objects: 1 << 20,
+            max_payload_size: 1 << 16,
+            max_depth: 1 << 10,
+        }
+    }
+}
+
+impl Default for DeserializeLimits
+{
+    fn default() -> Self
+    {
+        Self{
+            max_objects: usize::MAX,
+            max_payload_size: usize::MAX,
+            max_depth: usize::MAX,
+        }
+    }
+}
+
+/// The serializer passes the sink chunks of at most this many bytes.
+pub const CHUNK_SIZE: usize = 4096;
+
+/// The first bytes of every serialized term.
+const MAGIC: [u8; 4] = *b"AURM";
+
+/// Written after the magic number,
+/// and changed whenever the format changes incompatibly.
+const VERSION: u32 = 1;
+
+// Each record starts with one of these tags.
+const TAG_SYMBOL: u8 = 0;
+const TAG_GENSYM: u8 = 1;
+const TAG_QUALIFIED_SYMBOL: u8 = 2;
+const TAG_VARIABLE: u8 = 3;
+const TAG_METAVARIABLE: u8 = 4;
+const TAG_CHAR: u8 = 5;
+const TAG_APPLICATION: u8 = 6;
+const TAG_CONS: u8 = 7;
+const TAG_ANNOTATED: u8 = 8;
+const TAG_ENV_FRAME: u8 = 9;
+
+/// Follows the last record, and is followed by the checksum.
+const TAG_END: u8 = 0xFF;
+
+/// The number of bytes in the longest valid LEB128 encoding of a [`u64`].
+const MAX_VARINT_SIZE: usize = 10;
+
+/// Methods for serializing and deserializing terms.
+///
+/// A serialized term starts with a magic number and a format version,
+/// followed by a stream of records, one for each object.
+/// The records are written in postorder, so that every record
+/// refers to its children by how many records ago they were written.
+/// Objects that are reachable along multiple paths are written once,
+/// so sharing is preserved, and the last record is the term itself.
+/// Integers are written in LEB128, and byte strings are prefixed
+/// with their length, so the format does not depend on the platform.
+/// The records are followed by an end marker and a CRC-32 checksum
+/// of everything before it, so that truncated or damaged streams
+/// are reported as such rather than creating the wrong objects.
+///
+/// Neither direction needs the entire stream in memory at once:
+/// the serializer passes the stream to a sink in chunks,
+/// and a [`Deserializer`] accepts the stream in chunks of any size.
+/// Interned objects are interned again when deserialized,
+/// and gensyms are replaced by fresh gensyms with the same prefix.
+/// The [reduced-term flags][`ScopedHandle::is_whnf`] are not kept.
+impl<'h> Heap<'h>
+{
+    /// Serialize the term, passing the bytes to `sink` in chunks
+    /// of at most [`CHUNK_SIZE`] bytes.
+    ///
+    /// Besides the chunk buffer, the serializer remembers
+    /// which objects it has written, so that it can refer to them again.
+    /// If the term contains a custom object, an error is returned,
+    /// and the bytes passed to the sink so far do not form a term.
+    /// The same goes if an interrupt is requested, as the serializer
+    /// calls [`safepoint`][`Self::safepoint`] before each object.
+    pub fn serialize_term<'s>(
+        &self,
+        term: ScopedHandle<'h, 's>,
+        sink: impl FnMut(&[u8]),
+    ) -> Result<(), SerializeError>
+    {
+        let mut encoder = Encoder{
+            writer: Writer{
+                sink,
+                buffer: [0; CHUNK_SIZE],
+                len: 0,
+                checksum: Checksum::NEW,
+            },
+            indices: HeapMap::new(self),
+            error: None,
+        };
+        encoder.writer.bytes(&MAGIC);
+        encoder.writer.varint(VERSION.into());
+        let interrupted = self.try_walk(term, &mut encoder).err();
+        if let Some(err) = encoder.error.or(interrupted.map(Into::into)) {
+            encoder.writer.flush();
+            return Err(err);
+        }
+        encoder.writer.byte(TAG_END);
+        let checksum = encoder.writer.checksum.finish();
+        encoder.writer.bytes(&checksum.to_le_bytes());
+        encoder.writer.flush();
+        Ok(())
+    }
+
+    /// Create a deserializer with the given limits
+    /// and pass it to the given function.
+    ///
+    /// The objects created by the deserializer are destroyed
+    /// when the function returns, unless they are reachable
+    /// from the handle passed to [`Deserializer::finish`].
+    pub fn with_new_deserializer<F, R>(
+        &self,
+        limits: DeserializeLimits,
+        then: F,
+    ) -> R
+        where F: for<'g> FnOnce(&mut Deserializer<'g, 'h>) -> R
+    {
+        self.with_new_growable_scope(|objects| {
+            then(&mut Deserializer{
+                objects,
+                depths: self.new_vec(),
+                limits,
+                pending: self.new_vec(),
+                needed: 0,
+                offset: 0,
+                state: State::Header,
+                checksum: Checksum::NEW,
+            })
+        })
+    }
+
+    /// Deserialize a term from a stream that is entirely in memory.
+    pub fn deserialize_term<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        bytes: &[u8],
+        limits: DeserializeLimits,
+    ) -> Result<(), DeserializeError>
+    {
+        self.with_new_deserializer(limits, |deserializer| {
+            deserializer.feed(bytes)?;
+            deserializer.finish(into)?;
+            Ok(())
+        })
+    }
+}
+
+/// Sink with a fixed-size buffer.
+struct Writer<F>
+{
+    sink: F,
+    buffer: [u8; CHUNK_SIZE],
+    len: usize,
+
+    /// Checksum of all bytes written so far.
+    checksum: Checksum,
+}
+
+impl<F> Writer<F>
+    where F: FnMut(&[u8])
+{
+    fn bytes(&mut self, mut bytes: &[u8])
+    {
+        self.checksum.update(bytes);
+        while !bytes.is_empty() {
+            if self.len == CHUNK_SIZE {
+                self.flush();
+            }
+            let n = bytes.len().min(CHUNK_SIZE - self.len);
+            self.buffer[self.len .. self.len + n]
+                .copy_from_slice(&bytes[.. n]);
+            self.len += n;
+            bytes = &bytes[n ..];
+        }
+    }
+
+    fn byte(&mut self, byte: u8)
+    {
+        self.bytes(&[byte]);
+    }
+
+    fn varint(&mut self, mut value: u64)
+    {
+        while value >= 0x80 {
+            self.byte(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.byte(value as u8);
+    }
+
+    /// Write a byte string prefixed with its length.
+    fn string(&mut self, bytes: &[u8])
+    {
+        self.varint(bytes.len() as u64);
+        self.bytes(bytes);
+    }
+
+    fn flush(&mut self)
+    {
+        if self.len != 0 {
+            (self.sink)(&self.buffer[.. self.len]);
+            self.len = 0;
+        }
+    }
+}
+
+/// State of [`Heap::serialize_term`].
+struct Encoder<'h, F>
+{
+    writer: Writer<F>,
+
+    /// The index of the record of every object written so far,
+    /// by the address of the object.
+    indices: HeapMap<'h, usize, usize>,
+
+    error: Option<SerializeError>,
+}
+
+fn address(object: ScopedHandle) -> usize
+{
+    object.as_unsafe_handle().as_ptr() as usize
+}
+
+impl<'h, F> Visitor<'h> for Encoder<'h, F>
+    where F: FnMut(&[u8])
+{
+    fn pre(&mut self, object: ScopedHandle<'h, '_>) -> bool
+    {
+        self.error.is_none() && !self.indices.contains_key(&address(object))
+    }
+
+    fn post(&mut self, object: ScopedHandle<'h, '_>)
+    {
+        let address = address(object);
+        if self.error.is_some() || self.indices.contains_key(&address) {
+            return;
+        }
+        let index = self.indices.len();
+        match self.encode(index, object) {
+            Ok(()) => { self.indices.insert(address, index); },
+            Err(err) => self.error = Some(err),
+        }
+    }
+}
+
+impl<'h, F> Encoder<'h, F>
+    where F: FnMut(&[u8])
+{
+    /// Write the record for an object whose children were written.
+    fn encode(&mut self, index: usize, object: ScopedHandle)
+        -> Result<(), SerializeError>
+    {
+        if let Some(DeBruijn(i)) = object.as_variable() {
+            self.writer.byte(TAG_VARIABLE);
+            self.writer.varint(i.into());
+            return Ok(());
+        }
+        if let Some(Metavariable(m)) = object.as_metavariable() {
+            self.writer.byte(TAG_METAVARIABLE);
+            self.writer.varint(m.into());
+            return Ok(());
+        }
+        if let Some(char) = object.as_char() {
+            self.writer.byte(TAG_CHAR);
+            self.writer.varint(u32::from(char).into());
+            return Ok(());
+        }
+
+        let is_gensym = object.is_gensym();
+        object.with_pin(|object| {
+            if let Some(name) = object.as_symbol() {
+                if is_gensym {
+                    // Only the prefix, as the number is chosen anew.
+                    let end = name.iter().rposition(|&b| b == b'%');
+                    self.writer.byte(TAG_GENSYM);
+                    self.writer.string(&name[.. end.unwrap_or(0)]);
+                } else {
+                    self.writer.byte(TAG_SYMBOL);
+                    self.writer.string(name);
+                }
+            } else if let Some((ns, name)) = object.as_qualified_symbol() {
+                self.writer.byte(TAG_QUALIFIED_SYMBOL);
+                self.writer.string(ns);
+                self.writer.string(name);
+            } else if let Some((f, arguments)) = object.as_application() {
+                self.writer.byte(TAG_APPLICATION);
+                self.writer.varint(arguments.len() as u64);
+                self.reference(index, f);
+                for argument in arguments.iter() {
+                    self.reference(index, argument);
+                }
+            } else if let Some((head, tail)) = object.as_cons() {
+                self.writer.byte(TAG_CONS);
+                self.reference(index, head);
+                self.reference(index, tail);
+            } else if let Some((term, span)) = object.as_annotated() {
+                self.writer.byte(TAG_ANNOTATED);
+                self.reference(index, term);
+                self.writer.varint(span.file.into());
+                self.writer.varint(span.start.into());
+                self.writer.varint(span.end.into());
+            } else if let Some((parent, values)) = object.as_env_frame() {
+                self.writer.byte(TAG_ENV_FRAME);
+                self.writer.varint(values.len() as u64);
+                self.reference(index, parent);
+                for value in values.iter() {
+                    self.reference(index, value);
+                }
+            } else {
+                return Err(SerializeError::Custom);
+            }
+            Ok(())
+        })
+    }
+
+    /// Refer to a child from the record with the given index.
+    fn reference(&mut self, index: usize, child: ScopedHandle)
+    {
+        let distance = index - self.indices[&address(child)];
+        self.writer.varint(distance as u64);
+    }
+}
+
+/// Incremental deserializer, created by [`Heap::with_new_deserializer`].
+///
+/// Pass the stream to [`feed`][`Self::feed`] in chunks of any size,
+/// and then call [`finish`][`Self::finish`] to obtain the term.
+/// Each record is turned into an object as soon as it is complete,
+/// so only an incomplete record at the end of a chunk is buffered,
+/// and only while it does not exceed the [limits][`DeserializeLimits`].
+/// The checksum is only verified at the end of the stream,
+/// so until [`finish`][`Self::finish`] succeeds,
+/// the objects created so far must not be relied upon.
+pub struct Deserializer<'g, 'h>
+{
+    /// The object of every record read so far.
+    objects: GrowableScope<'g, 'h>,
+
+    /// The depth of every object, as limited by [`DeserializeLimits`].
+    depths: HeapVec<'h, usize>,
+
+    limits: DeserializeLimits,
+
+    /// The bytes of the incomplete record at the end of the stream.
+    pending: HeapVec<'h, u8>,
+
+    /// The number of pending bytes below which
+    /// the incomplete record cannot be complete.
+    needed: usize,
+
+    /// The offset into the stream of the first pending byte.
+    offset: usize,
+
+    /// What is expected next in the stream.
+    state: State,
+
+    /// Checksum of all bytes before the first pending byte.
+    checksum: Checksum,
+}
+
+/// Part of the stream that a [`Deserializer`] is at.
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum State
+{
+    /// The magic number and the version.
+    Header,
+
+    /// A record or the end marker.
+    Records,
+
+    /// Nothing, as the checksum was read.
+    Done,
+}
+
+/// Why a record could not be turned into an object.
+enum Stop
+{
+    /// More bytes are needed, and the item takes up
+    /// at least this many bytes, counted from its start.
+    Incomplete{needed: usize},
+
+    Failed(DeserializeError),
+}
+
+impl From<DeserializeError> for Stop
+{
+    fn from(other: DeserializeError) -> Self
+    {
+        Self::Failed(other)
+    }
+}
+
+impl From<FormatError> for Stop
+{
+    fn from(other: FormatError) -> Self
+    {
+        Self::Failed(other.into())
+    }
+}
+
+impl From<LimitError> for Stop
+{
+    fn from(other: LimitError) -> Self
+    {
+        Self::Failed(other.into())
+    }
+}
+
+impl From<AllocError> for Stop
+{
+    fn from(other: AllocError) -> Self
+    {
+        Self::Failed(other.into())
+    }
+}
+
+/// A record whose children have been looked up.
+enum Record<'a, 'h>
+{
+    Symbol(&'a [u8]),
+    Gensym(&'a [u8]),
+    QualifiedSymbol(&'a [u8], &'a [u8]),
+    Variable(DeBruijn),
+    Metavariable(Metavariable),
+    Char(char),
+
+    /// The function, then the arguments.
+    Application(HeapVec<'h, usize>),
+
+    /// The head, then the tail.
+    Cons([usize; 2]),
+
+    Annotated(usize, Span),
+
+    /// The parent, then the slots.
+    EnvFrame(HeapVec<'h, usize>),
+}
+
+impl<'g, 'h> Deserializer<'g, 'h>
+{
+    /// Read the next chunk of the stream.
+    ///
+    /// After an error is returned, the deserializer must not be used.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<(), DeserializeError>
+    {
+        // Reading the incomplete record again is pointless
+        // until enough bytes have arrived for it to be complete.
+        let buffered = !self.pending.is_empty();
+        if buffered && self.pending.len() + chunk.len() < self.needed {
+            self.pending.extend_from_slice(chunk);
+            return Ok(());
+        }
+
+        let heap = self.objects.heap();
+        let mut pending = mem::replace(&mut self.pending, heap.new_vec());
+        if buffered {
+            pending.extend_from_slice(chunk);
+        }
+        let bytes: &[u8] = if buffered { &pending } else { chunk };
+
+        let mut consumed = 0;
+        loop {
+            let mut reader = Reader{
+                bytes: &bytes[consumed ..],
+                offset: self.offset + consumed,
+                pos: 0,
+            };
+            match self.read_item(&mut reader) {
+                Ok(()) => {
+                    self.checksum.update(&reader.bytes[.. reader.pos]);
+                    consumed += reader.pos;
+                },
+                Err(Stop::Incomplete{needed}) => {
+                    self.needed = needed;
+                    break;
+                },
+                Err(Stop::Failed(err)) => return Err(err),
+            }
+        }
+
+        // Do not buffer a record that cannot be within the limits.
+        if self.needed > self.max_record_size() {
+            let offset = self.offset + consumed;
+            return Err(LimitError::PayloadSize{offset}.into());
+        }
+
+        if buffered {
+            pending.drain(.. consumed);
+        } else {
+            pending.extend_from_slice(&chunk[consumed ..]);
+        }
+        self.offset += consumed;
+        self.pending = pending;
+        Ok(())
+    }
+
+    /// The number of bytes that a record within the limits
+    /// takes up at most, not counting the checksum.
+    fn max_record_size(&self) -> usize
+    {
+        // Each field is referred to in at most a varint,
+        // and takes up a handle in the payload.
+        let handle_size = size_of::<UnsafeHandle>();
+        let max_fields = self.limits.max_payload_size / handle_size;
+        let max_body = self.limits.max_payload_size
+            .max(max_fields.saturating_mul(MAX_VARINT_SIZE))
+            .max(4 * MAX_VARINT_SIZE);
+        // The tag, and up to two lengths or a count before the body.
+        max_body.saturating_add(1 + 2 * MAX_VARINT_SIZE)
+    }
+
+    /// Check that the stream is complete, and obtain the term.
+    pub fn finish<'s>(&self, into: ScopedHandle<'h, 's>)
+        -> Result<(), FormatError>
+    {
+        if self.state != State::Done {
+            return Err(FormatError::Truncated);
+        }
+        let objects = self.objects.as_scope();
+        into.copy_from(objects.get(objects.len() - 1).unwrap());
+        Ok(())
+    }
+
+    /// Read whatever is expected next in the stream.
+    fn read_item(&mut self, reader: &mut Reader) -> Result<(), Stop>
+    {
+        let offset = reader.offset;
+        let corrupt = FormatError::Corrupt{offset};
+        match self.state {
+            State::Header => {
+                if reader.bytes(MAGIC.len())? != MAGIC {
+                    return Err(corrupt.into());
+                }
+                let version = reader.u32()?;
+                if version != VERSION {
+                    return Err(FormatError::UnsupportedVersion{version}.into());
+                }
+                self.state = State::Records;
+                Ok(())
+            },
+            State::Records if reader.peek()? == TAG_END => {
+                reader.byte()?;
+                let mut checksum = self.checksum;
+                checksum.update(&reader.bytes[.. reader.pos]);
+                let offset = reader.offset + reader.pos;
+                let expected = reader.bytes(4)?;
+                let expected = u32::from_le_bytes(expected.try_into().unwrap());
+                if checksum.finish() != expected {
+                    return Err(FormatError::Corrupt{offset}.into());
+                }
+                // There must be a term.
+                if self.objects.as_scope().is_empty() {
+                    return Err(corrupt.into());
+                }
+                self.state = State::Done;
+                Ok(())
+            },
+            State::Records => self.read_object(reader),
+            State::Done => {
+                // Nothing may follow the checksum.
+                reader.byte()?;
+                Err(corrupt.into())
+            },
+        }
+    }
+
+    /// Read a record and create the object it describes.
+    fn read_object(&mut self, reader: &mut Reader) -> Result<(), Stop>
+    {
+        let offset = reader.offset;
+        let corrupt = FormatError::Corrupt{offset};
+        if self.depths.len() >= self.limits.max_objects {
+            return Err(LimitError::NumObjects{offset}.into());
+        }
+        let record = self.read_record(reader)?;
+
+        let children = match &record {
+            Record::Application(fields) | Record::EnvFrame(fields) => fields,
+            Record::Cons(fields) => &fields[..],
+            Record::Annotated(term, _) => slice::from_ref(term),
+            _ => &[][..],
+        };
+        let depth = 1 + children.iter().map(|&i| self.depths[i]).max()
+            .unwrap_or(0);
+        if depth > self.limits.max_depth {
+            return Err(LimitError::Depth{offset}.into());
+        }
+
+        let heap = self.objects.heap();
+        let index = self.objects.push();
+        let objects = self.objects.as_scope();
+        let into = objects.get(index).unwrap();
+        let get = |index: usize| objects.get(index).unwrap();
+
+        let result = match record {
+            Record::Symbol(name) =>
+                heap.try_new_symbol(into, name)
+                    .map_err(|err| symbol_error(err, corrupt)),
+            Record::Gensym(prefix) =>
+                heap.try_new_gensym(into, prefix)
+                    .map_err(|err| symbol_error(err, corrupt)),
+            Record::QualifiedSymbol(ns, name) =>
+                heap.try_new_qualified_symbol(into, ns, name)
+                    .map_err(|err| symbol_error(err, corrupt)),
+            Record::Variable(de_bruijn) =>
+                heap.try_new_variable(into, de_bruijn).map_err(Stop::from),
+            Record::Metavariable(metavariable) =>
+                heap.try_new_metavariable(into, metavariable)
+                    .map_err(Stop::from),
+            Record::Char(char) =>
+                heap.try_new_char(into, char).map_err(Stop::from),
+            Record::Application(fields) => {
+                let arguments = fields[1 ..].iter().map(|&i| get(i));
+                heap.try_new_application(into, get(fields[0]), arguments)
+                    .map_err(|err| match err {
+                        TryNewApplicationError::NumArguments(_) =>
+                            corrupt.into(),
+                        TryNewApplicationError::Alloc(err) => err.into(),
+                    })
+            },
+            Record::Cons([head, tail]) =>
+                heap.try_new_cons(into, get(head), get(tail))
+                    .map_err(Stop::from),
+            Record::Annotated(term, span) =>
+                heap.try_new_annotated(into, get(term), span)
+                    .map_err(Stop::from),
+            Record::EnvFrame(fields) => {
+                let values = fields[1 ..].iter().map(|&i| get(i));
+                heap.try_new_env_frame(into, get(fields[0]), values)
+                    .map_err(|err| match err {
+                        TryNewEnvFrameError::NumSlots(_) => corrupt.into(),
+                        TryNewEnvFrameError::Alloc(err) => err.into(),
+                    })
+            },
+        };
+
+        match result {
+            Ok(()) => self.depths.push(depth),
+            Err(_) => self.objects.pop(),
+        }
+        result
+    }
+
+    /// Read a record without creating the object it describes.
+    fn read_record<'a>(&self, reader: &mut Reader<'a>)
+        -> Result<Record<'a, 'h>, Stop>
+    {
+        let offset = reader.offset;
+        let corrupt = FormatError::Corrupt{offset};
+        let record = match reader.byte()? {
+            TAG_SYMBOL => Record::Symbol(self.read_string(reader, offset)?),
+            TAG_GENSYM => Record::Gensym(self.read_string(reader, offset)?),
+            TAG_QUALIFIED_SYMBOL => {
+                let namespace = self.read_string(reader, offset)?;
+                let name = self.read_string(reader, offset)?;
+                let payload_size = namespace.len() + name.len();
+                if payload_size > self.limits.max_payload_size {
+                    return Err(LimitError::PayloadSize{offset}.into());
+                }
+                Record::QualifiedSymbol(namespace, name)
+            },
+            TAG_VARIABLE => Record::Variable(DeBruijn(reader.u32()?)),
+            TAG_METAVARIABLE =>
+                Record::Metavariable(Metavariable(reader.u32()?)),
+            TAG_CHAR => {
+                let char = char::from_u32(reader.u32()?).ok_or(corrupt)?;
+                Record::Char(char)
+            },
+            TAG_APPLICATION =>
+                Record::Application(self.read_fields(reader, offset)?),
+            TAG_CONS => {
+                let head = self.read_reference(reader)?;
+                let tail = self.read_reference(reader)?;
+                Record::Cons([head, tail])
+            },
+            TAG_ANNOTATED => {
+                let term = self.read_reference(reader)?;
+                let file = reader.u32()?;
+                let start = reader.u32()?;
+                let end = reader.u32()?;
+                Record::Annotated(term, Span{file, start, end})
+            },
+            TAG_ENV_FRAME =>
+                Record::EnvFrame(self.read_fields(reader, offset)?),
+            _ => return Err(corrupt.into()),
+        };
+        Ok(record)
+    }
+
+    /// Read a byte string for the record at the given offset.
+    fn read_string<'a>(&self, reader: &mut Reader<'a>, offset: usize)
+        -> Result<&'a [u8], Stop>
+    {
+        let len = reader.usize()?;
+        if len > self.limits.max_payload_size {
+            return Err(LimitError::PayloadSize{offset}.into());
+        }
+        reader.bytes(len)
+    }
+
+    /// Read a count followed by one more reference than that,
+    /// for the record at the given offset.
+    fn read_fields(&self, reader: &mut Reader, offset: usize)
+        -> Result<HeapVec<'h, usize>, Stop>
+    {
+        let count = reader.usize()?;
+        let payload_size = count.checked_add(1)
+            .and_then(|n| n.checked_mul(size_of::<UnsafeHandle>()));
+        match payload_size {
+            Some(size) if size <= self.limits.max_payload_size => (),
+            _ => return Err(LimitError::PayloadSize{offset}.into()),
+        }
+        // Do not trust the count with the size of the allocation.
+        let mut fields = self.objects.heap().new_vec();
+        for i in 0 ..= count {
+            match self.read_reference(reader) {
+                Ok(field) => fields.push(field),
+                // Each of the remaining references takes up a byte or more.
+                Err(Stop::Incomplete{needed}) =>
+                    return Err(Stop::Incomplete{needed: needed + count - i}),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(fields)
+    }
+
+    /// Read a reference to an earlier record, and return its index.
+    fn read_reference(&self, reader: &mut Reader) -> Result<usize, Stop>
+    {
+        let offset = reader.offset + reader.pos;
+        let distance = reader.usize()?;
+        let len = self.objects.as_scope().len();
+        match len.checked_sub(distance) {
+            Some(index) if distance != 0 => Ok(index),
+            _ => Err(FormatError::Corrupt{offset}.into()),
+        }
+    }
+}
+
+fn symbol_error(err: TryNewSymbolError, corrupt: FormatError) -> Stop
+{
+    match err {
+        TryNewSymbolError::Len(_) => corrupt.into(),
+        TryNewSymbolError::Alloc(err) => err.into(),
+    }
+}
+
+/// CRC-32 checksum, as used by zlib and PNG.
+#[derive(Clone, Copy)]
+struct Checksum(u32);
+
+/// The checksum of every byte, computed at compile time.
+const CHECKSUM_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 == 0 { c >> 1 } else { 0xEDB88320 ^ (c >> 1) };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+impl Checksum
+{
+    const NEW: Self = Self(!0);
+
+    fn update(&mut self, bytes: &[u8])
+    {
+        for &byte in bytes {
+            let index = (self.0 ^ u32::from(byte)) & 0xFF;
+            self.0 = CHECKSUM_TABLE[index as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    fn finish(self) -> u32
+    {
+        !self.0
+    }
+}
+
+/// Cursor into the bytes of a record.
+struct Reader<'a>
+{
+    bytes: &'a [u8],
+
+    /// The offset into the stream of the first byte.
+    offset: usize,
+
+    pos: usize,
+}
+
+impl<'a> Reader<'a>
+{
+    fn peek(&self) -> Result<u8, Stop>
+    {
+        let needed = self.pos + 1;
+        self.bytes.get(self.pos).copied().ok_or(Stop::Incomplete{needed})
+    }
+
+    fn byte(&mut self) -> Result<u8, Stop>
+    {
+        let byte = self.peek()?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Stop>
+    {
+        if self.bytes.len() - self.pos < len {
+            let needed = self.pos.saturating_add(len);
+            return Err(Stop::Incomplete{needed});
+        }
+        let bytes = &self.bytes[self.pos .. self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn varint(&mut self) -> Result<u64, Stop>
+    {
+        let offset = self.offset + self.pos;
+        let mut value = 0u64;
+        for shift in (0 .. 64).step_by(7) {
+            let byte = self.byte()?;
+            let bits = u64::from(byte & 0x7F);
+            if bits << shift >> shift != bits {
+                break;
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(FormatError::Corrupt{offset}.into())
+    }
+
+    fn u32(&mut self) -> Result<u32, Stop>
+    {
+        let offset = self.offset + self.pos;
+        let value = self.varint()?;
+        value.try_into().map_err(|_| FormatError::Corrupt{offset}.into())
+    }
+
+    fn usize(&mut self) -> Result<usize, Stop>
+    {
+        let offset = self.offset + self.pos;
+        let value = self.varint()?;
+        value.try_into().map_err(|_| FormatError::Corrupt{offset}.into())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::heap::HeapConfig;
+    use crate::object::WellKnown;
+    use crate::term;
+    use crate::testing::TermTree;
+
+    use alloc::vec::Vec;
+    use core::sync::atomic::AtomicBool;
+    use proptest::prelude::any;
+    use proptest::proptest;
+
+    fn serialize<'h>(heap: &Heap<'h>, term: ScopedHandle<'h, '_>) -> Vec<u8>
+    {
+        let mut bytes = Vec::new();
+        heap.serialize_term(term, |chunk| {
+            assert!(chunk.len() <= CHUNK_SIZE);
+            bytes.extend_from_slice(chunk);
+        }).unwrap();
+        bytes
+    }
+
+    proptest!
+    {
+        #[test]
+        fn roundtrip(tree in any::<TermTree>())
+        {
+            Heap::with_new(|heap| {
+                heap.with_new_array_scope(|[term, whole, bytewise]| {
+                    tree.build(heap, term);
+                    let bytes = serialize(heap, term);
+
+                    let limits = DeserializeLimits::default();
+                    heap.deserialize_term(whole, &bytes, limits).unwrap();
+                    assert_eq!(TermTree::read(heap, whole), tree);
+
+                    heap.with_new_deserializer(limits, |deserializer| {
+                        for byte in &bytes {
+                            deserializer.feed(&[*byte]).unwrap();
+                        }
+                        deserializer.finish(bytewise).unwrap();
+                    });
+                    assert_eq!(TermTree::read(heap, bytewise), tree);
+                });
+            });
+        }
+    }
+
+    #[test]
+    fn roundtrip_kinds()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[a, b, c, d, term, copy]| {
+                let span = Span{file: 1, start: 200, end: 300};
+                heap.new_gensym(a, b"x").unwrap();
+                heap.new_qualified_symbol(b, b"ns", b"name").unwrap();
+                heap.new_char(c, 'λ');
+                heap.new_annotated(c, c, span);
+                let nil = heap.interned_symbol(WellKnown::Nil);
+                // SAFETY: Interned objects are not destroyed.
+                unsafe { d.copy_from_unsafe_handle(nil) };
+                heap.new_cons(d, a, d);
+                heap.new_env_frame(d, d, [b, c]).unwrap();
+                term!(heap, term, (F {a} {d} {a} ?7));
+
+                let bytes = serialize(heap, term);
+                heap.deserialize_term(copy, &bytes, Default::default())
+                    .unwrap();
+                heap.verify();
+
+                copy.with_pin(|copy| {
+                    let (_, arguments) = copy.as_application().unwrap();
+                    let [a, d, a2, m] =
+                        [0, 1, 2, 3].map(|i| arguments.get(i).unwrap());
+                    assert!(a.ptr_eq(a2));
+                    assert!(a.is_gensym());
+                    assert_eq!(m.as_metavariable(), Some(Metavariable(7)));
+                    d.with_pin(|d| {
+                        let (parent, values) = d.as_env_frame().unwrap();
+                        parent.with_pin(|parent| {
+                            let (head, _) = parent.as_cons().unwrap();
+                            assert!(head.ptr_eq(a));
+                        });
+                        values.get(0).unwrap().with_pin(|b| {
+                            let qualified = b.as_qualified_symbol();
+                            assert_eq!(
+                                qualified,
+                                Some((&b"ns"[..], &b"name"[..])),
+                            );
+                        });
+                        values.get(1).unwrap().with_pin(|c| {
+                            let (c, got) = c.as_annotated().unwrap();
+                            assert_eq!(got, span);
+                            assert_eq!(c.as_char(), Some('λ'));
+                        });
+                    });
+                });
+            });
+        });
+    }
+
+    /// Surround the records with the header and the trailer.
+    fn framed(records: &[u8]) -> Vec<u8>
+    {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION as u8);
+        bytes.extend_from_slice(records);
+        bytes.push(TAG_END);
+        let mut checksum = Checksum::NEW;
+        checksum.update(&bytes);
+        bytes.extend_from_slice(&checksum.finish().to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn checksum()
+    {
+        let mut checksum = Checksum::NEW;
+        checksum.update(b"123456789");
+        assert_eq!(checksum.finish(), 0xCBF43926);
+    }
+
+    #[test]
+    fn malformed()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[term]| {
+                let corrupt = |bytes: &[u8]| {
+                    let limits = DeserializeLimits::default();
+                    match heap.deserialize_term(term, bytes, limits) {
+                        Err(DeserializeError::Format(err)) => err,
+                        other => panic!("{:?}", other),
+                    }
+                };
+                let at = |offset| FormatError::Corrupt{offset};
+
+                let truncated = FormatError::Truncated;
+                assert_eq!(corrupt(b""), truncated);
+                assert_eq!(corrupt(b"AURM\x01"), truncated);
+                assert_eq!(corrupt(b"AURM\x01\x00\x03a"), truncated);
+
+                // Wrong magic number and unsupported version.
+                assert_eq!(corrupt(b"AURN\x01"), at(0));
+                assert_eq!(
+                    corrupt(b"AURM\x02"),
+                    FormatError::UnsupportedVersion{version: 2},
+                );
+
+                // Unknown tag in the second record.
+                assert_eq!(corrupt(&framed(&[TAG_VARIABLE, 0, 99])), at(7));
+
+                // Reference to a record that does not exist.
+                let bytes = framed(&[TAG_VARIABLE, 0, TAG_CONS, 1, 2]);
+                assert_eq!(corrupt(&bytes), at(9));
+
+                // Surrogates are not characters.
+                let bytes = framed(&[TAG_CHAR, 0x80, 0xB0, 0x03]);
+                assert_eq!(corrupt(&bytes), at(5));
+
+                // There must be a term.
+                assert_eq!(corrupt(&framed(&[])), at(5));
+
+                // Damage is caught by the checksum.
+                let mut bytes = framed(&[TAG_SYMBOL, 1, b'A']);
+                heap.deserialize_term(term, &bytes, Default::default())
+                    .unwrap();
+                bytes[7] = b'B';
+                assert_eq!(corrupt(&bytes), at(9));
+
+                // Nothing may follow the checksum.
+                let mut bytes = framed(&[TAG_SYMBOL, 1, b'A']);
+                bytes.push(0);
+                assert_eq!(corrupt(&bytes), at(13));
+            });
+        });
+    }
+
+    #[test]
+    fn limits()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[term]| {
+                let exceeds = |bytes: &[u8], limits| {
+                    match heap.deserialize_term(term, bytes, limits) {
+                        Err(DeserializeError::Limit(err)) => err,
+                        other => panic!("{:?}", other),
+                    }
+                };
+                let unlimited = DeserializeLimits::default();
+
+                // Each record refers to the one before it twice,
+                // so the term has 2ⁿ paths but only n + 1 objects.
+                let mut records = [TAG_VARIABLE, 0].to_vec();
+                for _ in 0 .. 40 {
+                    records.extend_from_slice(&[TAG_CONS, 1, 1]);
+                }
+                let bytes = framed(&records);
+                heap.deserialize_term(term, &bytes, unlimited).unwrap();
+                let untrusted = DeserializeLimits::untrusted();
+                heap.deserialize_term(term, &bytes, untrusted).unwrap();
+
+                // The eleventh object is the tenth cons cell,
+                // which is also the first at depth eleven.
+                let offset = 5 + 2 + 9 * 3;
+                let limits = DeserializeLimits{max_objects: 10, ..unlimited};
+                assert_eq!(
+                    exceeds(&bytes, limits),
+                    LimitError::NumObjects{offset},
+                );
+                let limits = DeserializeLimits{max_depth: 10, ..unlimited};
+                assert_eq!(exceeds(&bytes, limits), LimitError::Depth{offset});
+
+                // A long list is too deep to be trusted.
+                let mut records = [TAG_VARIABLE, 0].to_vec();
+                for _ in 0 .. 2000 {
+                    records.extend_from_slice(&[TAG_VARIABLE, 0]);
+                    records.extend_from_slice(&[TAG_CONS, 1, 2]);
+                }
+                let bytes = framed(&records);
+                heap.deserialize_term(term, &bytes, unlimited).unwrap();
+                let offset = 5 + 2 + 1023 * 5 + 2;
+                assert_eq!(
+                    exceeds(&bytes, untrusted),
+                    LimitError::Depth{offset},
+                );
+
+                // The name is rejected before it is read.
+                let limits =
+                    DeserializeLimits{max_payload_size: 4, ..unlimited};
+                let bytes = b"AURM\x01\x00\x05";
+                assert_eq!(
+                    exceeds(bytes, limits),
+                    LimitError::PayloadSize{offset: 5},
+                );
+                let bytes = framed(&[TAG_VARIABLE, 0, TAG_CONS, 1, 1]);
+                heap.deserialize_term(term, &bytes, limits).unwrap();
+                let bytes = framed(&[TAG_VARIABLE, 0, TAG_APPLICATION, 0, 1]);
+                assert_eq!(
+                    exceeds(&bytes, limits),
+                    LimitError::PayloadSize{offset: 7},
+                );
+            });
+        });
+    }
+
+    #[test]
+    fn interrupt()
+    {
+        let flag = AtomicBool::new(true);
+        let config = HeapConfig{
+            interrupt: Some(&flag),
+            ..HeapConfig::default()
+        };
+        Heap::with_new_config(config, |heap| {
+            heap.with_new_array_scope(|[term]| {
+                term!(heap, term, (F A));
+                let result = heap.serialize_term(term, |_| ());
+                assert!(matches!(result, Err(SerializeError::Interrupted(_))));
+            });
+        });
+    }
+
+    #[test]
+    fn long_record()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[term]| {
+                // The name is buffered until it is complete.
+                let name = [b'a'; 1000];
+                let mut records = [TAG_SYMBOL, 0xE8, 0x07].to_vec();
+                records.extend_from_slice(&name);
+                let bytes = framed(&records);
+                let limits = DeserializeLimits::default();
+                heap.with_new_deserializer(limits, |deserializer| {
+                    for chunk in bytes.chunks(7) {
+                        deserializer.feed(chunk).unwrap();
+                    }
+                    deserializer.finish(term).unwrap();
+                });
+                term.with_pin(|term| {
+                    assert_eq!(term.as_symbol(), Some(&name[..]));
+                });
+            });
+        });
+    }
+}
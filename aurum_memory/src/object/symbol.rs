@@ -1,18 +1,103 @@
+use crate::heap::AllocError;
 use crate::heap::Heap;
 use crate::heap::PinnedHandle;
 use crate::heap::ScopedHandle;
 use super::Flags;
+use super::HEADER_SIZE;
 use super::FreeCache;
 use super::Header;
 use super::Kind;
+use super::Payload;
 
 use core::mem::MaybeUninit;
+use core::mem::size_of;
 use core::slice;
 
 /// Raised when attempting to create a symbol with a name that is too long.
 #[derive(Debug)]
 pub struct SymbolLenError;
 
+/// Raised when [`Heap::try_new_symbol`] fails.
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub enum TryNewSymbolError
+{
+    Len(SymbolLenError),
+    Alloc(AllocError),
+}
+
+impl From<SymbolLenError> for TryNewSymbolError
+{
+    fn from(other: SymbolLenError) -> Self
+    {
+        Self::Len(other)
+    }
+}
+
+impl From<AllocError> for TryNewSymbolError
+{
+    fn from(other: AllocError) -> Self
+    {
+        Self::Alloc(other)
+    }
+}
+
+/// Symbols with commonly used names, which are interned by every heap.
+///
+/// Use [`Heap::interned_symbol`] to obtain them.
+#[allow(missing_docs)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WellKnown
+{
+    Null,
+    True,
+    False,
+    Cons,
+    Nil,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Equal,
+    Less,
+}
+
+impl WellKnown
+{
+    /// All well-known symbols, in order.
+    pub const ALL: [Self; 11] = [
+        Self::Null,
+        Self::True,
+        Self::False,
+        Self::Cons,
+        Self::Nil,
+        Self::Add,
+        Self::Subtract,
+        Self::Multiply,
+        Self::Divide,
+        Self::Equal,
+        Self::Less,
+    ];
+
+    /// The name of the symbol.
+    pub fn name(self) -> &'static [u8]
+    {
+        match self {
+            Self::Null => b"Null",
+            Self::True => b"True",
+            Self::False => b"False",
+            Self::Cons => b"Cons",
+            Self::Nil => b"Nil",
+            Self::Add => b"Add",
+            Self::Subtract => b"Subtract",
+            Self::Multiply => b"Multiply",
+            Self::Divide => b"Divide",
+            Self::Equal => b"Equal",
+            Self::Less => b"Less",
+        }
+    }
+}
+
 /// Methods for creating symbol objects.
 impl<'h> Heap<'h>
 {
@@ -21,40 +106,132 @@ impl<'h> Heap<'h>
     pub fn new_symbol<'s>(&self, into: ScopedHandle<'h, 's>, name: &[u8])
         -> Result<(), SymbolLenError>
     {
-        const ERR: SymbolLenError = SymbolLenError;
-        let payload_size = name.len();
-        let name_len: u32 = name.len().try_into().map_err(|_| ERR)?;
-
+        let payload_size = payload_size(name)?;
         unsafe {
-            self.new(into, payload_size, |payload| {
-
-                // The extra field stores the length of the name.
-                let mut extra = MaybeUninit::uninit_array();
-                MaybeUninit::write_slice(&mut extra, &name_len.to_ne_bytes());
-
-                // The payload stores the bytes of the name.
-                MaybeUninit::write_slice(
-                    slice::from_raw_parts_mut(
-                        payload as *mut MaybeUninit<u8>,
-                        name.len(),
-                    ),
-                    name
-                );
-
-                Header{
-                    kind: Kind::Symbol,
-                    flags: Flags::empty(),
-                    free_cache: FreeCache::EMPTY,
-                    extra,
-                }
-
-            });
+            self.new(into, payload_size, |payload| init_symbol(payload, name));
         }
+        Ok(())
+    }
 
+    /// Similar to [`new_symbol`][`Self::new_symbol`],
+    /// but return an error if memory cannot be allocated.
+    #[inline]
+    pub fn try_new_symbol<'s>(&self, into: ScopedHandle<'h, 's>, name: &[u8])
+        -> Result<(), TryNewSymbolError>
+    {
+        let payload_size = payload_size(name)?;
+        unsafe {
+            self.try_new(into, payload_size, |payload| {
+                init_symbol(payload, name)
+            })?;
+        }
         Ok(())
     }
 }
 
+/// Whether the symbol can be stored using [`Kind::SmallSymbol`].
+fn is_small(name: &[u8]) -> bool
+{
+    name.len() <= 4 && name.last() != Some(&0)
+}
+
+/// Whether the symbol must be stored using [`Kind::LargeSymbol`].
+fn is_large(name: &[u8]) -> bool
+{
+    u32::try_from(name.len()).is_err()
+}
+
+/// The payload size of a symbol with the given name.
+pub (super) fn payload_size(name: &[u8]) -> Result<usize, SymbolLenError>
+{
+    if is_small(name) {
+        Ok(0)
+    } else if is_large(name) {
+        large_payload_size(name)
+    } else {
+        Ok(name.len())
+    }
+}
+
+/// The payload size of a [`Kind::LargeSymbol`] with the given name.
+pub (super) fn large_payload_size(name: &[u8])
+    -> Result<usize, SymbolLenError>
+{
+    // Leave room for the length and the object header,
+    // and make sure the object size is a valid layout size.
+    let size = name.len().checked_add(size_of::<u64>() + HEADER_SIZE + 7);
+    match size {
+        Some(size) if size <= isize::MAX as usize =>
+            Ok(size_of::<u64>() + name.len()),
+        _ => Err(SymbolLenError),
+    }
+}
+
+/// Initialize a symbol object with the given name.
+///
+/// # Safety
+///
+/// The payload size must have been computed by [`payload_size`]
+/// from the name, and the payload must be that large.
+pub (super) unsafe fn init_symbol(payload: *mut Payload, name: &[u8]) -> Header
+{
+    let mut extra = MaybeUninit::uninit_array();
+
+    let kind = if is_small(name) {
+        // The extra field stores the name, padded with zeroes.
+        let mut padded = [0; 4];
+        padded[.. name.len()].copy_from_slice(name);
+        MaybeUninit::write_slice(&mut extra, &padded);
+        Kind::SmallSymbol
+    } else if is_large(name) {
+        init_large_name(payload, name);
+        Kind::LargeSymbol
+    } else {
+        // The extra field stores the length of the name.
+        let name_len = name.len() as u32;
+        MaybeUninit::write_slice(&mut extra, &name_len.to_ne_bytes());
+
+        // The payload stores the bytes of the name.
+        MaybeUninit::write_slice(
+            slice::from_raw_parts_mut(
+                payload as *mut MaybeUninit<u8>,
+                name.len(),
+            ),
+            name
+        );
+
+        Kind::Symbol
+    };
+
+    Header{
+        kind,
+        flags: Flags::empty(),
+        free_cache: FreeCache::EMPTY,
+        extra,
+    }
+}
+
+/// Write the name of a [`Kind::LargeSymbol`] to its payload.
+///
+/// # Safety
+///
+/// The payload size must have been computed by [`large_payload_size`]
+/// from the name, and the payload must be that large.
+pub (super) unsafe fn init_large_name(payload: *mut Payload, name: &[u8])
+{
+    // The payload stores the length of the name,
+    // followed by the bytes of the name.
+    let name_len = payload as *mut u64;
+    *name_len = name.len() as u64;
+    MaybeUninit::write_slice(
+        slice::from_raw_parts_mut(
+            name_len.add(1) as *mut MaybeUninit<u8>,
+            name.len(),
+        ),
+        name
+    );
+}
+
 /// Methods for inspecting symbol objects.
 impl<'h, 'p> PinnedHandle<'h, 'p>
 {
@@ -79,6 +256,35 @@ impl<'h, 'p> PinnedHandle<'h, 'p>
                 };
                 Some(name)
             },
+            Kind::LargeSymbol | Kind::Gensym => {
+                let name_len = self.payload() as *const u64;
+                let name = unsafe {
+                    slice::from_raw_parts(
+                        name_len.add(1) as *const u8,
+                        *name_len as usize,
+                    )
+                };
+                Some(name)
+            },
+            Kind::SmallSymbol => {
+                // The name is padded with zeroes, but does not end in one.
+                let extra = header.extra;
+                let extra = unsafe { MaybeUninit::array_assume_init(extra) };
+                let name_len = 4 - extra.iter().rev()
+                    .take_while(|&&b| b == 0)
+                    .count();
+                // SAFETY: The header lives as long as the pin.
+                let extra = unsafe {
+                    &(*self.as_unsafe_handle().header()).extra
+                };
+                let name = unsafe {
+                    slice::from_raw_parts(
+                        extra.as_ptr() as *const u8,
+                        name_len,
+                    )
+                };
+                Some(name)
+            },
             _ => None,
         }
     }
@@ -92,6 +298,32 @@ mod tests
     use alloc::vec::Vec;
     use proptest::proptest;
 
+    #[test]
+    fn small()
+    {
+        let names: [(&[u8], bool); 7] = [
+            (b"", true),
+            (b"a", true),
+            (b"\0a", true),
+            (b"abcd", true),
+            (b"abcde", false),
+            (b"a\0", false),
+            (b"\0", false),
+        ];
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[handle]| {
+                for (name, small) in names {
+                    heap.new_symbol(handle, name).unwrap();
+                    let header = handle.header();
+                    assert_eq!(matches!(header.kind, Kind::SmallSymbol), small);
+                    handle.with_pin(|handle| {
+                        assert_eq!(handle.as_symbol(), Some(name));
+                    });
+                }
+            });
+        });
+    }
+
     proptest!
     {
         #[test]
@@ -0,0 +1,320 @@
+use crate::heap::Heap;
+use crate::heap::HeapVec;
+use crate::heap::Scope;
+use crate::heap::ScopedHandle;
+use super::Metavariable;
+use super::Visitor;
+use super::pattern::compare_shallow;
+
+use core::cell::Cell;
+
+/// Raised when two terms cannot be unified.
+#[derive(Debug, Eq, PartialEq)]
+pub enum UnifyError
+{
+    /// The terms differ at some position where neither has a metavariable.
+    Mismatch,
+
+    /// The metavariable would have to be replaced by a term containing it.
+    Occurs(Metavariable),
+}
+
+/// Methods for unifying terms.
+impl<'h> Heap<'h>
+{
+    /// Find a substitution of metavariables that makes two terms equal.
+    ///
+    /// This is first-order unification:
+    /// [metavariables][`Metavariable`] may be replaced by any term,
+    /// including applications of which they are the function,
+    /// and all other objects must be equal, as for [`Heap::match_pattern`].
+    /// A metavariable is never replaced by a term that contains it.
+    ///
+    /// The resulting substitution is the most general one,
+    /// and is written to `subst`.
+    /// The handle at the index of each metavariable
+    /// is made to refer to the term that replaces it,
+    /// which does not contain any replaced metavariables.
+    /// Metavariables that need not be replaced refer to themselves.
+    /// The terms must not be given by handles in `subst`.
+    /// If the terms cannot be unified,
+    /// an error is returned and the handles in `subst` are unspecified.
+    ///
+    /// # Panics
+    ///
+    /// If a metavariable in either term has no handle in `subst`,
+    /// this method panics.
+    pub fn unify<'s>(
+        &self,
+        a: ScopedHandle<'h, 's>,
+        b: ScopedHandle<'h, 's>,
+        subst: &Scope<'h>,
+    ) -> Result<(), UnifyError>
+    {
+        for (m, handle) in subst.iter().enumerate() {
+            self.new_metavariable(handle, Metavariable(m as u32));
+        }
+
+        let mut bound = self.new_vec();
+        bound.resize(subst.len(), false);
+        solve(self, a, b, subst, &mut bound)?;
+        self.resolve(subst, &bound);
+        Ok(())
+    }
+
+    /// Replace the metavariables in the bindings of a solved substitution,
+    /// so that the bindings no longer contain bound metavariables.
+    fn resolve(&self, subst: &Scope<'h>, bound: &[bool])
+    {
+        // Which bindings no longer contain bound metavariables.
+        // There are no cycles, thanks to the occurs check,
+        // so resolving the bindings in dependency order terminates.
+        let mut resolved = self.new_vec();
+        resolved.resize(subst.len(), false);
+
+        let mut pending = self.new_vec();
+        for m in 0 .. subst.len() {
+            if !bound[m] {
+                resolved[m] = true;
+                continue;
+            }
+
+            pending.push(m);
+            while let Some(&n) = pending.last() {
+                if resolved[n] {
+                    pending.pop();
+                    continue;
+                }
+
+                let binding = subst.get(n).unwrap();
+                let mut dependencies = Dependencies{
+                    bound,
+                    resolved: &resolved,
+                    found: self.new_vec(),
+                };
+                self.walk(binding, &mut dependencies);
+
+                if dependencies.found.is_empty() {
+                    self.instantiate(binding, binding, subst, &resolved);
+                    resolved[n] = true;
+                    pending.pop();
+                } else {
+                    pending.extend(dependencies.found);
+                }
+            }
+        }
+    }
+}
+
+/// Solve the equation between two terms.
+///
+/// Bindings are recorded in `subst` and `bound`,
+/// but may still contain bound metavariables.
+fn solve<'h>(
+    heap: &Heap<'h>,
+    a: ScopedHandle<'h, '_>,
+    b: ScopedHandle<'h, '_>,
+    subst: &Scope<'h>,
+    bound: &mut [bool],
+) -> Result<(), UnifyError>
+{
+    // Nothing is allocated while solving, so unsafe handles are fine.
+    let mut pairs = heap.new_vec();
+    pairs.push((a.as_unsafe_handle(), b.as_unsafe_handle()));
+
+    while let Some((a, b)) = pairs.pop() {
+        let a = Cell::new(a);
+        let b = Cell::new(b);
+        // SAFETY: The objects are reachable from the given handles,
+        //         or from the bindings in the substitution.
+        let a = unsafe { ScopedHandle::new(&a) };
+        let b = unsafe { ScopedHandle::new(&b) };
+        let a = walk_bindings(a, subst, bound);
+        let b = walk_bindings(b, subst, bound);
+
+        if a.ptr_eq(b) {
+            continue;
+        }
+
+        let (metavariable, term) =
+            if let Some(metavariable) = a.as_metavariable() {
+                (metavariable, b)
+            } else if let Some(metavariable) = b.as_metavariable() {
+                (metavariable, a)
+            } else {
+                if !compare_shallow(a, b, &mut pairs) {
+                    return Err(UnifyError::Mismatch);
+                }
+                continue;
+            };
+
+        if term.as_metavariable() == Some(metavariable) {
+            continue;
+        }
+        if occurs(heap, metavariable, term, subst, bound) {
+            return Err(UnifyError::Occurs(metavariable));
+        }
+
+        let Metavariable(m) = metavariable;
+        let binding = subst.get(m as usize)
+            .expect("Metavariable has no binding");
+        binding.copy_from(term);
+        bound[m as usize] = true;
+    }
+
+    Ok(())
+}
+
+/// Follow the bindings of bound metavariables.
+fn walk_bindings<'h, 'a>(
+    mut term: ScopedHandle<'h, 'a>,
+    subst: &'a Scope<'h>,
+    bound: &[bool],
+) -> ScopedHandle<'h, 'a>
+{
+    while let Some(Metavariable(m)) = term.as_metavariable() {
+        let m = m as usize;
+        let binding = subst.get(m).expect("Metavariable has no binding");
+        if !bound[m] {
+            break;
+        }
+        term = binding;
+    }
+    term
+}
+
+/// Whether the metavariable occurs in the term,
+/// taking into account the bindings of bound metavariables.
+fn occurs<'h>(
+    heap: &Heap<'h>,
+    metavariable: Metavariable,
+    term: ScopedHandle<'h, '_>,
+    subst: &Scope<'h>,
+    bound: &[bool],
+) -> bool
+{
+    // Nothing is allocated while searching, so unsafe handles are fine.
+    let mut terms = heap.new_vec();
+    terms.push(term.as_unsafe_handle());
+
+    while let Some(term) = terms.pop() {
+        let term = Cell::new(term);
+        // SAFETY: The object is reachable from the given handle,
+        //         or from the bindings in the substitution.
+        let term = unsafe { ScopedHandle::new(&term) };
+        let term = walk_bindings(term, subst, bound);
+
+        if term.as_metavariable() == Some(metavariable) {
+            return true;
+        }
+
+        term.with_pin(|term| {
+            term.visit_children(|child| {
+                terms.push(child.as_unsafe_handle());
+            });
+        });
+    }
+
+    false
+}
+
+/// Finds bound metavariables with unresolved bindings,
+/// for [`Heap::resolve`].
+struct Dependencies<'a, 'h>
+{
+    bound: &'a [bool],
+    resolved: &'a [bool],
+    found: HeapVec<'h, usize>,
+}
+
+impl<'a, 'h> Visitor<'h> for Dependencies<'a, 'h>
+{
+    fn pre(&mut self, object: ScopedHandle<'h, '_>) -> bool
+    {
+        if let Some(Metavariable(m)) = object.as_metavariable() {
+            let m = m as usize;
+            if self.bound[m] && !self.resolved[m] {
+                self.found.push(m);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::term;
+    use crate::testing::TermTree;
+
+    #[test]
+    fn unify()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_boxed_scope(4, |subst| {
+            heap.with_new_array_scope(|[a, b, expected]| {
+                term!(heap, a, (F ?0 (G ?1) ?1));
+                term!(heap, b, (F (H ?2) ?3 A));
+                heap.unify(a, b, subst).unwrap();
+
+                let check = |m, expected| {
+                    assert_eq!(
+                        TermTree::read(heap, subst.get(m).unwrap()),
+                        TermTree::read(heap, expected),
+                    );
+                };
+                term!(heap, expected, (H ?2));
+                check(0, expected);
+                term!(heap, expected, A);
+                check(1, expected);
+                term!(heap, expected, ?2);
+                check(2, expected);
+                term!(heap, expected, (G A));
+                check(3, expected);
+            }); });
+        });
+    }
+
+    #[test]
+    fn chain()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_boxed_scope(3, |subst| {
+            heap.with_new_array_scope(|[a, b, expected]| {
+                term!(heap, a, (F ?0 ?1 ?2));
+                term!(heap, b, (F (G ?1) (G ?2) A));
+                heap.unify(a, b, subst).unwrap();
+                term!(heap, expected, (G (G A)));
+                assert_eq!(
+                    TermTree::read(heap, subst.get(0).unwrap()),
+                    TermTree::read(heap, expected),
+                );
+            }); });
+        });
+    }
+
+    #[test]
+    fn failure()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_boxed_scope(2, |subst| {
+            heap.with_new_array_scope(|[a, b]| {
+                term!(heap, a, (F ?0 ?0));
+                term!(heap, b, (F A B));
+                assert_eq!(heap.unify(a, b, subst), Err(UnifyError::Mismatch));
+
+                term!(heap, a, (F ?0 ?1));
+                term!(heap, b, (F ?1 (G ?0)));
+                assert!(matches!(
+                    heap.unify(a, b, subst),
+                    Err(UnifyError::Occurs(_)),
+                ));
+
+                term!(heap, a, (F #0));
+                term!(heap, b, (F #1));
+                assert_eq!(heap.unify(a, b, subst), Err(UnifyError::Mismatch));
+            }); });
+        });
+    }
+}
@@ -0,0 +1,74 @@
+use crate::heap::ScopedHandle;
+use super::Flags;
+
+/// Methods for tagging objects on behalf of embedders.
+///
+/// Each object has two flags, [`Flags::USER0`] and [`Flags::USER1`],
+/// that this crate never sets or reads itself.
+/// Embedder algorithms can use them to mark objects,
+/// for instance as visited, without keeping a side table.
+/// [Forking] preserves them, but they are not part of the term,
+/// so serialization drops them.
+/// As objects may be shared, clear the flags when done with them,
+/// so that other algorithms find them cleared.
+///
+/// [Forking]: `crate::heap::Heap::fork`
+impl<'h, 's> ScopedHandle<'h, 's>
+{
+    /// The user flags that are set on the object.
+    #[inline]
+    pub fn user_flags(self) -> Flags
+    {
+        self.header().flags & Flags::USER
+    }
+
+    /// Replace the user flags of the object.
+    ///
+    /// # Panics
+    ///
+    /// If `flags` contains flags other than the user flags.
+    #[inline]
+    pub fn set_user_flags(self, flags: Flags)
+    {
+        assert!(Flags::USER.contains(flags), "Not user flags: {:?}", flags);
+        // SAFETY: The handle refers to an object, as it is scoped.
+        unsafe {
+            let header = self.as_unsafe_handle().header();
+            (*header).flags.remove(Flags::USER);
+            (*header).flags.insert(flags);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::heap::Heap;
+    use crate::term;
+
+    #[test]
+    fn user_flags()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[a, b]| {
+                term!(heap, a, (F A));
+                term!(heap, b, (F A));
+                assert_eq!(a.user_flags(), Flags::empty());
+
+                a.set_user_flags(Flags::USER0);
+                b.set_user_flags(Flags::USER);
+                b.mark_normal_form();
+                assert_eq!(a.user_flags(), Flags::USER0);
+                assert_eq!(b.user_flags(), Flags::USER);
+
+                // Other flags are unaffected.
+                b.with_pin(|_| ());
+                b.set_user_flags(Flags::USER1);
+                assert_eq!(b.user_flags(), Flags::USER1);
+                assert!(b.is_normal_form());
+                heap.verify();
+            });
+        });
+    }
+}
@@ -1,3 +1,4 @@
+use crate::heap::AllocError;
 use crate::heap::Heap;
 use crate::heap::ScopedHandle;
 use super::DeBruijn;
@@ -5,6 +6,7 @@ use super::Flags;
 use super::FreeCache;
 use super::Header;
 use super::Kind;
+use super::Payload;
 
 use core::mem::MaybeUninit;
 
@@ -32,6 +34,23 @@ impl<'h> Heap<'h>
         }
     }
 
+    /// Similar to [`new_variable`][`Self::new_variable`],
+    /// but return an error if memory cannot be allocated.
+    #[inline]
+    pub fn try_new_variable<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        de_bruijn: DeBruijn,
+    ) -> Result<(), AllocError>
+    {
+        let interned = self.interned_variable(de_bruijn);
+        match interned {
+            Some(result) => unsafe { into.copy_from_unsafe_handle(result) },
+            None => self.try_new_variable_not_interned(into, de_bruijn)?,
+        }
+        Ok(())
+    }
+
     /// Create a variable with the given De Bruijn index.
     #[inline]
     pub fn new_variable_not_interned<'s>(
@@ -41,25 +60,46 @@ impl<'h> Heap<'h>
     )
     {
         unsafe {
-            self.new(into, PAYLOAD_SIZE, |_payload| {
+            self.new(into, PAYLOAD_SIZE, |payload| {
+                init_variable(payload, de_bruijn)
+            });
+        }
+    }
 
-                // The De Bruijn index is stored in the extra field.
-                let mut extra = MaybeUninit::uninit_array();
-                let extra_bytes = de_bruijn.0.to_ne_bytes();
-                MaybeUninit::write_slice(&mut extra, &extra_bytes);
+    /// Similar to
+    /// [`new_variable_not_interned`][`Self::new_variable_not_interned`],
+    /// but return an error if memory cannot be allocated.
+    #[inline]
+    pub fn try_new_variable_not_interned<'s>(
+        &self,
+        into: ScopedHandle<'h, 's>,
+        de_bruijn: DeBruijn,
+    ) -> Result<(), AllocError>
+    {
+        unsafe {
+            self.try_new(into, PAYLOAD_SIZE, |payload| {
+                init_variable(payload, de_bruijn)
+            })
+        }
+    }
+}
 
-                // The variable appears free in itself.
-                let free_cache = FreeCache::EMPTY.insert(de_bruijn);
+/// Initialize a variable object with the given De Bruijn index.
+fn init_variable(_payload: *mut Payload, de_bruijn: DeBruijn) -> Header
+{
+    // The De Bruijn index is stored in the extra field.
+    let mut extra = MaybeUninit::uninit_array();
+    let extra_bytes = de_bruijn.0.to_ne_bytes();
+    MaybeUninit::write_slice(&mut extra, &extra_bytes);
 
-                Header{
-                    kind: Kind::Variable,
-                    flags: Flags::empty(),
-                    free_cache,
-                    extra,
-                }
+    // The variable appears free in itself.
+    let free_cache = FreeCache::EMPTY.insert(de_bruijn);
 
-            });
-        }
+    Header{
+        kind: Kind::Variable,
+        flags: Flags::empty(),
+        free_cache,
+        extra,
     }
 }
 
@@ -0,0 +1,238 @@
+use crate::heap::Heap;
+use crate::heap::Interrupted;
+use crate::heap::ScopedHandle;
+
+/// Callbacks for [`Heap::walk`].
+pub trait Visitor<'h>
+{
+    /// Called for an object before any of its children are walked.
+    ///
+    /// If this returns false, the children of the object are skipped.
+    /// This is useful for not walking shared objects more than once.
+    fn pre(&mut self, _object: ScopedHandle<'h, '_>) -> bool
+    {
+        true
+    }
+
+    /// Called for an object after all of its children have been walked.
+    ///
+    /// This is called even if [`pre`][`Self::pre`] returned false.
+    fn post(&mut self, _object: ScopedHandle<'h, '_>)
+    {
+    }
+}
+
+impl<'h> Heap<'h>
+{
+    /// Walk the given term and all objects reachable from it.
+    ///
+    /// Terms can be nested arbitrarily deeply,
+    /// so a recursive function that walks a term may overflow the call stack.
+    /// This method instead keeps track of the remaining work
+    /// on a stack that lives on the heap,
+    /// and calls the visitor for each object it encounters.
+    ///
+    /// The children of an object are those found by
+    /// [`visit_children`][`crate::object::visit_children`],
+    /// and they are walked in that order.
+    /// For applications, this is the function followed by the arguments.
+    /// Objects that are reachable along multiple paths are walked once
+    /// for each path, unless the visitor says otherwise.
+    ///
+    /// The visitor may create objects,
+    /// but it cannot hold on to the handles it is given,
+    /// as they are part of the work stack.
+    pub fn walk<'s>(
+        &self,
+        root: ScopedHandle<'h, 's>,
+        visitor: &mut impl Visitor<'h>,
+    )
+    {
+        match self.walk_with_safepoint(root, visitor, || Ok(())) {
+            Ok(()) => (),
+            Err(Interrupted) => unreachable!(),
+        }
+    }
+
+    /// Similar to [`walk`][`Self::walk`],
+    /// but call [`safepoint`][`Self::safepoint`] before each object,
+    /// and stop the walk if it returns an error.
+    pub fn try_walk<'s>(
+        &self,
+        root: ScopedHandle<'h, 's>,
+        visitor: &mut impl Visitor<'h>,
+    ) -> Result<(), Interrupted>
+    {
+        self.walk_with_safepoint(root, visitor, || self.safepoint())
+    }
+
+    fn walk_with_safepoint<'s>(
+        &self,
+        root: ScopedHandle<'h, 's>,
+        visitor: &mut impl Visitor<'h>,
+        mut safepoint: impl FnMut() -> Result<(), Interrupted>,
+    ) -> Result<(), Interrupted>
+    {
+        self.with_new_growable_scope(|mut stack| {
+
+            // For each handle on the stack,
+            // whether its children have already been pushed.
+            let mut expanded = self.new_vec();
+
+            let index = stack.push();
+            stack.as_scope().get(index).unwrap().copy_from(root);
+            expanded.push(false);
+
+            let mut children = self.new_vec();
+            while let Some(top) = expanded.len().checked_sub(1) {
+                let object = stack.as_scope().get(top).unwrap();
+
+                if expanded[top] {
+                    visitor.post(object);
+                    stack.pop();
+                    expanded.pop();
+                    continue;
+                }
+
+                safepoint()?;
+                expanded[top] = true;
+                if !visitor.pre(object) {
+                    continue;
+                }
+
+                children.clear();
+                object.with_pin(|object| {
+                    object.visit_children(|child| {
+                        children.push(child.as_unsafe_handle());
+                    });
+                });
+
+                // Push the children in reverse order,
+                // so that they are popped in the right order.
+                for &child in children.iter().rev() {
+                    let index = stack.push();
+                    let handle = stack.as_scope().get(index).unwrap();
+                    // SAFETY: The child is reachable from its parent,
+                    //         which is still on the stack.
+                    unsafe { handle.copy_from_unsafe_handle(child) };
+                    expanded.push(false);
+                }
+
+            }
+
+            Ok(())
+
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::heap::HeapConfig;
+    use crate::object::DeBruijn;
+
+    use alloc::vec::Vec;
+    use core::sync::atomic::AtomicBool;
+
+    /// Records the symbols and variables it encounters.
+    #[derive(Default)]
+    struct Recorder
+    {
+        pre: Vec<Vec<u8>>,
+        post: Vec<Vec<u8>>,
+    }
+
+    fn describe(object: ScopedHandle) -> Vec<u8>
+    {
+        if let Some(DeBruijn(i)) = object.as_variable() {
+            return alloc::format!("#{}", i).into_bytes();
+        }
+        object.with_pin(|object| {
+            match object.as_symbol() {
+                Some(name) => name.to_vec(),
+                None => b"()".to_vec(),
+            }
+        })
+    }
+
+    impl<'h> Visitor<'h> for Recorder
+    {
+        fn pre(&mut self, object: ScopedHandle<'h, '_>) -> bool
+        {
+            self.pre.push(describe(object));
+            true
+        }
+
+        fn post(&mut self, object: ScopedHandle<'h, '_>)
+        {
+            self.post.push(describe(object));
+        }
+    }
+
+    #[test]
+    fn order()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[term]| {
+                crate::term!(heap, term, (F (G #0) A));
+                let mut recorder = Recorder::default();
+                heap.walk(term, &mut recorder);
+                let pre: [&[u8]; 6] = [b"()", b"F", b"()", b"G", b"#0", b"A"];
+                let post: [&[u8]; 6] = [b"F", b"G", b"#0", b"()", b"A", b"()"];
+                assert!(Iterator::eq(recorder.pre.iter(), &pre));
+                assert!(Iterator::eq(recorder.post.iter(), &post));
+            });
+        });
+    }
+
+    #[test]
+    fn interrupt()
+    {
+        let flag = AtomicBool::new(true);
+        let config = HeapConfig{
+            interrupt: Some(&flag),
+            ..HeapConfig::default()
+        };
+        Heap::with_new_config(config, |heap| {
+            heap.with_new_array_scope(|[term]| {
+                crate::term!(heap, term, (F A));
+                let mut recorder = Recorder::default();
+                let result = heap.try_walk(term, &mut recorder);
+                assert!(matches!(result, Err(Interrupted)));
+                assert!(recorder.pre.is_empty());
+
+                // Walks that do not poll are not interrupted.
+                heap.walk(term, &mut recorder);
+                assert_eq!(recorder.post.len(), 3);
+            });
+        });
+    }
+
+    #[test]
+    fn deep()
+    {
+        struct Count(usize);
+        impl<'h> Visitor<'h> for Count
+        {
+            fn post(&mut self, _object: ScopedHandle<'h, '_>)
+            {
+                self.0 += 1;
+            }
+        }
+
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[f, term]| {
+                heap.new_symbol(f, b"F").unwrap();
+                heap.new_symbol(term, b"X").unwrap();
+                for _ in 0 .. 100_000 {
+                    heap.new_application(term, f, [term]).unwrap();
+                }
+                let mut count = Count(0);
+                heap.walk(term, &mut count);
+                assert_eq!(count.0, 200_001);
+            });
+        });
+    }
+}
@@ -0,0 +1,247 @@
+use crate::heap::GrowableScope;
+use crate::heap::Heap;
+use crate::heap::HeapVec;
+use crate::heap::ScopedHandle;
+
+/// Cursor for navigating and editing a term.
+///
+/// The zipper focuses on a subterm of the term it was created for.
+/// It can move [down][`Self::down`] into the fields
+/// of an application or cons cell and [up][`Self::up`] again.
+/// When the focus is [replaced][`Self::replace`] and the zipper moves up,
+/// the parent is recreated with the new field.
+/// Only the objects between the root and the edited subterms
+/// are recreated; the rest of the term is shared with the original.
+/// The original term is not modified, as objects are immutable.
+pub struct Zipper<'g, 'h>
+{
+    /// The parents of the focus, followed by the focus itself.
+    stack: GrowableScope<'g, 'h>,
+
+    /// For each parent, the field that contains the next object on the stack.
+    path: HeapVec<'h, usize>,
+}
+
+impl<'h> Heap<'h>
+{
+    /// Create a zipper focused on the given term
+    /// and pass it to the given function.
+    pub fn with_new_zipper<'s, F, R>(&self, root: ScopedHandle<'h, 's>, then: F)
+        -> R
+        where F: for<'g> FnOnce(&mut Zipper<'g, 'h>) -> R
+    {
+        self.with_new_growable_scope(|mut stack| {
+            let index = stack.push();
+            stack.as_scope().get(index).unwrap().copy_from(root);
+            then(&mut Zipper{stack, path: self.new_vec()})
+        })
+    }
+}
+
+impl<'g, 'h> Zipper<'g, 'h>
+{
+    /// The subterm that the zipper is focused on.
+    #[inline]
+    pub fn focus(&self) -> ScopedHandle<'h, '_>
+    {
+        let scope = self.stack.as_scope();
+        scope.get(scope.len() - 1).unwrap()
+    }
+
+    /// The fields the zipper moved down into to arrive at the focus.
+    #[inline]
+    pub fn path(&self) -> &[usize]
+    {
+        &self.path
+    }
+
+    /// Move the focus to the given field of the focused application
+    /// or cons cell.
+    ///
+    /// Field 0 of an application is the function,
+    /// and the arguments are the fields after that.
+    /// Field 0 of a cons cell is the head, and field 1 is the tail.
+    /// If the focus is neither or has no such field,
+    /// the focus does not change and this method returns false.
+    pub fn down(&mut self, field: usize) -> bool
+    {
+        let child = self.focus().with_pin(|focus| {
+            let child = match (focus.as_application(), focus.as_cons()) {
+                (Some((function, _)), _) if field == 0 => function,
+                (Some((_, arguments)), _) => arguments.get(field - 1)?,
+                (_, Some((head, _))) if field == 0 => head,
+                (_, Some((_, tail))) if field == 1 => tail,
+                _ => return None,
+            };
+            Some(child.as_unsafe_handle())
+        });
+
+        match child {
+            Some(child) => {
+                let index = self.stack.push();
+                let focus = self.stack.as_scope().get(index).unwrap();
+                // SAFETY: The child is reachable from the parent,
+                //         which is still on the stack.
+                unsafe { focus.copy_from_unsafe_handle(child) };
+                self.path.push(field);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Move the focus to the parent of the focus.
+    ///
+    /// If the focus was replaced, the parent is recreated with it.
+    /// If the focus is the root, this method returns false.
+    pub fn up(&mut self) -> bool
+    {
+        let field = match self.path.pop() {
+            Some(field) => field,
+            None => return false,
+        };
+
+        let heap = self.stack.heap();
+        let scope = self.stack.as_scope();
+        let parent = scope.get(scope.len() - 2).unwrap();
+        let child = scope.get(scope.len() - 1).unwrap();
+
+        parent.with_pin(|pinned| {
+            if let Some((head, tail)) = pinned.as_cons() {
+                let original = if field == 0 { head } else { tail };
+                if original.ptr_eq(child) {
+                    return;
+                }
+                let (head, tail) =
+                    if field == 0 { (child, tail) } else { (head, child) };
+                heap.new_cons(parent, head, tail);
+                return;
+            }
+
+            let (function, arguments) = pinned.as_application().unwrap();
+
+            let original = match field {
+                0 => function,
+                _ => arguments.get(field - 1).unwrap(),
+            };
+            if original.ptr_eq(child) {
+                return;
+            }
+
+            let function = if field == 0 { child } else { function };
+            let arguments = arguments.iter().enumerate()
+                .map(|(i, argument)| {
+                    if i + 1 == field { child } else { argument }
+                });
+            heap.new_application(parent, function, arguments)
+                .expect("Application has as many arguments as before");
+        });
+
+        self.stack.pop();
+        true
+    }
+
+    /// Make the focus refer to a different term.
+    #[inline]
+    pub fn replace(&mut self, replacement: ScopedHandle<'h, '_>)
+    {
+        self.focus().copy_from(replacement);
+    }
+
+    /// Move the focus all the way up,
+    /// and modify the given handle to refer to the resulting term.
+    pub fn finish(&mut self, into: ScopedHandle<'h, '_>)
+    {
+        while self.up() { }
+        into.copy_from(self.focus());
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::term;
+    use crate::testing::TermTree;
+
+    #[test]
+    fn edit()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[original, c, result, expected]| {
+                term!(heap, original, (F (G A) (H B)));
+                heap.new_symbol(c, b"C").unwrap();
+
+                heap.with_new_zipper(original, |zipper| {
+                    assert!(zipper.down(1));
+                    assert!(zipper.down(1));
+                    assert!(!zipper.down(0));
+                    assert_eq!(zipper.path(), [1, 1]);
+                    zipper.replace(c);
+                    zipper.finish(result);
+                });
+
+                term!(heap, expected, (F (G C) (H B)));
+                assert_eq!(
+                    TermTree::read(heap, result),
+                    TermTree::read(heap, expected),
+                );
+
+                // Unchanged subterms are shared with the original.
+                let same_argument = |i| result.with_pin(|result| {
+                    original.with_pin(|original| {
+                        let a = result.as_application().unwrap().1.get(i);
+                        let b = original.as_application().unwrap().1.get(i);
+                        a.unwrap().ptr_eq(b.unwrap())
+                    })
+                });
+                assert!(same_argument(1));
+                assert!(!same_argument(0));
+            });
+        });
+    }
+
+    #[test]
+    fn cons()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[a, b, list, result, expected]| {
+                heap.new_symbol(a, b"A").unwrap();
+                heap.new_symbol(b, b"B").unwrap();
+                heap.new_list(list, [a, a]);
+
+                heap.with_new_zipper(list, |zipper| {
+                    assert!(zipper.down(1));
+                    assert!(zipper.down(0));
+                    assert!(!zipper.down(2));
+                    assert_eq!(zipper.path(), [1, 0]);
+                    zipper.replace(b);
+                    zipper.finish(result);
+                });
+
+                heap.new_list(expected, [a, b]);
+                assert_eq!(
+                    TermTree::read(heap, result),
+                    TermTree::read(heap, expected),
+                );
+            });
+        });
+    }
+
+    #[test]
+    fn unchanged()
+    {
+        Heap::with_new(|heap| {
+            heap.with_new_array_scope(|[original, result]| {
+                term!(heap, original, (F (G A) B));
+                heap.with_new_zipper(original, |zipper| {
+                    assert!(zipper.down(1));
+                    assert!(zipper.down(0));
+                    assert!(!zipper.down(5));
+                    zipper.finish(result);
+                });
+                assert!(result.ptr_eq(original));
+            });
+        });
+    }
+}
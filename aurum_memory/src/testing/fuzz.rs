@@ -0,0 +1,180 @@
+use crate::heap::Heap;
+use crate::heap::HeapConfig;
+use crate::heap::Scope;
+use crate::heap::ScopedHandle;
+use crate::object::DeBruijn;
+
+use core::slice;
+
+/// Number of handles that the operations of [`interpret`] work on.
+const REGISTERS: usize = 8;
+
+/// How deeply [`interpret`] nests pins.
+const MAX_PIN_DEPTH: usize = 16;
+
+/// Memory limit of the heap created by [`interpret`].
+const MEMORY_LIMIT: usize = 1 << 16;
+
+/// Interpret a byte string as a sequence of heap operations.
+///
+/// This is intended as the body of a fuzz target.
+/// Every byte string is a valid program, so fuzzers can pass any input.
+/// The operations create objects of each kind,
+/// copy and swap handles, and pin objects while performing other operations.
+/// After each operation, the heap is [verified][`Heap::verify`].
+///
+/// Running out of memory is not a bug,
+/// so the heap has a small memory limit,
+/// and operations that would exceed it are skipped.
+///
+/// # Panics
+///
+/// If an operation leaves the heap in an inconsistent state,
+/// this function panics.
+pub fn interpret(program: &[u8])
+{
+    let config = HeapConfig{
+        memory_limit: MEMORY_LIMIT,
+        interned_variable_count: 4,
+        ..HeapConfig::default()
+    };
+    Heap::with_new_config(config, |heap| {
+        heap.with_new_boxed_scope(REGISTERS, |registers| {
+            let mut interpreter = Interpreter{
+                heap,
+                registers,
+                program: program.iter(),
+            };
+            while !interpreter.program.as_slice().is_empty() {
+                interpreter.step(0);
+            }
+        });
+    });
+}
+
+struct Interpreter<'a, 'h, 's>
+{
+    heap: &'a Heap<'h>,
+    registers: &'s Scope<'h>,
+    program: slice::Iter<'a, u8>,
+}
+
+impl<'a, 'h, 's> Interpreter<'a, 'h, 's>
+{
+    /// Read the next byte of the program.
+    ///
+    /// Past the end of the program, this returns zero,
+    /// so that truncated operations are still valid.
+    fn byte(&mut self) -> u8
+    {
+        self.program.next().copied().unwrap_or(0)
+    }
+
+    /// Read a register number from the program.
+    fn register(&mut self) -> ScopedHandle<'h, 's>
+    {
+        let index = self.byte() as usize % REGISTERS;
+        self.registers.get(index).unwrap()
+    }
+
+    /// Perform a single operation.
+    fn step(&mut self, pin_depth: usize)
+    {
+        let heap = self.heap;
+        match self.byte() % 8 {
+            0 => {
+                let into = self.register();
+                let len = self.byte() as usize % 16;
+                let rest = self.program.as_slice();
+                let (name, rest) = rest.split_at(len.min(rest.len()));
+                self.program = rest.iter();
+                let _ = heap.try_new_symbol(into, name);
+            },
+            1 => {
+                let into = self.register();
+                let de_bruijn = DeBruijn(self.byte().into());
+                let _ = heap.try_new_variable(into, de_bruijn);
+            },
+            2 => {
+                let into = self.register();
+                let de_bruijn = DeBruijn(self.byte().into());
+                let _ = heap.try_new_variable_not_interned(into, de_bruijn);
+            },
+            3 => {
+                let into = self.register();
+                let function = self.register();
+                let arguments: [_; REGISTERS] =
+                    [(); REGISTERS].map(|()| self.register());
+                let len = self.byte() as usize % REGISTERS;
+                let _ = heap.try_new_application(
+                    into,
+                    function,
+                    arguments[.. len].iter().copied(),
+                );
+            },
+            4 => {
+                let into = self.register();
+                let function = self.register();
+                let len = self.byte() as usize % REGISTERS;
+                let _ = heap.try_new_application_curried(
+                    into,
+                    function,
+                    self.registers.iter().take(len),
+                );
+            },
+            5 => {
+                let to = self.register();
+                let from = self.register();
+                to.copy_from(from);
+            },
+            6 => {
+                let a = self.register();
+                let b = self.register();
+                a.swap(b);
+            },
+            7 => {
+                // Inspect the object and perform the next operation
+                // while the object is pinned.
+                let register = self.register();
+                register.with_pin(|pinned| {
+                    let _ = pinned.object_size();
+                    let _ = pinned.as_symbol();
+                    let _ = pinned.as_application();
+                    if pin_depth < MAX_PIN_DEPTH {
+                        self.step(pin_depth + 1);
+                    }
+                });
+            },
+            _ => unreachable!(),
+        }
+        heap.verify();
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    use alloc::vec::Vec;
+    use proptest::proptest;
+
+    #[test]
+    fn interpret_examples()
+    {
+        interpret(&[]);
+        interpret(&[0, 1, 3, b'F', b'o', b'o']);
+        interpret(&[0, 0, 2, b'F', b'o', 2, 1, 200, 3, 2, 0, 1, 1, 1, 1]);
+        interpret(&[7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7]);
+        interpret(&[0, 1, 15]);
+    }
+
+    proptest!
+    {
+        #[test]
+        fn interpret_arbitrary(program: Vec<u8>)
+        {
+            interpret(&program);
+        }
+    }
+}
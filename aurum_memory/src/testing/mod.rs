@@ -0,0 +1,12 @@
+//! Utilities for testing code that works with objects.
+//!
+//! This module is only available with the `testing` feature.
+//! It lets crates that build on this one generate arbitrary terms
+//! for property tests, and exercise the heap from fuzz targets,
+//! without reinventing these.
+
+pub use self::fuzz::*;
+pub use self::term::*;
+
+mod fuzz;
+mod term;
@@ -0,0 +1,273 @@
+use crate::heap::Heap;
+use crate::heap::ScopedHandle;
+use crate::object::DeBruijn;
+use crate::object::Metavariable;
+use crate::object::NodeId;
+use crate::object::TermBuilder;
+use crate::object::Visitor;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use proptest::arbitrary::Arbitrary;
+use proptest::arbitrary::any;
+use proptest::collection::vec;
+use proptest::prop_oneof;
+use proptest::strategy::BoxedStrategy;
+use proptest::strategy::Strategy;
+
+/// Description of a term that does not live in any heap.
+///
+/// Since objects cannot outlive their heap,
+/// property tests generate these instead,
+/// and then [build][`Self::build`] them in a heap.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TermTree
+{
+    /// Symbol with the given name.
+    Symbol(Vec<u8>),
+
+    /// Qualified symbol with the given namespace and name.
+    QualifiedSymbol(Vec<u8>, Vec<u8>),
+
+    /// Variable with the given De Bruijn index.
+    Variable(DeBruijn),
+
+    /// Application of a function to arguments.
+    Application(Box<TermTree>, Vec<TermTree>),
+
+    /// Metavariable.
+    Metavariable(Metavariable),
+
+    /// Character.
+    Char(char),
+
+    /// Cons cell with the given head and tail.
+    Cons(Box<TermTree>, Box<TermTree>),
+}
+
+impl TermTree
+{
+    /// Create the term in the given heap.
+    ///
+    /// # Panics
+    ///
+    /// If a symbol name is too long or an application has too many arguments,
+    /// this method panics.
+    pub fn build<'h>(&self, heap: &Heap<'h>, into: ScopedHandle<'h, '_>)
+    {
+        heap.with_new_term_builder(|builder| {
+            let node = self.add_to(builder);
+            builder.build(node, into);
+        });
+    }
+
+    /// Create the term using the given term builder.
+    ///
+    /// See [`build`][`Self::build`] for more information.
+    pub fn add_to(&self, builder: &mut TermBuilder) -> NodeId
+    {
+        match self {
+            Self::Symbol(name) => builder.symbol(name).unwrap(),
+            Self::QualifiedSymbol(namespace, name) =>
+                builder.qualified_symbol(namespace, name).unwrap(),
+            Self::Variable(de_bruijn) => builder.var(*de_bruijn),
+            Self::Metavariable(metavariable) => builder.metavar(*metavariable),
+            Self::Char(char) => builder.char(*char),
+            Self::Application(function, arguments) => {
+                let function = function.add_to(builder);
+                let arguments: Vec<NodeId> =
+                    arguments.iter().map(|a| a.add_to(builder)).collect();
+                builder.app(function, &arguments).unwrap()
+            },
+            Self::Cons(head, tail) => {
+                let head = head.add_to(builder);
+                let tail = tail.add_to(builder);
+                builder.cons(head, tail)
+            },
+        }
+    }
+
+    /// Describe the term that the given handle refers to.
+    pub fn read<'h>(heap: &Heap<'h>, handle: ScopedHandle<'h, '_>) -> Self
+    {
+        let mut reader = Reader{terms: Vec::new()};
+        heap.walk(handle, &mut reader);
+        reader.terms.pop().unwrap()
+    }
+}
+
+/// Visitor that describes terms, for [`TermTree::read`].
+struct Reader
+{
+    /// Descriptions of the children of the objects being walked.
+    terms: Vec<TermTree>,
+}
+
+impl<'h> Visitor<'h> for Reader
+{
+    fn post(&mut self, object: ScopedHandle<'h, '_>)
+    {
+        if let Some(de_bruijn) = object.as_variable() {
+            self.terms.push(TermTree::Variable(de_bruijn));
+            return;
+        }
+        if let Some(metavariable) = object.as_metavariable() {
+            self.terms.push(TermTree::Metavariable(metavariable));
+            return;
+        }
+        if let Some(char) = object.as_char() {
+            self.terms.push(TermTree::Char(char));
+            return;
+        }
+        // Annotations are transparent,
+        // so the description of the annotated term is used as is.
+        if object.with_pin(|object| object.as_annotated().is_some()) {
+            return;
+        }
+        let term = object.with_pin(|object| {
+            if let Some(name) = object.as_symbol() {
+                return TermTree::Symbol(name.to_vec());
+            }
+            if let Some((namespace, name)) = object.as_qualified_symbol() {
+                return TermTree::QualifiedSymbol(
+                    namespace.to_vec(),
+                    name.to_vec(),
+                );
+            }
+            if object.as_cons().is_some() {
+                let tail = self.terms.pop().unwrap();
+                let head = self.terms.pop().unwrap();
+                return TermTree::Cons(Box::new(head), Box::new(tail));
+            }
+            match object.as_application() {
+                Some((_, arguments)) => {
+                    let start = self.terms.len() - arguments.len();
+                    let arguments = self.terms.split_off(start);
+                    let function = self.terms.pop().unwrap();
+                    TermTree::Application(Box::new(function), arguments)
+                },
+                None => unreachable!("Unknown kind of object"),
+            }
+        });
+        self.terms.push(term);
+    }
+}
+
+/// Options for generating arbitrary terms.
+///
+/// The weights determine how often each kind of object is generated,
+/// relative to the other kinds.
+/// At least one of the symbol and variable weights must be non-zero.
+#[derive(Clone, Debug)]
+pub struct TermConfig
+{
+    /// Maximum depth of nested applications.
+    pub depth: u32,
+
+    /// Number of objects to aim for, across all levels.
+    pub desired_size: u32,
+
+    /// Maximum number of arguments of each application.
+    pub max_arguments: usize,
+
+    /// Maximum number of bytes in symbol names.
+    pub max_symbol_len: usize,
+
+    /// De Bruijn indices of variables are below this number.
+    pub de_bruijn_limit: u32,
+
+    /// How often to generate symbols.
+    pub symbol_weight: u32,
+
+    /// How often to generate variables.
+    pub variable_weight: u32,
+
+    /// How often to generate applications.
+    pub application_weight: u32,
+}
+
+impl Default for TermConfig
+{
+    fn default() -> Self
+    {
+        Self{
+            depth: 4,
+            desired_size: 64,
+            max_arguments: 4,
+            max_symbol_len: 8,
+            de_bruijn_limit: 32,
+            symbol_weight: 1,
+            variable_weight: 1,
+            application_weight: 2,
+        }
+    }
+}
+
+/// Strategy for generating arbitrary terms.
+pub fn arb_term(config: TermConfig) -> BoxedStrategy<TermTree>
+{
+    let symbol = vec(any::<u8>(), 0 ..= config.max_symbol_len)
+        .prop_map(TermTree::Symbol);
+
+    let variable = (0 .. config.de_bruijn_limit)
+        .prop_map(|i| TermTree::Variable(DeBruijn(i)));
+
+    let leaf = prop_oneof![
+        config.symbol_weight => symbol,
+        config.variable_weight => variable,
+    ];
+
+    let leaf_weight = config.symbol_weight + config.variable_weight;
+    // The function and on average half the maximum number of arguments.
+    let expected_branch_size = 1 + config.max_arguments as u32 / 2;
+
+    leaf.clone().prop_recursive(
+        config.depth,
+        config.desired_size,
+        expected_branch_size,
+        move |inner| {
+            let application =
+                (inner.clone(), vec(inner, 0 ..= config.max_arguments))
+                .prop_map(|(function, arguments)| {
+                    TermTree::Application(Box::new(function), arguments)
+                });
+            prop_oneof![
+                leaf_weight => leaf.clone(),
+                config.application_weight => application,
+            ]
+        },
+    ).boxed()
+}
+
+impl Arbitrary for TermTree
+{
+    type Parameters = TermConfig;
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(config: Self::Parameters) -> Self::Strategy
+    {
+        arb_term(config)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    use proptest::proptest;
+
+    proptest!
+    {
+        #[test]
+        fn roundtrip(tree in any::<TermTree>())
+        {
+            Heap::with_new(|heap| {
+                heap.with_new_array_scope(|[term]| {
+                    tree.build(heap, term);
+                    assert_eq!(TermTree::read(heap, term), tree);
+                });
+            });
+        }
+    }
+}
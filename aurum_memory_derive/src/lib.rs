@@ -0,0 +1,150 @@
+//! This crate implements the derive macros of `aurum_memory`.
+//!
+//! Use them through the `derive` feature of `aurum_memory`,
+//! which re-exports them next to the traits they implement.
+
+#![warn(missing_docs)]
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::format_ident;
+use quote::quote;
+use syn::Data;
+use syn::DeriveInput;
+use syn::Error;
+use syn::Fields;
+use syn::Lifetime;
+use syn::LifetimeParam;
+use syn::Result;
+use syn::Type;
+use syn::parse_macro_input;
+use syn::parse_quote;
+
+/// Derive `aurum_memory::heap::Trace`.
+///
+/// Every field is traced in turn,
+/// except for fields marked `#[trace(skip)]`.
+#[proc_macro_derive(Trace, attributes(trace))]
+pub fn derive_trace(input: TokenStream) -> TokenStream
+{
+    let input = parse_macro_input!(input as DeriveInput);
+    match trace(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn trace(input: &DeriveInput) -> Result<TokenStream2>
+{
+    let name = &input.ident;
+    let heap = Lifetime::new("'__aurum_h", Span::call_site());
+
+    let mut types = Vec::new();
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let (pattern, calls) = trace_fields(&data.fields, &mut types)?;
+            quote! { let Self #pattern = self; #(#calls)* }
+        },
+        Data::Enum(data) if data.variants.is_empty() => {
+            quote! { match *self {} }
+        },
+        Data::Enum(data) => {
+            let mut arms = Vec::new();
+            for variant in &data.variants {
+                let variant_name = &variant.ident;
+                let (pattern, calls) =
+                    trace_fields(&variant.fields, &mut types)?;
+                arms.push(quote! {
+                    Self::#variant_name #pattern => { #(#calls)* },
+                });
+            }
+            quote! { match self { #(#arms)* } }
+        },
+        Data::Union(_) => {
+            let message = "Trace cannot be derived for unions";
+            return Err(Error::new_spanned(input, message));
+        },
+    };
+
+    // Every traced field must itself implement Trace.
+    let mut generics = input.generics.clone();
+    generics.params.insert(0, LifetimeParam::new(heap.clone()).into());
+    let where_clause = generics.make_where_clause();
+    for ty in types {
+        where_clause.predicates.push(parse_quote! {
+            #ty: ::aurum_memory::heap::Trace<#heap>
+        });
+    }
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::aurum_memory::heap::Trace<#heap>
+            for #name #ty_generics
+            #where_clause
+        {
+            fn trace(
+                &self,
+                tracer: &mut dyn ::aurum_memory::heap::Tracer<#heap>,
+            )
+            {
+                #body
+            }
+        }
+    })
+}
+
+/// A pattern that binds the traced fields,
+/// and the statements that trace them.
+/// The types of the traced fields are added to `types`.
+fn trace_fields(fields: &Fields, types: &mut Vec<Type>)
+    -> Result<(TokenStream2, Vec<TokenStream2>)>
+{
+    let mut bindings = Vec::new();
+    let mut calls = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        let binding = format_ident!("__field{}", i);
+        if is_skipped(field)? {
+            // Named fields are left out of the pattern instead.
+            if field.ident.is_none() {
+                bindings.push(quote! { _ });
+            }
+            continue;
+        }
+        match &field.ident {
+            Some(name) => bindings.push(quote! { #name: #binding }),
+            None => bindings.push(quote! { #binding }),
+        }
+        calls.push(quote! {
+            ::aurum_memory::heap::Trace::trace(#binding, tracer);
+        });
+        types.push(field.ty.clone());
+    }
+
+    let pattern = match fields {
+        Fields::Named(..) => quote! { { #(#bindings,)* .. } },
+        Fields::Unnamed(..) => quote! { ( #(#bindings),* ) },
+        Fields::Unit => quote! {},
+    };
+    Ok((pattern, calls))
+}
+
+/// Whether the field is marked `#[trace(skip)]`.
+fn is_skipped(field: &syn::Field) -> Result<bool>
+{
+    let mut skip = false;
+    for attr in &field.attrs {
+        if attr.path().is_ident("trace") {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("skip") {
+                    skip = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("unknown trace attribute"))
+                }
+            })?;
+        }
+    }
+    Ok(skip)
+}
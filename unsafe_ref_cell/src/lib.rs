@@ -4,21 +4,29 @@
 #![no_std]
 #![warn(missing_docs)]
 
+#[allow(unused)] use core::cell::Ref;
 #[allow(unused)] use core::cell::RefCell;
 #[allow(unused)] use core::cell::RefMut;
 #[allow(unused)] use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem;
 use core::ops::Deref;
 use core::ops::DerefMut;
 
 /// Behaves just like [`UnsafeCell`],
 /// but with [`RefCell`]-like runtime checks
 /// when debug assertions are enabled.
+///
+/// The `force-checks` feature enables the checks in release builds too,
+/// for diagnosing aliasing bugs that only show up in production builds.
+/// Wherever this documentation mentions debug mode,
+/// that includes release builds with this feature.
 pub struct UnsafeRefCell<T>
 {
-    #[cfg(debug_assertions)]
+    #[cfg(any(debug_assertions, feature = "force-checks"))]
     inner: RefCell<T>,
 
-    #[cfg(not(debug_assertions))]
+    #[cfg(not(any(debug_assertions, feature = "force-checks")))]
     inner: UnsafeCell<T>,
 }
 
@@ -30,13 +38,13 @@ impl<T> UnsafeRefCell<T>
         Self::new_internal(value)
     }
 
-    #[cfg(debug_assertions)]
+    #[cfg(any(debug_assertions, feature = "force-checks"))]
     const fn new_internal(value: T) -> Self
     {
         Self{inner: RefCell::new(value)}
     }
 
-    #[cfg(not(debug_assertions))]
+    #[cfg(not(any(debug_assertions, feature = "force-checks")))]
     const fn new_internal(value: T) -> Self
     {
         Self{inner: UnsafeCell::new(value)}
@@ -48,6 +56,42 @@ impl<T> UnsafeRefCell<T>
         self.inner.into_inner()
     }
 
+    /// Mutably borrow the contained value.
+    ///
+    /// This is safe, as the unique borrow of the cell
+    /// guarantees that there are no other borrows of the value.
+    pub fn get_mut(&mut self) -> &mut T
+    {
+        self.inner.get_mut()
+    }
+
+    /// Immutably borrow the contained value.
+    ///
+    /// The borrow lasts until the return value is dropped.
+    /// Multiple immutable borrows may exist at the same time.
+    ///
+    /// # Safety
+    ///
+    /// There must not exist mutable borrows of the value.
+    /// In debug mode, such a condition causes a panic.
+    /// In release mode, the behavior under such a condition is undefined.
+    pub unsafe fn borrow(&self) -> UnsafeRef<T>
+    {
+        self.borrow_internal()
+    }
+
+    #[cfg(any(debug_assertions, feature = "force-checks"))]
+    unsafe fn borrow_internal(&self) -> UnsafeRef<T>
+    {
+        UnsafeRef{inner: self.inner.borrow()}
+    }
+
+    #[cfg(not(any(debug_assertions, feature = "force-checks")))]
+    unsafe fn borrow_internal(&self) -> UnsafeRef<T>
+    {
+        UnsafeRef{inner: &*self.inner.get()}
+    }
+
     /// Mutably borrow the contained value.
     ///
     /// The borrow lasts until the return value is dropped.
@@ -62,26 +106,114 @@ impl<T> UnsafeRefCell<T>
         self.borrow_mut_internal()
     }
 
-    #[cfg(debug_assertions)]
+    #[cfg(any(debug_assertions, feature = "force-checks"))]
     unsafe fn borrow_mut_internal(&self) -> UnsafeRefMut<T>
     {
         UnsafeRefMut{inner: self.inner.borrow_mut()}
     }
 
-    #[cfg(not(debug_assertions))]
+    #[cfg(not(any(debug_assertions, feature = "force-checks")))]
     unsafe fn borrow_mut_internal(&self) -> UnsafeRefMut<T>
     {
         UnsafeRefMut{inner: &mut *self.inner.get()}
     }
+
+    /// Mutably borrow the contained value,
+    /// returning an error if it is already borrowed.
+    ///
+    /// In release mode, borrows are not tracked,
+    /// so this method always succeeds.
+    ///
+    /// # Safety
+    ///
+    /// The same as for [`borrow_mut`][`Self::borrow_mut`],
+    /// except that in debug mode an error is returned instead of panicking.
+    pub unsafe fn try_borrow_mut(&self)
+        -> Result<UnsafeRefMut<T>, BorrowMutError>
+    {
+        self.try_borrow_mut_internal()
+    }
+
+    #[cfg(any(debug_assertions, feature = "force-checks"))]
+    unsafe fn try_borrow_mut_internal(&self)
+        -> Result<UnsafeRefMut<T>, BorrowMutError>
+    {
+        match self.inner.try_borrow_mut() {
+            Ok(inner) => Ok(UnsafeRefMut{inner}),
+            Err(_) => Err(BorrowMutError),
+        }
+    }
+
+    #[cfg(not(any(debug_assertions, feature = "force-checks")))]
+    unsafe fn try_borrow_mut_internal(&self)
+        -> Result<UnsafeRefMut<T>, BorrowMutError>
+    {
+        Ok(self.borrow_mut_internal())
+    }
+
+    /// Replace the contained value, returning the old value.
+    ///
+    /// # Safety
+    ///
+    /// The same as for [`borrow_mut`][`Self::borrow_mut`].
+    pub unsafe fn replace(&self, value: T) -> T
+    {
+        mem::replace(&mut *self.borrow_mut(), value)
+    }
+
+    /// Replace the contained value with its default,
+    /// returning the old value.
+    ///
+    /// # Safety
+    ///
+    /// The same as for [`borrow_mut`][`Self::borrow_mut`].
+    pub unsafe fn take(&self) -> T
+        where T: Default
+    {
+        self.replace(T::default())
+    }
+}
+
+/// Returned by [`UnsafeRefCell::try_borrow_mut`]
+/// when the value is already borrowed.
+#[derive(Debug)]
+pub struct BorrowMutError;
+
+impl fmt::Display for BorrowMutError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        write!(f, "Already borrowed")
+    }
+}
+
+/// Immutably borrowed content of [`UnsafeRefCell`].
+pub struct UnsafeRef<'a, T>
+{
+    #[cfg(any(debug_assertions, feature = "force-checks"))]
+    inner: Ref<'a, T>,
+
+    #[cfg(not(any(debug_assertions, feature = "force-checks")))]
+    inner: &'a T,
+}
+
+impl<'a, T> Deref for UnsafeRef<'a, T>
+{
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target
+    {
+        self.inner.deref()
+    }
 }
 
 /// Mutably borrowed content of [`UnsafeRefCell`].
 pub struct UnsafeRefMut<'a, T>
 {
-    #[cfg(debug_assertions)]
+    #[cfg(any(debug_assertions, feature = "force-checks"))]
     inner: RefMut<'a, T>,
 
-    #[cfg(not(debug_assertions))]
+    #[cfg(not(any(debug_assertions, feature = "force-checks")))]
     inner: &'a mut T,
 }
 
@@ -95,6 +227,75 @@ impl<'a, T> Deref for UnsafeRefMut<'a, T>
     }
 }
 
+impl<'a, T> UnsafeRefMut<'a, T>
+{
+    /// Narrow the borrow to a part of the borrowed value,
+    /// such as a field, like [`RefMut::map`].
+    ///
+    /// This is an associated function, so as not to shadow
+    /// methods of the borrowed value.
+    pub fn map<U, F>(orig: Self, f: F) -> UnsafeRefMut<'a, U>
+        where F: FnOnce(&mut T) -> &mut U
+    {
+        UnsafeRefMut{inner: Self::map_internal(orig.inner, f)}
+    }
+
+    #[cfg(any(debug_assertions, feature = "force-checks"))]
+    fn map_internal<U, F>(inner: RefMut<'a, T>, f: F) -> RefMut<'a, U>
+        where F: FnOnce(&mut T) -> &mut U
+    {
+        RefMut::map(inner, f)
+    }
+
+    #[cfg(not(any(debug_assertions, feature = "force-checks")))]
+    fn map_internal<U, F>(inner: &'a mut T, f: F) -> &'a mut U
+        where F: FnOnce(&mut T) -> &mut U
+    {
+        f(inner)
+    }
+
+    /// Narrow the borrow to a part of the borrowed value that may be absent,
+    /// like [`RefMut::filter_map`].
+    ///
+    /// If `f` returns [`None`], the original borrow is returned as an error.
+    pub fn filter_map<U, F>(orig: Self, f: F)
+        -> Result<UnsafeRefMut<'a, U>, Self>
+        where F: FnOnce(&mut T) -> Option<&mut U>
+    {
+        Self::filter_map_internal(orig, f)
+    }
+
+    #[cfg(any(debug_assertions, feature = "force-checks"))]
+    fn filter_map_internal<U, F>(mut orig: Self, f: F)
+        -> Result<UnsafeRefMut<'a, U>, Self>
+        where F: FnOnce(&mut T) -> Option<&mut U>
+    {
+        // RefMut::filter_map is not available on all supported toolchains,
+        // so find the part first and then narrow the borrow to it.
+        match f(&mut orig.inner).map(|part| part as *mut U) {
+            // SAFETY: The part lives as long as the original borrow.
+            Some(part) => Ok(UnsafeRefMut{
+                inner: RefMut::map(orig.inner, |_| unsafe { &mut *part }),
+            }),
+            None => Err(orig),
+        }
+    }
+
+    #[cfg(not(any(debug_assertions, feature = "force-checks")))]
+    fn filter_map_internal<U, F>(orig: Self, f: F)
+        -> Result<UnsafeRefMut<'a, U>, Self>
+        where F: FnOnce(&mut T) -> Option<&mut U>
+    {
+        let inner = orig.inner as *mut T;
+        // SAFETY: The original borrow is returned only if f returns None,
+        // in which case f no longer borrows it.
+        match f(unsafe { &mut *inner }) {
+            Some(inner) => Ok(UnsafeRefMut{inner}),
+            None => Err(UnsafeRefMut{inner: unsafe { &mut *inner }}),
+        }
+    }
+}
+
 impl<'a, T> DerefMut for UnsafeRefMut<'a, T>
 {
     fn deref_mut(&mut self) -> &mut Self::Target
@@ -102,3 +303,135 @@ impl<'a, T> DerefMut for UnsafeRefMut<'a, T>
         self.inner.deref_mut()
     }
 }
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn get_mut()
+    {
+        let mut cell = UnsafeRefCell::new(1);
+        *cell.get_mut() = 2;
+        assert_eq!(cell.into_inner(), 2);
+    }
+
+    #[test]
+    fn replace_take()
+    {
+        let cell = UnsafeRefCell::new(1);
+        // SAFETY: There are no other borrows.
+        unsafe {
+            assert_eq!(cell.replace(2), 1);
+            assert_eq!(cell.take(), 2);
+        }
+        assert_eq!(cell.into_inner(), 0);
+    }
+
+    #[test]
+    #[cfg(any(debug_assertions, feature = "force-checks"))]
+    #[should_panic(expected = "already borrowed")]
+    fn replace_borrowed()
+    {
+        let cell = UnsafeRefCell::new(1);
+        // SAFETY: The conflict causes a panic, as borrows are tracked.
+        unsafe {
+            let _borrow = cell.borrow();
+            cell.replace(2);
+        }
+    }
+
+    #[test]
+    #[cfg(any(debug_assertions, feature = "force-checks"))]
+    #[should_panic(expected = "already borrowed")]
+    fn take_borrowed()
+    {
+        let cell = UnsafeRefCell::new(1);
+        // SAFETY: The conflict causes a panic, as borrows are tracked.
+        unsafe {
+            let _borrow = cell.borrow();
+            cell.take();
+        }
+    }
+
+    #[test]
+    fn map()
+    {
+        let cell = UnsafeRefCell::new((1, 2));
+        {
+            // SAFETY: There are no other borrows.
+            let borrow = unsafe { cell.borrow_mut() };
+            let mut second = UnsafeRefMut::map(borrow, |pair| &mut pair.1);
+            assert_eq!(*second, 2);
+            *second = 3;
+        }
+        assert_eq!(cell.into_inner(), (1, 3));
+    }
+
+    #[test]
+    fn filter_map()
+    {
+        let cell = UnsafeRefCell::new(Some(1));
+        // SAFETY: There are no other borrows.
+        let borrow = unsafe { cell.borrow_mut() };
+        match UnsafeRefMut::filter_map(borrow, Option::as_mut) {
+            Ok(mut value) => *value = 2,
+            Err(_) => panic!("Value is absent"),
+        }
+        assert_eq!(cell.into_inner(), Some(2));
+
+        let cell = UnsafeRefCell::new(None::<i32>);
+        // SAFETY: There are no other borrows.
+        let borrow = unsafe { cell.borrow_mut() };
+        match UnsafeRefMut::filter_map(borrow, Option::as_mut) {
+            Ok(_) => panic!("Value is present"),
+            Err(mut orig) => *orig = Some(3),
+        }
+        assert_eq!(cell.into_inner(), Some(3));
+    }
+
+    #[test]
+    #[cfg(any(debug_assertions, feature = "force-checks"))]
+    fn try_borrow_mut_checked()
+    {
+        let cell = UnsafeRefCell::new(1);
+        // SAFETY: Conflicts are reported, as borrows are tracked.
+        unsafe {
+            let borrow = cell.borrow();
+            assert!(cell.try_borrow_mut().is_err());
+            drop(borrow);
+            assert!(cell.try_borrow_mut().is_ok());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "force-checks")]
+    #[should_panic(expected = "already mutably borrowed")]
+    fn force_checks()
+    {
+        let cell = UnsafeRefCell::new(1);
+        // SAFETY: The conflict causes a panic, even in release builds.
+        unsafe {
+            let _borrow_mut = cell.borrow_mut();
+            cell.borrow();
+        }
+    }
+
+    #[test]
+    #[cfg(not(any(debug_assertions, feature = "force-checks")))]
+    fn try_borrow_mut_unchecked()
+    {
+        let cell = UnsafeRefCell::new(1);
+        // SAFETY: The immutable borrow is not used
+        //         once the mutable borrow exists.
+        unsafe {
+            let borrow = cell.borrow();
+            assert_eq!(*borrow, 1);
+            // Borrows are not tracked, so the conflict goes unnoticed.
+            let mut borrow_mut = cell.try_borrow_mut().unwrap();
+            *borrow_mut = 2;
+        }
+        assert_eq!(cell.into_inner(), 2);
+    }
+}